@@ -0,0 +1,453 @@
+use std::{collections::HashMap, fmt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::message::topic_matches_filter;
+use crate::stats::{StatValue, StatsWindow};
+use crate::stats_config::StatMetric;
+
+/// z-scoreを評価する前に必要なベースラインの値の数
+const MIN_BASELINE_SAMPLES: u64 = 10;
+
+fn default_baseline() -> u64 {
+    100
+}
+
+/// アラートの条件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// 値が範囲外（メッセージごとに評価）
+    Range {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// 統計期間の統計量が範囲外（期間の終了時に評価）
+    Stat {
+        metric: StatMetric,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// 指定秒数メッセージがない
+    Silence { seconds: u64 },
+    /// 移動ベースライン（指数移動平均）に対するz-scoreが閾値を超える
+    Zscore {
+        threshold: f64,
+        /// ベースラインの長さ（値の数）
+        #[serde(default = "default_baseline")]
+        baseline: u64,
+    },
+}
+
+/// アラートのルール（`--stats-config` の `alerts`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    /// MQTTのトピックフィルタ（`+` と `#` を使用可能）
+    pub filter: String,
+    /// 対象のキーパス（`silence` 以外で必須）
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+}
+
+impl AlertRule {
+    /// 設定の整合性をチェック
+    pub fn validate(&self) -> Result<(), String> {
+        let needs_key = !matches!(self.condition, AlertCondition::Silence { .. });
+        if needs_key && self.key.is_none() {
+            return Err(format!("alert '{}' requires a key", self.name));
+        }
+        if let AlertCondition::Stat { metric: StatMetric::Top, .. } = self.condition {
+            return Err(format!("alert '{}' cannot use the top statistic", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// アラートの状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// 発生または解消したアラート
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub state: AlertState,
+    /// 評価した時刻（UNIX秒）
+    pub time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    pub message: String,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        };
+        write!(f, "[{}] {} on '{}': {}", state, self.rule, self.topic, self.message)
+    }
+}
+
+/// 指数移動平均による平均と分散
+#[derive(Debug, Clone, Default)]
+struct Baseline {
+    count: u64,
+    mean: f64,
+    variance: f64,
+}
+
+impl Baseline {
+    fn push(&mut self, value: f64, length: u64) {
+        let alpha = 2.0 / (length.max(1) as f64 + 1.0);
+        if self.count == 0 {
+            self.mean = value;
+        } else {
+            let delta = value - self.mean;
+            self.mean += alpha * delta;
+            self.variance = (1.0 - alpha) * (self.variance + alpha * delta * delta);
+        }
+        self.count += 1;
+    }
+
+    /// 値のz-score（ベースラインが不十分な場合はNone）
+    fn zscore(&self, value: f64) -> Option<f64> {
+        let stddev = self.variance.sqrt();
+        if self.count < MIN_BASELINE_SAMPLES || stddev <= f64::EPSILON {
+            return None;
+        }
+        Some((value - self.mean) / stddev)
+    }
+}
+
+/// ルールとトピックごとの評価状態
+#[derive(Debug, Default)]
+struct RuleState {
+    firing: bool,
+    baseline: Baseline,
+}
+
+/// アラートのルールを評価する
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    states: HashMap<(usize, String), RuleState>,
+    last_seen: HashMap<String, f64>, // トピック -> 最後のメッセージの時刻
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        AlertEngine {
+            rules,
+            states: HashMap::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// ルールが設定されていないか
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// トピックに一致する `stat` ルールの統計量（統計期間で計算が必要なもの）
+    pub fn stat_metrics_for(&self, topic: &str) -> Vec<StatMetric> {
+        self.rules
+            .iter()
+            .filter(|rule| topic_matches_filter(&rule.filter, topic))
+            .filter_map(|rule| match rule.condition {
+                AlertCondition::Stat { metric, .. } => Some(metric),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// メッセージごとのルールを評価（JSON以外のペイロードはNone）
+    pub fn on_message(&mut self, topic: &str, payload: Option<&Value>, time: f64) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        self.last_seen.insert(topic.to_string(), time);
+
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            if !topic_matches_filter(&rule.filter, topic) {
+                continue;
+            }
+            match rule.condition {
+                AlertCondition::Silence { .. } => {
+                    events.extend(self.transition(index, topic, false, None, time, "messages resumed".to_string()));
+                }
+                AlertCondition::Range { min, max } => {
+                    let value = match lookup_number(payload, rule.key.as_deref()) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let violated = outside(value, min, max);
+                    let message = format!(
+                        "{}={} {} {}",
+                        rule.key.as_deref().unwrap_or_default(),
+                        value,
+                        if violated { "outside" } else { "within" },
+                        range_text(min, max)
+                    );
+                    events.extend(self.transition(index, topic, violated, Some(value), time, message));
+                }
+                AlertCondition::Zscore { threshold, baseline } => {
+                    let value = match lookup_number(payload, rule.key.as_deref()) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let key = rule.key.clone().unwrap_or_default();
+                    let state = self.states.entry((index, topic.to_string())).or_default();
+                    let zscore = state.baseline.zscore(value);
+                    state.baseline.push(value, baseline);
+                    if let Some(zscore) = zscore {
+                        let violated = zscore.abs() > threshold;
+                        let message = format!(
+                            "{}={} has z-score {:.2} against baseline (threshold {})",
+                            key, value, zscore, threshold
+                        );
+                        events.extend(self.transition(index, topic, violated, Some(value), time, message));
+                    }
+                }
+                AlertCondition::Stat { .. } => {}
+            }
+        }
+        events
+    }
+
+    /// 統計期間の終了時に `stat` ルールを評価
+    pub fn on_window(&mut self, window: &StatsWindow) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        let time = window.end.timestamp_millis() as f64 / 1000.0;
+
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            if !topic_matches_filter(&rule.filter, &window.topic) {
+                continue;
+            }
+            if let AlertCondition::Stat { metric, min, max } = rule.condition {
+                let key = rule.key.as_deref().unwrap_or_default();
+                let value = window.values.iter().find_map(|(k, m, v)| match v {
+                    StatValue::Number(n) if k == key && *m == metric => Some(*n),
+                    _ => None,
+                });
+                let value = match value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let violated = outside(value, min, max);
+                let message = format!(
+                    "{} of {} is {} {} {}",
+                    metric,
+                    key,
+                    value,
+                    if violated { "outside" } else { "within" },
+                    range_text(min, max)
+                );
+                events.extend(self.transition(index, &window.topic, violated, Some(value), time, message));
+            }
+        }
+        events
+    }
+
+    /// 定期的に `silence` ルールを評価
+    pub fn on_tick(&mut self, now: f64) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        let last_seen: Vec<(String, f64)> = self.last_seen.iter().map(|(t, s)| (t.clone(), *s)).collect();
+
+        for index in 0..self.rules.len() {
+            let seconds = match self.rules[index].condition {
+                AlertCondition::Silence { seconds } => seconds as f64,
+                _ => continue,
+            };
+            for (topic, seen) in &last_seen {
+                if !topic_matches_filter(&self.rules[index].filter, topic) {
+                    continue;
+                }
+                let silent = now - seen;
+                if silent >= seconds {
+                    let message = format!("no message for {:.0} seconds", silent);
+                    events.extend(self.transition(index, topic, true, None, now, message));
+                }
+            }
+        }
+        events
+    }
+
+    /// 状態が変化した場合にアラートを作成
+    fn transition(
+        &mut self,
+        index: usize,
+        topic: &str,
+        violated: bool,
+        value: Option<f64>,
+        time: f64,
+        message: String,
+    ) -> Option<AlertEvent> {
+        let state = self.states.entry((index, topic.to_string())).or_default();
+        if state.firing == violated {
+            return None;
+        }
+        state.firing = violated;
+        let rule = &self.rules[index];
+        Some(AlertEvent {
+            rule: rule.name.clone(),
+            topic: topic.to_string(),
+            key: rule.key.clone(),
+            state: if violated { AlertState::Firing } else { AlertState::Resolved },
+            time,
+            value,
+            message,
+        })
+    }
+}
+
+/// ヘルパー関数：キーパス（`a.b[0].c`）の数値を取得
+pub fn lookup_number(payload: Option<&Value>, key_path: Option<&str>) -> Option<f64> {
+    let mut value = payload?;
+    for segment in key_path?.split('.') {
+        let (name, indexes) = match segment.find('[') {
+            Some(pos) => segment.split_at(pos),
+            None => (segment, ""),
+        };
+        if !name.is_empty() {
+            value = value.get(name)?;
+        }
+        for index in indexes.split('[').skip(1) {
+            let index: usize = index.strip_suffix(']')?.parse().ok()?;
+            value = value.get(index)?;
+        }
+    }
+    value.as_f64()
+}
+
+fn outside(value: f64, min: Option<f64>, max: Option<f64>) -> bool {
+    min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max)
+}
+
+fn range_text(min: Option<f64>, max: Option<f64>) -> String {
+    let bound = |b: Option<f64>| b.map_or("..".to_string(), |b| b.to_string());
+    format!("[{}, {}]", bound(min), bound(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use serde_json::json;
+    use crate::stats::PayloadSummary;
+
+    fn engine(rules: Value) -> AlertEngine {
+        let rules: Vec<AlertRule> = serde_json::from_value(rules).unwrap();
+        for rule in &rules {
+            rule.validate().unwrap();
+        }
+        AlertEngine::new(rules)
+    }
+
+    fn states(events: &[AlertEvent]) -> Vec<(&str, AlertState)> {
+        events.iter().map(|event| (event.rule.as_str(), event.state)).collect()
+    }
+
+    #[test]
+    fn range_alert_fires_and_clears() {
+        let mut engine = engine(json!([
+            {"name": "hot", "filter": "plant/+/temp", "key": "t", "type": "range", "max": 30.0}
+        ]));
+        let mut check = |t: f64| engine.on_message("plant/1/temp", Some(&json!({ "t": t })), 0.0);
+        assert!(check(25.0).is_empty());
+        let fired = check(31.0);
+        assert_eq!(states(&fired), vec![("hot", AlertState::Firing)]);
+        assert_eq!(fired[0].value, Some(31.0));
+        // 発生中は繰り返し報告しない
+        assert!(check(35.0).is_empty());
+        assert_eq!(states(&check(29.0)), vec![("hot", AlertState::Resolved)]);
+        assert!(check(20.0).is_empty());
+
+        // 一致しないトピックとキーのないペイロードは評価しない
+        assert!(engine.on_message("plant/1/humidity", Some(&json!({"t": 99.0})), 0.0).is_empty());
+        assert!(engine.on_message("plant/1/temp", None, 0.0).is_empty());
+    }
+
+    #[test]
+    fn silence_alert_fires_and_clears() {
+        let mut engine = engine(json!([
+            {"name": "quiet", "filter": "s/#", "type": "silence", "seconds": 10}
+        ]));
+        assert!(engine.on_tick(100.0).is_empty());
+        assert!(engine.on_message("s/a", None, 100.0).is_empty());
+        assert!(engine.on_tick(109.0).is_empty());
+        let fired = engine.on_tick(110.0);
+        assert_eq!(states(&fired), vec![("quiet", AlertState::Firing)]);
+        assert_eq!(fired[0].topic, "s/a");
+        assert!(engine.on_tick(120.0).is_empty());
+        assert_eq!(states(&engine.on_message("s/a", None, 121.0)), vec![("quiet", AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn zscore_alert_fires_and_clears() {
+        let mut engine = engine(json!([
+            {"name": "spike", "filter": "#", "key": "v", "type": "zscore", "threshold": 3.0, "baseline": 20}
+        ]));
+        let mut check = |v: f64| engine.on_message("s", Some(&json!({ "v": v })), 0.0);
+        // ベースラインが揃うまでは評価しない
+        for i in 0..MIN_BASELINE_SAMPLES {
+            assert!(check(if i % 2 == 0 { 9.0 } else { 11.0 }).is_empty());
+        }
+        for i in 0..20 {
+            assert!(check(if i % 2 == 0 { 9.0 } else { 11.0 }).is_empty());
+        }
+        let fired = check(50.0);
+        assert_eq!(states(&fired), vec![("spike", AlertState::Firing)]);
+        assert!(fired[0].message.contains("z-score"), "{}", fired[0].message);
+        assert_eq!(states(&check(10.0)), vec![("spike", AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn stat_alert_is_evaluated_per_window() {
+        let mut engine = engine(json!([
+            {"name": "slow", "filter": "s", "key": "latency", "type": "stat", "metric": "p95", "max": 100.0}
+        ]));
+        assert_eq!(engine.stat_metrics_for("s"), vec![StatMetric::P95]);
+        assert!(engine.stat_metrics_for("other").is_empty());
+
+        let window = |p95: f64| StatsWindow {
+            topic: "s".to_string(),
+            start: Local.timestamp_opt(1_753_405_200, 0).unwrap(),
+            end: Local.timestamp_opt(1_753_405_260, 0).unwrap(),
+            messages: 1,
+            payload: PayloadSummary::default(),
+            values: vec![("latency".to_string(), StatMetric::P95, StatValue::Number(p95))],
+        };
+        let fired = engine.on_window(&window(150.0));
+        assert_eq!(states(&fired), vec![("slow", AlertState::Firing)]);
+        assert_eq!(fired[0].time, 1_753_405_260.0);
+        assert_eq!(states(&engine.on_window(&window(80.0))), vec![("slow", AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn validates_rules_and_looks_up_key_paths() {
+        let rule: AlertRule = serde_json::from_value(json!({"name": "r", "filter": "#", "type": "range"})).unwrap();
+        assert!(rule.validate().is_err());
+        let rule: AlertRule =
+            serde_json::from_value(json!({"name": "r", "filter": "#", "key": "s", "type": "stat", "metric": "top"})).unwrap();
+        assert!(rule.validate().is_err());
+
+        let payload = json!({"a": {"b": [1, {"c": 2.5}]}, "s": "x"});
+        assert_eq!(lookup_number(Some(&payload), Some("a.b[1].c")), Some(2.5));
+        assert_eq!(lookup_number(Some(&payload), Some("a.b[0]")), Some(1.0));
+        assert_eq!(lookup_number(Some(&payload), Some("a.b[2]")), None);
+        assert_eq!(lookup_number(Some(&payload), Some("s")), None);
+    }
+}
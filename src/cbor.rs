@@ -0,0 +1,269 @@
+use std::convert::TryFrom;
+use serde_json::{json, Map, Number, Value};
+
+/// 入れ子の最大の深さ（不正なデータによるスタックオーバーフローを防ぐ）
+const MAX_DEPTH: usize = 128;
+
+/// CBOR（RFC 8949）のペイロードをJSONの値に変換（データ全体が1つの値である必要がある）
+/// バイト列はBase64の文字列、タグは中身の値、NaNと無限大はnullになる
+pub fn decode_cbor(data: &[u8]) -> Result<Value, String> {
+    let mut reader = CborReader { data, pos: 0 };
+    let value = reader.read_value(0)?;
+    if reader.pos != data.len() {
+        return Err(format!("{} trailing bytes after CBOR value", data.len() - reader.pos));
+    }
+    Ok(value)
+}
+
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("truncated CBOR value at byte {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn peek_break(&self) -> bool {
+        self.data.get(self.pos) == Some(&0xff)
+    }
+
+    /// 追加情報から引数を読み込み（不定長の場合はNone）
+    fn read_argument(&mut self, info: u8) -> Result<Option<u64>, String> {
+        let mut be = |len: usize| -> Result<u64, String> {
+            Ok(self.read_bytes(len)?.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+        };
+        match info {
+            0..=23 => Ok(Some(info as u64)),
+            24 => Ok(Some(be(1)?)),
+            25 => Ok(Some(be(2)?)),
+            26 => Ok(Some(be(4)?)),
+            27 => Ok(Some(be(8)?)),
+            31 => Ok(None),
+            _ => Err(format!("invalid CBOR additional information {}", info)),
+        }
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("CBOR value nested too deeply".to_string());
+        }
+        let initial = self.read_u8()?;
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+
+        if major == 7 {
+            return self.read_simple(info);
+        }
+        let argument = self.read_argument(info)?;
+        match (major, argument) {
+            (0, Some(n)) => Ok(json!(n)),
+            (1, Some(n)) => Ok(match i64::try_from(n) {
+                Ok(n) => json!(-1 - n),
+                Err(_) => json!(-1.0 - n as f64),
+            }),
+            (2, _) => Ok(json!(base64::encode(self.read_string(2, argument, depth)?))),
+            (3, _) => {
+                let bytes = self.read_string(3, argument, depth)?;
+                String::from_utf8(bytes).map(Value::String).map_err(|_| "invalid UTF-8 in CBOR text".to_string())
+            }
+            (4, _) => {
+                let mut items = Vec::new();
+                match argument {
+                    Some(len) => {
+                        for _ in 0..len {
+                            items.push(self.read_value(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while !self.peek_break() {
+                            items.push(self.read_value(depth + 1)?);
+                        }
+                        self.pos += 1;
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            (5, _) => {
+                let mut map = Map::new();
+                let mut remaining = argument;
+                loop {
+                    match remaining {
+                        Some(0) => break,
+                        Some(ref mut n) => *n -= 1,
+                        None if self.peek_break() => {
+                            self.pos += 1;
+                            break;
+                        }
+                        None => {}
+                    }
+                    let key = map_key(self.read_value(depth + 1)?);
+                    let value = self.read_value(depth + 1)?;
+                    map.insert(key, value);
+                }
+                Ok(Value::Object(map))
+            }
+            // タグ（日時、bignumなど）は中身の値として扱う
+            (6, Some(_)) => self.read_value(depth + 1),
+            _ => Err(format!("invalid CBOR initial byte 0x{:02x}", initial)),
+        }
+    }
+
+    /// バイト列または文字列（不定長の場合は同じ型のチャンクを連結）
+    fn read_string(&mut self, major: u8, argument: Option<u64>, depth: usize) -> Result<Vec<u8>, String> {
+        if let Some(len) = argument {
+            return Ok(self.read_bytes(len as usize)?.to_vec());
+        }
+        let mut bytes = Vec::new();
+        while !self.peek_break() {
+            let initial = self.read_u8()?;
+            if initial >> 5 != major {
+                return Err("invalid chunk in indefinite-length CBOR string".to_string());
+            }
+            match self.read_argument(initial & 0x1f)? {
+                Some(len) => bytes.extend_from_slice(self.read_bytes(len as usize)?),
+                None => return Err(format!("nested indefinite-length CBOR string at depth {}", depth)),
+            }
+        }
+        self.pos += 1;
+        Ok(bytes)
+    }
+
+    fn read_simple(&mut self, info: u8) -> Result<Value, String> {
+        let float = |f: f64| Number::from_f64(f).map_or(Value::Null, Value::Number);
+        match info {
+            20 => Ok(Value::Bool(false)),
+            21 => Ok(Value::Bool(true)),
+            22 | 23 => Ok(Value::Null),
+            24 => {
+                self.read_u8()?;
+                Ok(Value::Null)
+            }
+            25 => {
+                let bytes = self.read_bytes(2)?;
+                Ok(float(half_to_f64(u16::from_be_bytes([bytes[0], bytes[1]]))))
+            }
+            26 => {
+                let bytes = self.read_bytes(4)?;
+                Ok(float(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64))
+            }
+            27 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(self.read_bytes(8)?);
+                Ok(float(f64::from_be_bytes(buf)))
+            }
+            0..=19 => Ok(json!(info)),
+            _ => Err(format!("invalid CBOR simple value {}", info)),
+        }
+    }
+}
+
+/// ヘルパー関数：半精度浮動小数点数を変換
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// ヘルパー関数：マップのキーを文字列に変換（文字列以外はJSON表記）
+pub(crate) fn map_key(key: Value) -> String {
+    match key {
+        Value::String(key) => key,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Value {
+        json!({
+            "temperature": 21.5,
+            "count": 42,
+            "offset": -7,
+            "big": u64::MAX,
+            "ok": true,
+            "missing": null,
+            "name": "sensor",
+            "readings": [1, 2.25, {"nested": ["a", "b"]}],
+        })
+    }
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(value, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn round_trips_json_values() {
+        let value = sample();
+        assert_eq!(decode_cbor(&encode(&value)), Ok(value));
+    }
+
+    #[test]
+    fn decodes_cbor_specific_items() {
+        // 不定長の配列とバイト列、タグ付きの値、整数のキー、半精度のNaN
+        assert_eq!(decode_cbor(&[0x9f, 0x01, 0x02, 0xff]), Ok(json!([1, 2])));
+        assert_eq!(decode_cbor(&[0x5f, 0x42, 0x01, 0x02, 0x41, 0x03, 0xff]), Ok(json!("AQID")));
+        assert_eq!(decode_cbor(&[0xc1, 0x1a, 0x65, 0x53, 0xf1, 0x00]), Ok(json!(1_700_000_000u64)));
+        assert_eq!(decode_cbor(&[0xa1, 0x01, 0x61, 0x61]), Ok(json!({"1": "a"})));
+        assert_eq!(decode_cbor(&[0xf9, 0x7e, 0x00]), Ok(Value::Null));
+        assert_eq!(decode_cbor(&[0xf9, 0x3c, 0x00]), Ok(json!(1.0)));
+        assert_eq!(decode_cbor(&[0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Ok(json!(-1.0 - u64::MAX as f64)));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let data = encode(&sample());
+        for len in 0..data.len() {
+            assert!(decode_cbor(&data[..len]).is_err(), "prefix of {} bytes", len);
+        }
+        assert!(decode_cbor(&[0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let max = [0xff; 8];
+        for major in [0x5b, 0x7b, 0x9b, 0xbb] {
+            let mut data = vec![major];
+            data.extend_from_slice(&max);
+            assert!(decode_cbor(&data).is_err(), "major 0x{:02x}", major);
+        }
+        assert!(decode_cbor(&[0x5f, 0x5f, 0xff, 0xff]).is_err());
+        assert!(decode_cbor(&[0x1c]).is_err());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_values() {
+        for wrapper in [0x81, 0xc1, 0x9f] {
+            let mut data = vec![wrapper; 100_000];
+            data.push(0x01);
+            let err = decode_cbor(&data).unwrap_err();
+            assert!(err.contains("nested too deeply"), "{}", err);
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 use structopt::StructOpt;
+use crate::decode::DumpFormat;
+use crate::replay::is_valid_speed;
+use crate::stats_config::{ArrayMode, StatMetric, StatsFormat};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "mqtt-recorder", about = "mqtt recorder written in rust")]
@@ -20,6 +23,14 @@ pub struct Opt {
     #[structopt(short, long)]
     pub cafile: Option<PathBuf>,
 
+    /// Serve Prometheus metrics on http://<address>/metrics, for example 0.0.0.0:9898
+    #[structopt(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Maximum number of topic labels in the metrics, further topics are counted as "__other__"
+    #[structopt(long, default_value = "100")]
+    pub metrics_max_topics: usize,
+
     /// Mode to run software in
     #[structopt(subcommand)]
     pub mode: Mode,
@@ -38,6 +49,26 @@ pub enum Mode {
     // Replay values from an input file
     #[structopt(name = "replay")]
     Replay(ReplayOptions),
+
+    // Verifies hash chains, digests and signatures of recorded files
+    #[structopt(name = "verify")]
+    Verify(VerifyOptions),
+
+    // Generates an Ed25519 key pair for signing recorded files
+    #[structopt(name = "keygen")]
+    Keygen(KeygenOptions),
+
+    // Prints the decrypted contents of an encrypted recording or stats file
+    #[structopt(name = "decrypt")]
+    Decrypt(DecryptOptions),
+
+    // Recomputes statistics from recorded files without a broker
+    #[structopt(name = "stats")]
+    Stats(StatsRecomputeOptions),
+
+    // Prints recorded messages with decoded payloads (JSON, Sparkplug B, protobuf, CBOR, MessagePack)
+    #[structopt(name = "dump")]
+    Dump(DumpOptions),
 }
 
 #[derive(Debug, StructOpt)]
@@ -48,6 +79,93 @@ pub struct RecordOptions {
     /// The directory to write mqtt message files to
     #[structopt(short, long, parse(from_os_str))]
     pub directory: PathBuf,
+    /// Add a running hash chain to each recorded message
+    #[structopt(long)]
+    pub hash_chain: bool,
+    /// Ed25519 private key (hex) used to sign each closed file
+    #[structopt(long, parse(from_os_str))]
+    pub sign_key: Option<PathBuf>,
+    /// AES-256 key file (hex) to encrypt recording and stats files with (indexes and manifests stay plaintext)
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+    /// Enable statistical analysis of payloads (rate, size, jitter, duplicates, and JSON key paths)
+    #[structopt(long)]
+    pub enable_stats: bool,
+    #[structopt(flatten)]
+    pub stats: StatsOptions,
+    #[structopt(flatten)]
+    pub heartbeat: HeartbeatOptions,
+}
+
+// 記録モードと統計の再計算で共通の統計オプション（flatten するため doc コメントにしない）
+#[derive(Debug, StructOpt)]
+pub struct StatsOptions {
+    /// Interval in seconds for statistical analysis, aligned to wall-clock boundaries (default: 60 seconds)
+    #[structopt(long, default_value = "60")]
+    pub stats_interval: u64,
+    /// Comma separated statistics to compute for every key path (count, nulls, missing, min, max, mean, stddev, variance, p50, p95, p99, unique, top)
+    #[structopt(long, use_delimiter = true)]
+    pub stats_metrics: Option<Vec<StatMetric>>,
+    /// JSON file selecting the statistics per topic filter
+    #[structopt(long, parse(from_os_str))]
+    pub stats_config: Option<PathBuf>,
+    /// How to track JSON arrays: index (values[0], values[1], ...), aggregate (all elements as values[*]) or first:<n> (only the first n elements)
+    #[structopt(long)]
+    pub stats_arrays: Option<ArrayMode>,
+    /// Comma separated key path globs to track, for example sensor.* or readings[*].** (default: all key paths)
+    #[structopt(long, use_delimiter = true)]
+    pub stats_include: Option<Vec<String>>,
+    /// Comma separated key path globs not to track, for example debug or raw.**
+    #[structopt(long, use_delimiter = true)]
+    pub stats_exclude: Option<Vec<String>>,
+    /// Statistics file format: text, jsonl (one JSON object per window) or csv (one row per statistic)
+    #[structopt(long, default_value = "text")]
+    pub stats_format: StatsFormat,
+    /// Also write statistics windows without messages as zero-count rows
+    #[structopt(long)]
+    pub stats_empty_windows: bool,
+    /// Infer the JSON schema of each topic and log new keys, removed keys and type changes
+    #[structopt(long)]
+    pub schema_drift: bool,
+    /// MQTT topic to publish schema drift events to (requires --schema-drift)
+    #[structopt(long)]
+    pub schema_alert_topic: Option<String>,
+    /// MQTT topic to publish alerts of the rules in --stats-config to
+    #[structopt(long)]
+    pub alert_topic: Option<String>,
+    /// Publish each statistics window as JSON to <prefix>/<topic>, for example $recorder/stats
+    #[structopt(long)]
+    pub stats_publish_prefix: Option<String>,
+    #[structopt(flatten)]
+    pub decode: DecodeOptions,
+}
+
+// ペイロードの解析オプション（統計と dump で共通）
+#[derive(Debug, StructOpt)]
+pub struct DecodeOptions {
+    /// JSON file mapping topic filters to protobuf message types of a compiled FileDescriptorSet
+    #[structopt(long, parse(from_os_str))]
+    pub protobuf_config: Option<PathBuf>,
+    /// Topic filter whose payloads are CBOR, can be used multiple times
+    #[structopt(long, number_of_values = 1)]
+    pub cbor_topic: Vec<String>,
+    /// Topic filter whose payloads are MessagePack, can be used multiple times
+    #[structopt(long, number_of_values = 1)]
+    pub msgpack_topic: Vec<String>,
+    /// Do not try to decode other binary payloads as CBOR or MessagePack
+    #[structopt(long)]
+    pub no_payload_sniffing: bool,
+}
+
+// 記録モード共通のハートビートオプション
+#[derive(Debug, StructOpt)]
+pub struct HeartbeatOptions {
+    /// MQTT topic to publish the recorder status (uptime, message rate, open files, bytes written) to
+    #[structopt(long)]
+    pub heartbeat_topic: Option<String>,
+    /// Interval in seconds between heartbeats (default: 10 seconds)
+    #[structopt(long, default_value = "10")]
+    pub heartbeat_interval: u64,
 }
 
 #[derive(Debug, StructOpt)]
@@ -61,17 +179,30 @@ pub struct IntelligentRecordOptions {
     /// Seconds to wait for messages before closing file (default: 30 seconds)
     #[structopt(long, default_value = "30")]
     pub sec: u64,
-    /// Enable statistical analysis of JSON payloads
+    /// Enable statistical analysis of payloads (rate, size, jitter, duplicates, and JSON key paths)
     #[structopt(long)]
     pub enable_stats: bool,
-    /// Interval in seconds for statistical analysis (default: 60 seconds)
-    #[structopt(long, default_value = "60")]
-    pub stats_interval: u64,
+    #[structopt(flatten)]
+    pub stats: StatsOptions,
+    #[structopt(flatten)]
+    pub heartbeat: HeartbeatOptions,
+    /// Continue appending to the latest file of each topic if it was written within the --sec timeout
+    #[structopt(long)]
+    pub resume: bool,
+    /// Add a running hash chain to each recorded message
+    #[structopt(long)]
+    pub hash_chain: bool,
+    /// Ed25519 private key (hex) used to sign each closed file
+    #[structopt(long, parse(from_os_str))]
+    pub sign_key: Option<PathBuf>,
+    /// AES-256 key file (hex) to encrypt recording and stats files with (indexes and manifests stay plaintext)
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
 pub struct ReplayOptions {
-    #[structopt(short, long, default_value = "1.0")]
+    #[structopt(short, long, default_value = "1.0", parse(try_from_str = parse_speed))]
     /// Speed of the playback, 2.0 makes it twice as fast
     pub speed: f64,
 
@@ -79,11 +210,15 @@ pub struct ReplayOptions {
     #[structopt(short, long, parse(from_os_str))]
     pub directory: PathBuf,
 
-    /// Start date and time (YYYY-MM-DD HH:MM)
+    /// Topic filter to replay, can be used multiple times (default: all topics)
+    #[structopt(short, long, default_value = "#")]
+    pub topic: Vec<String>,
+
+    /// Start date and time (YYYY-MM-DD HH:MM[:SS])
     #[structopt(long)]
     pub start_time: Option<String>,
 
-    /// End date and time (YYYY-MM-DD HH:MM)
+    /// End date and time (YYYY-MM-DD HH:MM[:SS])
     #[structopt(long)]
     pub end_time: Option<String>,
 
@@ -95,4 +230,125 @@ pub struct ReplayOptions {
         default_value = "false"
     )]
     pub loop_replay: bool,
+
+    /// AES-256 key file (hex) to decrypt encrypted recordings with
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+
+    /// Control the replay with commands on stdin: pause, resume, step, seek, speed, status (type "help")
+    #[structopt(short, long)]
+    pub interactive: bool,
+
+    /// Log the replay progress (position, percent done, rate, ETA) every N seconds, 0 disables
+    #[structopt(long, default_value = "10")]
+    pub progress_interval: u64,
+
+    /// Write the replay status and progress as JSON to this file every second
+    #[structopt(long, parse(from_os_str))]
+    pub status_file: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyOptions {
+    /// The directory containing the recorded files to verify
+    #[structopt(short, long, parse(from_os_str))]
+    pub directory: PathBuf,
+
+    /// Ed25519 public key (hex) to check file signatures with
+    #[structopt(long, parse(from_os_str))]
+    pub public_key: Option<PathBuf>,
+
+    /// AES-256 key file (hex) to decrypt encrypted recordings with
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+
+    /// Fail files that have no hash chain (recorded without --hash-chain or with the chain values removed)
+    #[structopt(long)]
+    pub require_chain: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct KeygenOptions {
+    /// Path to write the private key to, the public key is written to <path>.pub
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// Generate an AES-256 key for encrypting recordings instead of a signing key pair
+    #[structopt(long)]
+    pub encryption: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DecryptOptions {
+    /// Encrypted recording or stats file to print
+    #[structopt(short, long, parse(from_os_str))]
+    pub input: PathBuf,
+
+    /// AES-256 key file (hex) the file was encrypted with
+    #[structopt(short, long, parse(from_os_str))]
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct StatsRecomputeOptions {
+    /// The directory to read recorded files from
+    #[structopt(short, long, parse(from_os_str))]
+    pub directory: PathBuf,
+
+    /// The directory to write stats files to, existing stats files for the same topics are replaced
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// Start date and time (YYYY-MM-DD HH:MM[:SS])
+    #[structopt(long)]
+    pub start_time: Option<String>,
+
+    /// End date and time (YYYY-MM-DD HH:MM[:SS])
+    #[structopt(long)]
+    pub end_time: Option<String>,
+
+    /// AES-256 key file (hex) to decrypt recordings and encrypt stats files with
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub stats: StatsOptions,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DumpOptions {
+    /// The directory to read recorded files from
+    #[structopt(short, long, parse(from_os_str))]
+    pub directory: PathBuf,
+
+    /// Topic filter to print, can be used multiple times (default: all topics)
+    #[structopt(short, long, default_value = "#")]
+    pub topic: Vec<String>,
+
+    /// Output format: text (one line per message), jsonl (one JSON object per message) or csv (one row per key path)
+    #[structopt(long, default_value = "text")]
+    pub format: DumpFormat,
+
+    #[structopt(flatten)]
+    pub decode: DecodeOptions,
+
+    /// Start date and time (YYYY-MM-DD HH:MM[:SS])
+    #[structopt(long)]
+    pub start_time: Option<String>,
+
+    /// End date and time (YYYY-MM-DD HH:MM[:SS])
+    #[structopt(long)]
+    pub end_time: Option<String>,
+
+    /// AES-256 key file (hex) to decrypt encrypted recordings with
+    #[structopt(long, parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+}
+
+/// ヘルパー関数：再生速度を解析（正の有限の値のみ）
+fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if is_valid_speed(speed) => Ok(speed),
+        _ => Err(format!("invalid speed '{}', expected a positive number", value)),
+    }
 }
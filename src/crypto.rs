@@ -0,0 +1,236 @@
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use crate::integrity::read_key_bytes;
+
+/// 暗号化された行の先頭に付与するマーカー
+pub const ENCRYPTED_LINE_PREFIX: &str = "enc1:";
+
+/// AES-GCMのノンス長（バイト）
+const NONCE_LEN: usize = 12;
+
+/// 記録ファイルと統計ファイルを行単位で暗号化する（AES-256-GCM）
+/// ファイル名と行番号（1から）を認証データとして結び付けるため、行を別のファイルや別の位置に移すと復号に失敗する
+#[derive(Clone)]
+pub struct RecordCipher {
+    cipher: Aes256Gcm,
+}
+
+impl RecordCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        RecordCipher {
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /// 16進数文字列のキーファイルから読み込み
+    pub fn from_key_file(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(&read_key_bytes(path)?))
+    }
+
+    /// ファイルの `line_number` 行目を暗号化（ランダムなノンス + 暗号文 + 認証タグをBase64で表現）
+    pub fn encrypt_line(&self, plaintext: &str, path: &Path, line_number: u64) -> io::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(io::Error::from)?;
+        let aad = associated_data(path, line_number);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: &aad,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_LINE_PREFIX, base64::encode(payload)))
+    }
+
+    /// ファイルの `line_number` 行目の暗号化された行を復号（認証に失敗した場合はエラー）
+    pub fn decrypt_line(&self, line: &str, path: &Path, line_number: u64) -> io::Result<String> {
+        let encoded = line
+            .strip_prefix(ENCRYPTED_LINE_PREFIX)
+            .ok_or_else(|| invalid_data("line is not encrypted"))?;
+        let payload = base64::decode(encoded).map_err(invalid_data)?;
+        if payload.len() < NONCE_LEN {
+            return Err(invalid_data("encrypted line is too short"));
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let aad = associated_data(path, line_number);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| invalid_data("decryption failed, wrong key, altered or moved line"))?;
+        String::from_utf8(plaintext).map_err(invalid_data)
+    }
+}
+
+/// ヘルパー関数：認証データ（ファイル名の長さ、ファイル名、行番号）
+/// ディレクトリは含めないため、ファイルを別のディレクトリに移動しても復号できる
+fn associated_data(path: &Path, line_number: u64) -> Vec<u8> {
+    let file_name = path.file_name().unwrap_or_default().as_encoded_bytes();
+    let mut aad = Vec::with_capacity(16 + file_name.len());
+    aad.extend_from_slice(&(file_name.len() as u64).to_be_bytes());
+    aad.extend_from_slice(file_name);
+    aad.extend_from_slice(&line_number.to_be_bytes());
+    aad
+}
+
+/// ヘルパー関数：行が暗号化されているかチェック
+pub fn is_encrypted_line(line: &str) -> bool {
+    line.starts_with(ENCRYPTED_LINE_PREFIX)
+}
+
+/// ヘルパー関数：ファイルの `line_number` 行目に書き込む行を必要に応じて暗号化
+pub fn seal_line<'a>(line: &'a str, cipher: Option<&RecordCipher>, path: &Path, line_number: u64) -> io::Result<Cow<'a, str>> {
+    match cipher {
+        Some(cipher) => Ok(Cow::Owned(cipher.encrypt_line(line, path, line_number)?)),
+        None => Ok(Cow::Borrowed(line)),
+    }
+}
+
+/// ヘルパー関数：ファイルの `line_number` 行目を必要に応じて復号（平文の行はそのまま返す）
+pub fn open_line<'a>(line: &'a str, cipher: Option<&RecordCipher>, path: &Path, line_number: u64) -> io::Result<Cow<'a, str>> {
+    let line = line.trim_end();
+    if !is_encrypted_line(line) {
+        return Ok(Cow::Borrowed(line));
+    }
+    match cipher {
+        Some(cipher) => Ok(Cow::Owned(cipher.decrypt_line(line, path, line_number)?)),
+        None => Err(invalid_data("file is encrypted, a key file is required")),
+    }
+}
+
+/// 行単位で追記するファイル（暗号化する場合は行番号を数えて認証データに使う）
+pub struct SealedFile {
+    file: fs::File,
+    path: PathBuf,
+    cipher: Option<RecordCipher>,
+    lines: u64,
+}
+
+impl SealedFile {
+    /// 追記用に開く（暗号化する場合は既存の行数を数える）
+    pub fn append(path: &Path, cipher: Option<RecordCipher>) -> io::Result<Self> {
+        let lines = if cipher.is_some() { count_lines(path)? } else { 0 };
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(SealedFile {
+            file,
+            path: path.to_path_buf(),
+            cipher,
+            lines,
+        })
+    }
+
+    /// 既存の内容がないかチェック
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.file.metadata()?.len() == 0)
+    }
+
+    /// 1行を必要に応じて暗号化して書き込み
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let line = seal_line(line, self.cipher.as_ref(), &self.path, self.lines + 1)?;
+        writeln!(self.file, "{}", line)?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// ヘルパー関数：追記するファイルの既存の行数（存在しない場合は0）
+fn count_lines(path: &Path) -> io::Result<u64> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+    let mut lines = 0;
+    let mut buffer = Vec::new();
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        lines += 1;
+        buffer.clear();
+    }
+    Ok(lines)
+}
+
+/// ヘルパー関数：新しい暗号化キーを生成して保存
+pub fn generate_encryption_key(path: &Path) -> io::Result<()> {
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(io::Error::from)?;
+    fs::write(path, hex::encode(key) + "\n")
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_lines_to_file_name_and_line_number() {
+        let cipher = RecordCipher::new(&[3; 32]);
+        let path = Path::new("/data/s/t/mqtt-recorder-s-t-20250725-010000.json");
+        let line = cipher.encrypt_line("{\"x\":1}", path, 2).unwrap();
+        assert!(is_encrypted_line(&line));
+        assert_eq!(cipher.decrypt_line(&line, path, 2).unwrap(), "{\"x\":1}");
+        // ディレクトリを移動しても復号できる
+        let moved = Path::new("/archive/mqtt-recorder-s-t-20250725-010000.json");
+        assert_eq!(open_line(&line, Some(&cipher), moved, 2).unwrap(), "{\"x\":1}");
+
+        // 別の行、別のファイル、別の鍵では復号できない
+        assert!(cipher.decrypt_line(&line, path, 3).is_err());
+        assert!(cipher.decrypt_line(&line, Path::new("/data/s/t/other.json"), 2).is_err());
+        assert!(RecordCipher::new(&[4; 32]).decrypt_line(&line, path, 2).is_err());
+        assert!(open_line(&line, None, path, 2).is_err());
+        assert_eq!(open_line("{\"x\":1}", Some(&cipher), path, 9).unwrap(), "{\"x\":1}");
+    }
+
+    #[test]
+    fn sealed_file_continues_line_numbers_when_reopened() {
+        let dir = std::env::temp_dir().join(format!("mqtt-recorder-crypto-sealed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stats.jsonl");
+        let cipher = RecordCipher::new(&[5; 32]);
+
+        let mut file = SealedFile::append(&path, Some(cipher.clone())).unwrap();
+        assert!(file.is_empty().unwrap());
+        file.write_line("a").unwrap();
+        file.write_line("b").unwrap();
+        drop(file);
+        let mut file = SealedFile::append(&path, Some(cipher.clone())).unwrap();
+        assert!(!file.is_empty().unwrap());
+        file.write_line("c").unwrap();
+        drop(file);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<String> = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| open_line(line, Some(&cipher), &path, i as u64 + 1).unwrap().into_owned())
+            .collect();
+        assert_eq!(lines, vec!["a", "b", "c"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
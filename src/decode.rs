@@ -0,0 +1,287 @@
+use std::str::FromStr;
+use chrono::SecondsFormat;
+use log::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::cbor::decode_cbor;
+use crate::message::{topic_matches_filter, MqttMessage};
+use crate::msgpack::decode_msgpack;
+use crate::protobuf::ProtobufDecoder;
+use crate::sparkplug::SparkplugDecoder;
+use crate::stats::{csv_field, timestamp_to_local};
+
+/// ペイロードの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    Json,
+    SparkplugB,
+    /// `--protobuf-config` で設定されたメッセージ型
+    Protobuf,
+    Cbor,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// JSON以外のUTF-8の文字列
+    Text,
+    Binary,
+}
+
+/// CBORとMessagePackのデコード関数
+type BinaryDecodeFn = fn(&[u8]) -> Result<Value, String>;
+
+/// 解析したペイロード（JSONとして扱える形式の場合は値を持つ）
+#[derive(Debug, Clone)]
+pub struct DecodedPayload {
+    pub format: PayloadFormat,
+    pub value: Option<Value>,
+}
+
+/// トピックとペイロードから形式を判定してJSONの値に変換する
+/// Sparkplug Bのエイリアスのように複数のメッセージにまたがる状態を持つため、記録全体で1つを使用する
+#[derive(Debug)]
+pub struct PayloadDecoder {
+    sparkplug: SparkplugDecoder,
+    protobuf: Option<ProtobufDecoder>,
+    cbor_topics: Vec<String>, // CBORとして解析するトピックフィルタ
+    msgpack_topics: Vec<String>, // MessagePackとして解析するトピックフィルタ
+    sniff_binary: bool, // UTF-8でないペイロードをCBORとMessagePackとして試すか
+}
+
+impl PayloadDecoder {
+    pub fn new() -> Self {
+        PayloadDecoder {
+            sparkplug: SparkplugDecoder::new(),
+            protobuf: None,
+            cbor_topics: Vec::new(),
+            msgpack_topics: Vec::new(),
+            sniff_binary: true,
+        }
+    }
+
+    /// CBORとして解析するトピックフィルタを設定
+    pub fn set_cbor_topics(&mut self, filters: Vec<String>) {
+        self.cbor_topics = filters;
+    }
+
+    /// MessagePackとして解析するトピックフィルタを設定
+    pub fn set_msgpack_topics(&mut self, filters: Vec<String>) {
+        self.msgpack_topics = filters;
+    }
+
+    /// 形式が設定されていないUTF-8でないペイロードの判定を設定
+    /// データ全体がCBORまたはMessagePackのマップか配列として解析できた場合のみ採用する
+    pub fn set_sniff_binary(&mut self, enabled: bool) {
+        self.sniff_binary = enabled;
+    }
+
+    /// トピックごとのProtocol Buffersのメッセージ型を設定（Sparkplug B より優先）
+    pub fn set_protobuf(&mut self, protobuf: Option<ProtobufDecoder>) {
+        self.protobuf = protobuf;
+    }
+
+    pub fn decode(&mut self, topic: &str, payload: &[u8]) -> DecodedPayload {
+        match self.protobuf.as_ref().and_then(|protobuf| protobuf.decode(topic, payload)) {
+            Some(Ok(value)) => {
+                return DecodedPayload {
+                    format: PayloadFormat::Protobuf,
+                    value: Some(value),
+                }
+            }
+            Some(Err(e)) => debug!("Failed to decode protobuf payload on '{}': {}", topic, e),
+            None => {}
+        }
+
+        // 設定されたトピックは形式を判定せずに解析
+        let configured: [(&[String], PayloadFormat, BinaryDecodeFn); 2] = [
+            (&self.cbor_topics, PayloadFormat::Cbor, decode_cbor),
+            (&self.msgpack_topics, PayloadFormat::MessagePack, decode_msgpack),
+        ];
+        for (filters, format, decode) in configured {
+            if !filters.iter().any(|filter| topic_matches_filter(filter, topic)) {
+                continue;
+            }
+            match decode(payload) {
+                Ok(value) => {
+                    return DecodedPayload {
+                        format,
+                        value: Some(value),
+                    }
+                }
+                Err(e) => {
+                    debug!("Failed to decode {:?} payload on '{}': {}", format, topic, e);
+                    return DecodedPayload {
+                        format: PayloadFormat::Binary,
+                        value: None,
+                    };
+                }
+            }
+        }
+
+        match self.sparkplug.decode(topic, payload) {
+            Some(Ok(value)) => {
+                return DecodedPayload {
+                    format: PayloadFormat::SparkplugB,
+                    value: Some(value),
+                }
+            }
+            Some(Err(e)) => debug!("Failed to decode Sparkplug B payload on '{}': {}", topic, e),
+            None => {}
+        }
+
+        match std::str::from_utf8(payload) {
+            Ok(text) => match serde_json::from_str::<Value>(text) {
+                Ok(value) => DecodedPayload {
+                    format: PayloadFormat::Json,
+                    value: Some(value),
+                },
+                Err(_) => DecodedPayload {
+                    format: PayloadFormat::Text,
+                    value: None,
+                },
+            },
+            Err(_) => self.sniff(payload),
+        }
+    }
+
+    /// UTF-8でないペイロードをCBOR、MessagePackの順に試す
+    fn sniff(&self, payload: &[u8]) -> DecodedPayload {
+        if self.sniff_binary {
+            let candidates: [(PayloadFormat, BinaryDecodeFn); 2] = [
+                (PayloadFormat::Cbor, decode_cbor),
+                (PayloadFormat::MessagePack, decode_msgpack),
+            ];
+            for (format, decode) in candidates {
+                if let Ok(value @ (Value::Object(_) | Value::Array(_))) = decode(payload) {
+                    return DecodedPayload {
+                        format,
+                        value: Some(value),
+                    };
+                }
+            }
+        }
+        DecodedPayload {
+            format: PayloadFormat::Binary,
+            value: None,
+        }
+    }
+}
+
+impl Default for PayloadDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ヘルパー関数：JSONの値をキーパス（`a.b[0].c`）と末端の値に展開
+pub fn flatten_value(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_value(&path, child, out);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter().enumerate() {
+                flatten_value(&format!("{}[{}]", prefix, index), child, out);
+            }
+        }
+        _ => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// `dump` の出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    /// 1メッセージ1行の人が読める形式
+    #[default]
+    Text,
+    /// 1メッセージ1行のJSON
+    Jsonl,
+    /// 1キーパス1行のCSV
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "txt" => Ok(DumpFormat::Text),
+            "jsonl" | "json" => Ok(DumpFormat::Jsonl),
+            "csv" => Ok(DumpFormat::Csv),
+            _ => Err(format!("unknown dump format '{}', expected text, jsonl or csv", s)),
+        }
+    }
+}
+
+/// `dump --format csv` のヘッダー
+pub const DUMP_CSV_HEADER: &str = "time,time_iso,topic,format,key,value";
+
+/// ヘルパー関数：記録されたメッセージを `dump` の出力行に変換
+pub fn dump_lines(msg: &MqttMessage, payload: &[u8], decoded: &DecodedPayload, format: DumpFormat) -> Vec<String> {
+    let time_iso = timestamp_to_local(msg.time).to_rfc3339_opts(SecondsFormat::Millis, false);
+    let format_name = serde_json::to_value(decoded.format)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    // 値を持たないペイロードは文字列またはBase64で出力
+    let raw = match decoded.format {
+        PayloadFormat::Binary => base64::encode(payload),
+        _ => String::from_utf8_lossy(payload).into_owned(),
+    };
+
+    match format {
+        DumpFormat::Text => {
+            let body = match (&decoded.value, decoded.format) {
+                (Some(value), _) => value.to_string(),
+                (None, PayloadFormat::Binary) => format!("<{} bytes> {}", payload.len(), raw),
+                (None, _) => Value::String(raw).to_string(),
+            };
+            vec![format!("{} {} [{}] {}", time_iso, msg.topic, format_name, body)]
+        }
+        DumpFormat::Jsonl => {
+            let mut line = json!({
+                "time": msg.time,
+                "time_iso": time_iso,
+                "topic": msg.topic,
+                "format": decoded.format,
+            });
+            match (&decoded.value, decoded.format) {
+                (Some(value), _) => line["payload"] = value.clone(),
+                (None, PayloadFormat::Binary) => line["payload_b64"] = json!(raw),
+                (None, _) => line["payload"] = json!(raw),
+            }
+            vec![line.to_string()]
+        }
+        DumpFormat::Csv => {
+            let mut rows = Vec::new();
+            match &decoded.value {
+                Some(value) => flatten_value("", value, &mut rows),
+                None => rows.push((String::new(), Value::String(raw))),
+            }
+            rows.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) => s,
+                        Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    format!(
+                        "{},{},{},{},{},{}",
+                        msg.time,
+                        time_iso,
+                        csv_field(&msg.topic),
+                        format_name,
+                        csv_field(&key),
+                        csv_field(&value)
+                    )
+                })
+                .collect()
+        }
+    }
+}
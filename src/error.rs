@@ -0,0 +1,110 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use rumqttc::ConnectionError;
+use crate::reader::RecordingError;
+
+/// 終了コード：その他のエラー
+pub const EXIT_FAILURE: i32 = 1;
+/// 終了コード：検証の失敗、または復号・解析できない記録
+pub const EXIT_INVALID_DATA: i32 = 2;
+/// 終了コード：引数、設定ファイル、鍵ファイルの誤り
+pub const EXIT_CONFIG: i32 = 3;
+/// 終了コード：ファイルまたはディレクトリの読み書きの失敗
+pub const EXIT_IO: i32 = 4;
+/// 終了コード：ブローカーとの接続の失敗
+pub const EXIT_CONNECTION: i32 = 5;
+
+/// ライブラリ全体のエラー
+#[derive(Debug)]
+pub enum RecorderError {
+    /// 時刻の形式が正しくない（YYYY-MM-DD HH:MM[:SS]）
+    InvalidTime(String),
+    /// 設定ファイルまたは鍵ファイルを読み込めない
+    Config { path: PathBuf, source: io::Error },
+    /// ファイルまたはディレクトリの読み書きに失敗
+    Io { path: PathBuf, source: io::Error },
+    /// メッセージをJSONに変換できない
+    Serialize(serde_json::Error),
+    /// 記録ファイルの読み込みエラー
+    Recording(RecordingError),
+    /// ブローカーとの接続エラー
+    Connection(ConnectionError),
+}
+
+impl RecorderError {
+    /// ファイルの読み書きのエラーを作成
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        RecorderError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// 設定ファイルまたは鍵ファイルのエラーを作成
+    pub fn config(path: &Path, source: io::Error) -> Self {
+        RecorderError::Config {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// エラーの種類に応じたプロセスの終了コード
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RecorderError::InvalidTime(_) | RecorderError::Config { .. } => EXIT_CONFIG,
+            RecorderError::Io { .. } => EXIT_IO,
+            RecorderError::Serialize(_) => EXIT_FAILURE,
+            RecorderError::Recording(RecordingError::InvalidTime(_)) => EXIT_CONFIG,
+            RecorderError::Recording(RecordingError::Io { .. } | RecordingError::List { .. }) => EXIT_IO,
+            RecorderError::Recording(_) => EXIT_INVALID_DATA,
+            RecorderError::Connection(_) => EXIT_CONNECTION,
+        }
+    }
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecorderError::InvalidTime(time) => {
+                write!(f, "invalid time '{}', expected YYYY-MM-DD HH:MM[:SS]", time)
+            }
+            RecorderError::Config { path, source } => write!(f, "failed to load {:?}: {}", path, source),
+            RecorderError::Io { path, source } => write!(f, "failed to access {:?}: {}", path, source),
+            RecorderError::Serialize(e) => write!(f, "failed to serialize message: {}", e),
+            RecorderError::Recording(e) => e.fmt(f),
+            RecorderError::Connection(e) => write!(f, "connection error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RecorderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecorderError::Config { source, .. } | RecorderError::Io { source, .. } => Some(source),
+            RecorderError::Serialize(e) => Some(e),
+            RecorderError::Recording(e) => e.source(),
+            RecorderError::Connection(e) => Some(e),
+            RecorderError::InvalidTime(_) => None,
+        }
+    }
+}
+
+impl From<RecordingError> for RecorderError {
+    fn from(e: RecordingError) -> Self {
+        RecorderError::Recording(e)
+    }
+}
+
+impl From<serde_json::Error> for RecorderError {
+    fn from(e: serde_json::Error) -> Self {
+        RecorderError::Serialize(e)
+    }
+}
+
+impl From<ConnectionError> for RecorderError {
+    fn from(e: ConnectionError) -> Self {
+        RecorderError::Connection(e)
+    }
+}
@@ -1,15 +1,26 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
-    time::Instant,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
 };
-use chrono::Local;
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
 use log::*;
-use crate::stats::StatsManager;
+use ed25519_dalek::SigningKey;
+use crate::crypto::{open_line, seal_line, RecordCipher};
+use crate::decode::PayloadDecoder;
+use crate::error::RecorderError;
+use crate::heartbeat::{Heartbeat, HeartbeatReport};
+use crate::index::{remove_file_index, write_file_index};
+use crate::integrity::{sign_file, signature_path_for, HashChain};
+use crate::message::MqttMessage;
+use crate::metrics::Metrics;
+use crate::stats::{OutboundMessage, StatsManager};
+use crate::stats_config::{StatsConfig, StatsFormat};
 
 /// ヘルパー関数：現在時刻に基づいてファイルパスを生成
-pub fn get_current_file_path(base_dir: &PathBuf) -> PathBuf {
+pub fn get_current_file_path(base_dir: &Path) -> PathBuf {
     let now = Local::now();
     let date_str = now.format("%Y-%m-%d").to_string();
     let time_str = now.format("%Y-%m-%d-%H%M").to_string();
@@ -24,7 +35,7 @@ pub fn topic_to_path(topic: &str) -> String {
 }
 
 /// ヘルパー関数：ベースタイムスタンプを使用してファイルパスを生成（ファイル番号付き）
-pub fn get_intelligent_file_path(base_dir: &PathBuf, topic: &str, base_timestamp: &str, file_number: u32) -> PathBuf {
+pub fn get_intelligent_file_path(base_dir: &Path, topic: &str, base_timestamp: &str, file_number: u32) -> PathBuf {
     let now = Local::now();
     let date_str = now.format("%Y-%m-%d").to_string();
     
@@ -41,14 +52,284 @@ pub fn get_intelligent_file_path(base_dir: &PathBuf, topic: &str, base_timestamp
     }
 }
 
+/// ヘルパー関数：インテリジェント記録のファイル名からベースタイムスタンプとファイル番号を取得
+pub fn parse_intelligent_file_name(file_name: &str, topic: &str) -> Option<(String, u32)> {
+    let prefix = format!("mqtt-recorder-{}-", topic_to_path(topic));
+    let rest = file_name.strip_prefix(&prefix)?.strip_suffix(".json")?;
+    
+    // yyyymmdd-hhmmss または yyyymmdd-hhmmss-{number}
+    let parts: Vec<&str> = rest.split('-').collect();
+    let (timestamp, file_number) = match parts.as_slice() {
+        [date, time] => (format!("{}-{}", date, time), 0),
+        [date, time, number] => (format!("{}-{}", date, time), number.parse().ok()?),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&timestamp, "%Y%m%d-%H%M%S").ok()?;
+    Some((timestamp, file_number))
+}
+
+/// ヘルパー関数：トピックの最新の記録ファイルを検索（ベースタイムスタンプ、ファイル番号の順で比較）
+pub fn find_latest_topic_file(base_dir: &Path, topic: &str) -> Option<(PathBuf, String, u32)> {
+    let topic_dir = base_dir.join(topic);
+    let mut latest: Option<(PathBuf, String, u32)> = None;
+    
+    for date_entry in fs::read_dir(&topic_dir).ok()?.flatten() {
+        let date_dir = date_entry.path();
+        // 日付ディレクトリのみ対象（サブトピックのディレクトリは除外）
+        let is_date_dir = date_dir
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|name| NaiveDate::parse_from_str(name, "%Y-%m-%d").is_ok());
+        if !date_dir.is_dir() || !is_date_dir {
+            continue;
+        }
+        for entry in fs::read_dir(&date_dir).ok()?.flatten() {
+            let path = entry.path();
+            let parsed = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .and_then(|name| parse_intelligent_file_name(name, topic));
+            if let Some((timestamp, file_number)) = parsed {
+                let is_newer = latest.as_ref().is_none_or(|(_, latest_ts, latest_number)| {
+                    (&timestamp, file_number) > (latest_ts, *latest_number)
+                });
+                if is_newer {
+                    latest = Some((path, timestamp, file_number));
+                }
+            }
+        }
+    }
+    latest
+}
+
+/// ヘルパー関数：閉じたファイルのサイドカーインデックスとマニフェストを書き込み（鍵があれば署名も）
+pub fn finalize_file(path: &Path, signing_key: Option<&SigningKey>, cipher: Option<&RecordCipher>) -> Result<(), RecorderError> {
+    let index = write_file_index(path, cipher).map_err(|e| RecorderError::io(path, e))?;
+    if let Some(key) = signing_key {
+        sign_file(path, &index, key).map_err(|e| RecorderError::io(path, e))?;
+    }
+    Ok(())
+}
+
+/// ヘルパー関数：記録の新しいファイルを作成（ディレクトリも作成）
+fn create_record_file(path: &Path) -> Result<fs::File, RecorderError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| RecorderError::io(parent, e))?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| RecorderError::io(path, e))
+}
+
+/// ヘルパー関数：メッセージを記録ファイルの `line_number` 行目に変換（ハッシュチェーンの付与と暗号化）
+fn encode_record(
+    msg: &mut MqttMessage,
+    chain: Option<&mut HashChain>,
+    cipher: Option<&RecordCipher>,
+    path: &Path,
+    line_number: u64,
+) -> Result<String, RecorderError> {
+    if let Some(chain) = chain {
+        chain.seal(msg)?;
+    }
+    let serialized = serde_json::to_string(msg)?;
+    seal_line(&serialized, cipher, path, line_number)
+        .map(|line| line.into_owned())
+        .map_err(|e| RecorderError::io(path, e))
+}
+
+/// 標準記録で書き込み中のファイル
+struct MinuteFile {
+    file: fs::File,
+    path: PathBuf,
+    minute: u32,
+    chain: HashChain,
+    lines: u64, // 書き込んだ行数（暗号化の行番号）
+}
+
+/// 標準記録用のファイル管理構造体（全トピックを1分ごとのファイルに記録）
+pub struct MinuteFileManager {
+    base_dir: PathBuf,
+    current: Option<MinuteFile>,
+    stats_manager: StatsManager,
+    hash_chain: bool,
+    signing_key: Option<SigningKey>,
+    cipher: Option<RecordCipher>,
+    heartbeat: Heartbeat,
+    metrics: Metrics,
+}
+
+impl MinuteFileManager {
+    pub fn new(base_dir: PathBuf, stats_enabled: bool, stats_interval_secs: u64) -> Self {
+        let stats_manager = StatsManager::new(base_dir.clone(), stats_enabled, stats_interval_secs);
+        Self {
+            base_dir,
+            current: None,
+            stats_manager,
+            hash_chain: false,
+            signing_key: None,
+            cipher: None,
+            heartbeat: Heartbeat::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// 各メッセージにハッシュチェーンを付与するかを設定
+    pub fn set_hash_chain(&mut self, enabled: bool) {
+        self.hash_chain = enabled;
+    }
+
+    /// 閉じたファイルに署名するための鍵を設定
+    pub fn set_signing_key(&mut self, key: Option<SigningKey>) {
+        self.signing_key = key;
+    }
+
+    /// 計算する統計量の設定
+    pub fn set_stats_config(&mut self, config: StatsConfig) {
+        self.stats_manager.set_config(config);
+    }
+
+    /// メッセージのない統計期間も件数0として出力するかを設定
+    pub fn set_stats_empty_windows(&mut self, enabled: bool) {
+        self.stats_manager.set_emit_empty(enabled);
+    }
+
+    /// スキーマの推定と変化の検出を設定
+    pub fn set_schema_drift(&mut self, enabled: bool, alert_topic: Option<String>) {
+        self.stats_manager.set_schema_drift(enabled, alert_topic);
+    }
+
+    /// アラートを送信するMQTTトピックを設定
+    pub fn set_alert_topic(&mut self, alert_topic: Option<String>) {
+        self.stats_manager.set_alert_topic(alert_topic);
+    }
+
+    /// 統計期間をJSONで送信するトピックの接頭辞を設定
+    pub fn set_stats_publish_prefix(&mut self, prefix: Option<String>) {
+        self.stats_manager.set_publish_prefix(prefix);
+    }
+
+    /// 統計のペイロードの解析方法を設定
+    pub fn set_payload_decoder(&mut self, decoder: PayloadDecoder) {
+        self.stats_manager.set_payload_decoder(decoder);
+    }
+
+    /// 書き込み、開いているファイル数、統計期間を記録するメトリクスを設定
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.stats_manager.set_metrics(metrics.clone());
+        self.metrics = metrics;
+    }
+
+    /// 統計からブローカーに送信するメッセージを取得
+    pub fn take_stats_outbound(&mut self) -> Vec<OutboundMessage> {
+        self.stats_manager.take_outbound()
+    }
+
+    /// 統計ファイルの出力形式を設定
+    pub fn set_stats_format(&mut self, format: StatsFormat) {
+        self.stats_manager.set_format(format);
+    }
+
+    /// 記録ファイルと統計ファイルを暗号化する鍵を設定
+    pub fn set_cipher(&mut self, cipher: Option<RecordCipher>) {
+        self.stats_manager.set_cipher(cipher.clone());
+        self.cipher = cipher;
+    }
+
+    /// メッセージを書き込み、統計分析も実行（分が変わったら新しいファイルに切り替え）
+    pub fn write_message(&mut self, msg: &mut MqttMessage) -> Result<(), RecorderError> {
+        use std::io::Write;
+
+        let minute = Local::now().minute();
+        if self.current.as_ref().is_some_and(|current| current.minute != minute) {
+            self.close_file();
+        }
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => {
+                let path = get_current_file_path(&self.base_dir);
+                let file = create_record_file(&path)?;
+                info!("Recording to: {:?}", path);
+                self.metrics.set_open_files(1);
+                self.current.insert(MinuteFile {
+                    file,
+                    chain: HashChain::new(&path),
+                    path,
+                    minute,
+                    lines: 0,
+                })
+            }
+        };
+
+        let chain = if self.hash_chain { Some(&mut current.chain) } else { None };
+        let line = encode_record(msg, chain, self.cipher.as_ref(), &current.path, current.lines + 1)?;
+        writeln!(current.file, "{}", line).map_err(|e| RecorderError::io(&current.path, e))?;
+        current.lines += 1;
+        self.heartbeat.record(line.len() + 1);
+        self.metrics.message_written(&msg.topic, line.len() + 1);
+
+        // 統計分析にメッセージを追加（期間の出力はタイマーから check_stats_windows で行う）
+        self.stats_manager.add_message(msg);
+        Ok(())
+    }
+
+    /// 書き込み中のファイルを閉じてインデックスを書き込み
+    fn close_file(&mut self) {
+        if let Some(current) = self.current.take() {
+            drop(current.file);
+            if let Err(e) = finalize_file(&current.path, self.signing_key.as_ref(), self.cipher.as_ref()) {
+                error!("Failed to finalize file: {}", e);
+            }
+            self.metrics.set_open_files(0);
+        }
+    }
+
+    /// 記録の状態を報告
+    pub fn heartbeat(&mut self) -> HeartbeatReport {
+        self.heartbeat.report(self.current.iter().count())
+    }
+
+    /// 終了した統計期間を出力（メッセージの到着とは独立にタイマーから呼び出す）
+    pub fn check_stats_windows(&mut self) {
+        self.stats_manager.check_and_calculate_stats();
+    }
+
+    /// ファイルを閉じて全トピックの統計を出力（記録の終了時）
+    pub fn close(&mut self) {
+        self.close_file();
+        self.stats_manager.force_calculate_all_stats();
+    }
+}
+
+/// トピックごとに開いているファイルの状態
+struct OpenFile {
+    file: fs::File,
+    path: PathBuf,
+    last_access: Instant,
+    message_count: u32,
+    file_number: u32,
+    chain: Option<HashChain>, // ハッシュチェーン（有効時のみ）
+    lines: u64, // ファイルの行数（暗号化の行番号、再開時は既存の行を含む）
+}
+
 /// インテリジェント記録用のファイル管理構造体
 pub struct TopicFileManager {
-    files: HashMap<String, (fs::File, PathBuf, Instant, u32, u32)>, // (ファイル, パス, 最終アクセス, メッセージ数, ファイル番号)
+    files: HashMap<String, OpenFile>,
     base_timestamps: HashMap<String, String>, // トピックごとのベースタイムスタンプ
     base_dir: PathBuf,
     timeout_secs: u64,
     max_messages_per_file: u32,
     stats_manager: StatsManager,
+    hash_chain: bool,
+    signing_key: Option<SigningKey>,
+    cipher: Option<RecordCipher>,
+    resume: bool,
+    resume_checked: HashSet<String>, // 再開チェック済みのトピック
+    heartbeat: Heartbeat,
+    metrics: Metrics,
+    last_cleanup: Instant,
 }
 
 impl TopicFileManager {
@@ -61,19 +342,94 @@ impl TopicFileManager {
             timeout_secs,
             max_messages_per_file: 100_000, // 10万メッセージまで
             stats_manager,
+            hash_chain: false,
+            signing_key: None,
+            cipher: None,
+            resume: false,
+            resume_checked: HashSet::new(),
+            heartbeat: Heartbeat::new(),
+            metrics: Metrics::default(),
+            last_cleanup: Instant::now(),
         }
     }
+
+    /// 各メッセージにハッシュチェーンを付与するかを設定
+    pub fn set_hash_chain(&mut self, enabled: bool) {
+        self.hash_chain = enabled;
+    }
+
+    /// 閉じたファイルに署名するための鍵を設定
+    pub fn set_signing_key(&mut self, key: Option<SigningKey>) {
+        self.signing_key = key;
+    }
+
+    /// 再起動時にタイムアウト内の既存ファイルへ追記するかを設定
+    pub fn set_resume(&mut self, enabled: bool) {
+        self.resume = enabled;
+    }
+
+    /// 計算する統計量の設定
+    pub fn set_stats_config(&mut self, config: StatsConfig) {
+        self.stats_manager.set_config(config);
+    }
+
+    /// メッセージのない統計期間も件数0として出力するかを設定
+    pub fn set_stats_empty_windows(&mut self, enabled: bool) {
+        self.stats_manager.set_emit_empty(enabled);
+    }
+
+    /// スキーマの推定と変化の検出を設定
+    pub fn set_schema_drift(&mut self, enabled: bool, alert_topic: Option<String>) {
+        self.stats_manager.set_schema_drift(enabled, alert_topic);
+    }
+
+    /// アラートを送信するMQTTトピックを設定
+    pub fn set_alert_topic(&mut self, alert_topic: Option<String>) {
+        self.stats_manager.set_alert_topic(alert_topic);
+    }
+
+    /// 統計期間をJSONで送信するトピックの接頭辞を設定
+    pub fn set_stats_publish_prefix(&mut self, prefix: Option<String>) {
+        self.stats_manager.set_publish_prefix(prefix);
+    }
+
+    /// 統計のペイロードの解析方法を設定
+    pub fn set_payload_decoder(&mut self, decoder: PayloadDecoder) {
+        self.stats_manager.set_payload_decoder(decoder);
+    }
+
+    /// 書き込み、開いているファイル数、統計期間を記録するメトリクスを設定
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.stats_manager.set_metrics(metrics.clone());
+        self.metrics = metrics;
+    }
+
+    /// 統計からブローカーに送信するメッセージを取得
+    pub fn take_stats_outbound(&mut self) -> Vec<OutboundMessage> {
+        self.stats_manager.take_outbound()
+    }
+
+    /// 統計ファイルの出力形式を設定
+    pub fn set_stats_format(&mut self, format: StatsFormat) {
+        self.stats_manager.set_format(format);
+    }
+
+    /// 記録ファイルと統計ファイルを暗号化する鍵を設定
+    pub fn set_cipher(&mut self, cipher: Option<RecordCipher>) {
+        self.stats_manager.set_cipher(cipher.clone());
+        self.cipher = cipher;
+    }
     
-    pub fn get_or_create_file(&mut self, topic: &str) -> Result<&mut fs::File, std::io::Error> {
+    fn get_or_create_file(&mut self, topic: &str) -> Result<(), RecorderError> {
         let now = Instant::now();
         let mut create_new_file = false;
         let mut file_number = 0;
         let mut use_existing_timestamp = false;
         
         // 既存のファイルをチェック（タイムアウトまたはメッセージ数制限）
-        let should_remove = if let Some((_, _, last_access, message_count, current_file_number)) = self.files.get(topic) {
-            let timed_out = now.duration_since(*last_access).as_secs() > self.timeout_secs;
-            let message_limit_reached = *message_count >= self.max_messages_per_file;
+        let should_remove = if let Some(open) = self.files.get(topic) {
+            let timed_out = now.duration_since(open.last_access).as_secs() > self.timeout_secs;
+            let message_limit_reached = open.message_count >= self.max_messages_per_file;
             
             if timed_out {
                 info!("File for topic '{}' timed out, creating new file", topic);
@@ -83,7 +439,7 @@ impl TopicFileManager {
                 create_new_file = true;
                 true
             } else if message_limit_reached {
-                file_number = current_file_number + 1;
+                file_number = open.file_number + 1;
                 use_existing_timestamp = true; // 既存のタイムスタンプを使用
                 info!("File for topic '{}' reached message limit ({}), creating new file with number {}", 
                       topic, self.max_messages_per_file, file_number);
@@ -99,8 +455,32 @@ impl TopicFileManager {
             false
         };
         
+        // 起動後最初のメッセージでは既存ファイルへの追記を試みる
+        if self.resume && !self.files.contains_key(topic) && self.resume_checked.insert(topic.to_string()) {
+            match self.resume_topic_file(topic) {
+                Ok(Some(next_file_number)) => {
+                    // 既存ファイルがメッセージ数制限に達していたので次の番号で作成
+                    file_number = next_file_number;
+                    use_existing_timestamp = true;
+                }
+                Ok(None) => {
+                    if self.files.contains_key(topic) {
+                        create_new_file = false;
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to resume file for topic '{}': {:?}", topic, e);
+                }
+            }
+        }
+        
         if should_remove {
-            self.files.remove(topic);
+            if let Some(open) = self.files.remove(topic) {
+                drop(open.file);
+                if let Err(e) = finalize_file(&open.path, self.signing_key.as_ref(), self.cipher.as_ref()) {
+                    error!("Failed to finalize file for topic '{}': {}", topic, e);
+                }
+            }
         }
         
         // ファイルが存在しない場合は新規作成
@@ -118,78 +498,336 @@ impl TopicFileManager {
             
             // ディレクトリを作成
             if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
+                fs::create_dir_all(parent).map_err(|e| RecorderError::io(parent, e))?;
             }
             
             let file = fs::OpenOptions::new()
                 .write(true)
                 .create_new(true)
-                .open(&file_path)?;
+                .open(&file_path)
+                .map_err(|e| RecorderError::io(&file_path, e))?;
                 
             info!("Created new file for topic '{}': {:?}", topic, file_path);
-            self.files.insert(topic.to_string(), (file, file_path, now, 0, file_number));
-        } else {
-            // アクセス時刻を更新し、メッセージ数をインクリメント
-            if let Some((_, _, last_access, message_count, _)) = self.files.get_mut(topic) {
-                *last_access = now;
-                *message_count += 1;
+            let chain = if self.hash_chain { Some(HashChain::new(&file_path)) } else { None };
+            self.files.insert(topic.to_string(), OpenFile {
+                file,
+                path: file_path,
+                last_access: now,
+                message_count: 0,
+                file_number,
+                chain,
+                lines: 0,
+            });
+        }
+        
+        Ok(())
+    }
+    
+    /// トピックの最新ファイルがタイムアウト内なら追記用に開く
+    /// 最新ファイルがメッセージ数制限に達している場合は次のファイル番号を返す
+    fn resume_topic_file(&mut self, topic: &str) -> Result<Option<u32>, std::io::Error> {
+        let (path, base_timestamp, file_number) = match find_latest_topic_file(&self.base_dir, topic) {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        
+        // 最終更新からタイムアウト時間を超えていれば新しいファイルを作成
+        let modified = fs::metadata(&path)?.modified()?;
+        let idle_secs = SystemTime::now()
+            .duration_since(modified)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if idle_secs > self.timeout_secs {
+            debug!("Latest file for topic '{}' is idle for {}s, not resuming: {:?}", topic, idle_secs, path);
+            return Ok(None);
+        }
+        
+        // 既存のメッセージ数と最後のチェーンの値を取得（改行で終わっていない最後の行は除く）
+        let mut message_count = 0;
+        let mut lines = 0u64;
+        let mut last_chain = None;
+        let mut complete_len = 0u64;
+        let mut reader = BufReader::new(fs::File::open(&path)?);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            complete_len += read as u64;
+            lines += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            message_count += 1;
+            if self.hash_chain {
+                let chain = open_line(line.trim_end(), self.cipher.as_ref(), &path, lines)
+                    .ok()
+                    .and_then(|plain| serde_json::from_str::<MqttMessage>(&plain).ok())
+                    .and_then(|msg| msg.chain);
+                if chain.is_some() {
+                    last_chain = chain;
+                }
             }
         }
         
-        Ok(&mut self.files.get_mut(topic).unwrap().0)
+        self.base_timestamps.insert(topic.to_string(), base_timestamp);
+        if message_count >= self.max_messages_per_file {
+            info!("Latest file for topic '{}' is full, continuing with file number {}", topic, file_number + 1);
+            return Ok(Some(file_number + 1));
+        }
+        
+        // 異常終了で途中まで書き込まれた最後の行を切り詰める
+        let file = fs::OpenOptions::new().append(true).open(&path)?;
+        let file_len = file.metadata()?.len();
+        if file_len > complete_len {
+            warn!("Truncating {} bytes of partial last line in {:?}", file_len - complete_len, path);
+            file.set_len(complete_len)?;
+        }
+        
+        // 閉じた時に書き込んだインデックス、署名、マニフェストのエントリは追記で古くなるため削除
+        remove_file_index(&path)?;
+        let signature_path = signature_path_for(&path);
+        if signature_path.exists() {
+            fs::remove_file(signature_path)?;
+        }
+        
+        info!("Resuming file for topic '{}' ({} messages): {:?}", topic, message_count, path);
+        let chain = if self.hash_chain { Some(HashChain::resume(&path, last_chain)) } else { None };
+        self.files.insert(topic.to_string(), OpenFile {
+            file,
+            path,
+            last_access: Instant::now(),
+            message_count,
+            file_number,
+            chain,
+            lines,
+        });
+        Ok(None)
     }
     
     pub fn cleanup_timeout_files(&mut self) {
         let now = Instant::now();
         let timeout_secs = self.timeout_secs;
+        self.last_cleanup = now;
         
         // タイムアウトしたトピックを収集
         let mut topics_to_remove = Vec::new();
+        let mut closed_paths = Vec::new();
         
-        self.files.retain(|topic, (_, _, last_access, _, _)| {
-            let should_keep = now.duration_since(*last_access).as_secs() <= timeout_secs;
+        self.files.retain(|topic, open| {
+            let should_keep = now.duration_since(open.last_access).as_secs() <= timeout_secs;
             if !should_keep {
                 info!("Closing file for topic '{}' due to timeout", topic);
                 topics_to_remove.push(topic.clone());
+                closed_paths.push(open.path.clone());
             }
             should_keep
         });
         
+        // 閉じたファイルのインデックスを書き込み
+        for path in &closed_paths {
+            if let Err(e) = finalize_file(path, self.signing_key.as_ref(), self.cipher.as_ref()) {
+                error!("Failed to finalize file: {}", e);
+            }
+        }
+        
         // タイムアウトしたトピックの統計を強制計算してからベースタイムスタンプもクリア
         for topic in &topics_to_remove {
             self.stats_manager.force_calculate_stats_for_topic(topic);
             self.base_timestamps.remove(topic);
         }
+        self.metrics.set_open_files(self.files.len());
     }
 
     /// メッセージを書き込み、統計分析も実行
-    pub fn write_message(&mut self, topic: &str, json_message: &str) -> Result<(), std::io::Error> {
+    pub fn write_message(&mut self, msg: &mut MqttMessage) -> Result<(), RecorderError> {
         use std::io::Write;
         
-        // ファイルに書き込み
-        {
-            let file = self.get_or_create_file(topic)?;
-            writeln!(file, "{}", json_message)?;
-            file.flush()?;
-        }
+        let topic = msg.topic.clone();
         
-        // メッセージ数をインクリメント
-        if let Some((_, _, ref mut last_access, ref mut message_count, _)) = self.files.get_mut(topic) {
-            *last_access = std::time::Instant::now();
-            *message_count += 1;
+        // ファイルに書き込み（アクセス時刻を更新し、メッセージ数をインクリメント）
+        {
+            self.get_or_create_file(&topic)?;
+            let open = self.files.get_mut(&topic).unwrap();
+            if let Some(chain) = open.chain.as_mut() {
+                chain.seal(msg)?;
+            }
+            let serialized = serde_json::to_string(msg)?;
+            let line = seal_line(&serialized, self.cipher.as_ref(), &open.path, open.lines + 1)
+                .map_err(|e| RecorderError::io(&open.path, e))?;
+            writeln!(open.file, "{}", line)
+                .and_then(|_| open.file.flush())
+                .map_err(|e| RecorderError::io(&open.path, e))?;
+            open.lines += 1;
+            open.last_access = Instant::now();
+            open.message_count += 1;
+            self.heartbeat.record(line.len() + 1);
+            self.metrics.message_written(&topic, line.len() + 1);
+            self.metrics.set_open_files(self.files.len());
         }
         
-        // 統計分析にメッセージを追加
-        self.stats_manager.add_message(topic, json_message);
-        
-        // 定期的な統計計算をチェック
-        self.stats_manager.check_and_calculate_stats();
+        // 統計分析にメッセージを追加（期間の出力はタイマーから check_stats_windows で行う）
+        self.stats_manager.add_message(msg);
         
         Ok(())
     }
 
+    /// 記録の状態を報告
+    pub fn heartbeat(&mut self) -> HeartbeatReport {
+        self.heartbeat.report(self.files.len())
+    }
+
+    /// 終了した統計期間を出力（メッセージの到着とは独立にタイマーから呼び出す）
+    pub fn check_stats_windows(&mut self) {
+        self.stats_manager.check_and_calculate_stats();
+    }
+
     /// ファイル分割時に統計を強制計算
     pub fn force_stats_calculation(&mut self, topic: &str) {
         self.stats_manager.force_calculate_stats_for_topic(topic);
     }
+
+    /// タイムアウトの半分の間隔でファイルをクリーンアップし、終了した統計期間を出力（1秒ごとに呼び出す）
+    pub fn tick(&mut self) {
+        if self.last_cleanup.elapsed().as_secs() >= (self.timeout_secs / 2).max(1) {
+            self.cleanup_timeout_files();
+        }
+        self.check_stats_windows();
+    }
+
+    /// 開いているすべてのファイルを閉じて統計を出力（記録の終了時）
+    pub fn close_all(&mut self) {
+        for (topic, open) in std::mem::take(&mut self.files) {
+            drop(open.file);
+            if let Err(e) = finalize_file(&open.path, self.signing_key.as_ref(), self.cipher.as_ref()) {
+                error!("Failed to finalize file for topic '{}': {}", topic, e);
+            }
+            self.stats_manager.force_calculate_stats_for_topic(&topic);
+        }
+        self.base_timestamps.clear();
+        self.metrics.set_open_files(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::index::{index_path_for, Manifest};
+
+    fn message(topic: &str, time: f64) -> MqttMessage {
+        MqttMessage {
+            time,
+            qos: 0,
+            retain: false,
+            topic: topic.to_string(),
+            msg_b64: base64::encode(b"{\"x\":1}"),
+            seq: None,
+            mono: None,
+            run: None,
+            chain: None,
+        }
+    }
+
+    fn manager(base_dir: &Path) -> TopicFileManager {
+        let mut manager = TopicFileManager::new(base_dir.to_path_buf(), 300, false, 60);
+        manager.set_resume(true);
+        manager.set_signing_key(Some(SigningKey::from_bytes(&[7; 32])));
+        manager
+    }
+
+    #[test]
+    fn resume_invalidates_sidecars_and_truncates_partial_line() {
+        let base_dir = std::env::temp_dir().join(format!("mqtt-recorder-resume-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+
+        let mut first = manager(&base_dir);
+        first.write_message(&mut message("s/t", 1.0)).unwrap();
+        first.write_message(&mut message("s/t", 2.0)).unwrap();
+        first.close_all();
+
+        let (path, _, _) = find_latest_topic_file(&base_dir, "s/t").unwrap();
+        let dir = path.parent().unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(index_path_for(&path).exists());
+        assert!(signature_path_for(&path).exists());
+        assert!(Manifest::load(dir).unwrap().files.contains_key(&name));
+
+        // 異常終了で途中まで書き込まれた行を再現
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"time\": 3.0, \"qos\"").unwrap();
+        drop(file);
+
+        let mut second = manager(&base_dir);
+        second.write_message(&mut message("s/t", 4.0)).unwrap();
+        assert!(!index_path_for(&path).exists());
+        assert!(!signature_path_for(&path).exists());
+        assert!(!Manifest::load(dir).unwrap().files.contains_key(&name));
+
+        let content = fs::read_to_string(&path).unwrap();
+        let times: Vec<f64> = content
+            .lines()
+            .map(|line| serde_json::from_str::<MqttMessage>(line).unwrap().time)
+            .collect();
+        assert_eq!(times, vec![1.0, 2.0, 4.0]);
+
+        second.close_all();
+        assert!(index_path_for(&path).exists());
+        assert!(Manifest::load(dir).unwrap().files.contains_key(&name));
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn encrypted_resume_keeps_lines_readable_from_index_seek() {
+        let base_dir = std::env::temp_dir().join(format!("mqtt-recorder-resume-encrypted-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        let cipher = RecordCipher::new(&[9; 32]);
+
+        let mut first = manager(&base_dir);
+        first.set_cipher(Some(cipher.clone()));
+        for i in 0..9 {
+            first.write_message(&mut message("s/t", i as f64 * 5.0)).unwrap();
+        }
+        first.close_all();
+
+        let mut second = manager(&base_dir);
+        second.set_cipher(Some(cipher.clone()));
+        second.write_message(&mut message("s/t", 45.0)).unwrap();
+        second.close_all();
+
+        let (path, _, _) = find_latest_topic_file(&base_dir, "s/t").unwrap();
+        let index = crate::index::read_file_index(&path).unwrap();
+        assert_eq!(index.summary.message_count, 10);
+        assert_eq!(index.seek_offset(25.0).1, 4);
+
+        let times: Vec<f64> = crate::replay::MergedRecords::new(vec![path], Some(25.0), Some(cipher))
+            .map(|msg| msg.time)
+            .collect();
+        assert_eq!(times, vec![25.0, 30.0, 35.0, 40.0, 45.0]);
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn plain_record_mode_writes_payload_statistics() {
+        let base_dir = std::env::temp_dir().join(format!("mqtt-recorder-minute-stats-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(&base_dir).unwrap();
+
+        let mut manager = MinuteFileManager::new(base_dir.clone(), true, 60);
+        for i in 0..3 {
+            let mut msg = message("plain/bin", i as f64);
+            msg.msg_b64 = base64::encode([0xffu8, i]);
+            manager.write_message(&mut msg).unwrap();
+        }
+        manager.close();
+
+        let stats_path = base_dir.join("plain/bin/mqtt-recorder-plain-bin-stats.txt");
+        let content = fs::read_to_string(&stats_path).unwrap();
+        assert!(content.contains("$payload{messages}:3.000"), "{}", content);
+        assert!(content.contains("$payload{size_mean}:2.000"), "{}", content);
+        assert!(content.contains("$payload{duplicate_rate}:0.000"), "{}", content);
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
 }
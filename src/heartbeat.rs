@@ -0,0 +1,70 @@
+use std::time::Instant;
+use chrono::{Local, SecondsFormat};
+use serde::Serialize;
+
+/// 記録の状態の報告
+#[derive(Debug, Clone, Serialize)]
+pub struct HeartbeatReport {
+    pub time: String,
+    pub uptime_secs: u64,
+    /// 起動からの記録メッセージ数
+    pub messages: u64,
+    /// 前回の報告からの1秒あたりのメッセージ数
+    pub messages_per_sec: f64,
+    pub open_files: usize,
+    /// 起動からの書き込みバイト数（暗号化後）
+    pub bytes_written: u64,
+}
+
+/// 記録のハートビート（稼働時間、メッセージレート、書き込み量）
+#[derive(Debug)]
+pub struct Heartbeat {
+    started: Instant,
+    last_report: Instant,
+    messages: u64,
+    messages_at_last_report: u64,
+    bytes_written: u64,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Heartbeat {
+            started: now,
+            last_report: now,
+            messages: 0,
+            messages_at_last_report: 0,
+            bytes_written: 0,
+        }
+    }
+
+    /// 書き込んだメッセージを記録
+    pub fn record(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes_written += bytes as u64;
+    }
+
+    /// 現在の状態を報告（メッセージレートは前回の報告からの値）
+    pub fn report(&mut self, open_files: usize) -> HeartbeatReport {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_report).as_secs_f64();
+        let messages = self.messages - self.messages_at_last_report;
+        self.last_report = now;
+        self.messages_at_last_report = self.messages;
+
+        HeartbeatReport {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            uptime_secs: now.duration_since(self.started).as_secs(),
+            messages: self.messages,
+            messages_per_sec: if elapsed > 0.0 { messages as f64 / elapsed } else { 0.0 },
+            open_files,
+            bytes_written: self.bytes_written,
+        }
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::crypto::{open_line, RecordCipher};
+use crate::message::MqttMessage;
+
+/// インデックスのチェックポイントを記録する間隔（記録時刻ベース、秒）
+pub const INDEX_INTERVAL_SECS: f64 = 10.0;
+
+/// ディレクトリ単位のマニフェストファイル名
+pub const MANIFEST_FILE_NAME: &str = "mqtt-recorder-manifest.idx";
+
+/// 記録ファイルの概要（マニフェストとサイドカーインデックスで共通）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSummary {
+    pub first_time: f64,
+    pub last_time: f64,
+    pub message_count: u64,
+    pub topics: Vec<String>,
+    pub sha256: String,
+}
+
+impl FileSummary {
+    /// 指定された時間範囲（UNIX秒）と重なるかチェック
+    pub fn overlaps(&self, start: Option<f64>, end: Option<f64>) -> bool {
+        if self.message_count == 0 {
+            return false;
+        }
+        if let Some(start) = start {
+            if self.last_time < start {
+                return false;
+            }
+        }
+        if let Some(end) = end {
+            if self.first_time > end {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 時刻とバイトオフセットの対応
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexCheckpoint {
+    pub time: f64,
+    pub offset: u64,
+    /// オフセットより前の行数（暗号化された行の復号に必要、古いインデックスにはない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<u64>,
+}
+
+/// ファイルごとのサイドカーインデックス
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileIndex {
+    #[serde(flatten)]
+    pub summary: FileSummary,
+    pub interval_secs: f64,
+    pub checkpoints: Vec<IndexCheckpoint>,
+}
+
+impl FileIndex {
+    /// 記録ファイルを走査してインデックスを構築（暗号化されている場合は復号して解析）
+    pub fn build(path: &Path, cipher: Option<&RecordCipher>) -> io::Result<Self> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let mut hasher = Sha256::new();
+        let mut topics = BTreeSet::new();
+        let mut checkpoints: Vec<IndexCheckpoint> = Vec::new();
+        let mut first_time = 0.0;
+        let mut last_time = 0.0;
+        let mut message_count = 0;
+        let mut offset = 0u64;
+        let mut line_number = 0u64;
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(line.as_bytes());
+            line_number += 1;
+
+            let msg = open_line(&line, cipher, path, line_number)
+                .ok()
+                .and_then(|plain| serde_json::from_str::<MqttMessage>(&plain).ok());
+            if let Some(msg) = msg {
+                if message_count == 0 {
+                    first_time = msg.time;
+                }
+                last_time = msg.time;
+                message_count += 1;
+                topics.insert(msg.topic.clone());
+
+                // 前回のチェックポイントから一定時間経過していればオフセットを記録
+                let needs_checkpoint = checkpoints
+                    .last()
+                    .is_none_or(|cp| msg.time - cp.time >= INDEX_INTERVAL_SECS);
+                if needs_checkpoint {
+                    checkpoints.push(IndexCheckpoint {
+                        time: msg.time,
+                        offset,
+                        lines: Some(line_number - 1),
+                    });
+                }
+            }
+            offset += read as u64;
+        }
+
+        Ok(FileIndex {
+            summary: FileSummary {
+                first_time,
+                last_time,
+                message_count,
+                topics: topics.into_iter().collect(),
+                sha256: format!("{:x}", hasher.finalize()),
+            },
+            interval_secs: INDEX_INTERVAL_SECS,
+            checkpoints,
+        })
+    }
+
+    /// 指定時刻以前で最も近いチェックポイントのバイトオフセットとそれより前の行数を取得
+    /// 行数のないチェックポイントは使用しない
+    pub fn seek_offset(&self, time: f64) -> (u64, u64) {
+        self.checkpoints
+            .iter()
+            .take_while(|cp| cp.time <= time)
+            .filter_map(|cp| cp.lines.map(|lines| (cp.offset, lines)))
+            .last()
+            .unwrap_or((0, 0))
+    }
+}
+
+/// ディレクトリ単位のマニフェスト（ファイル名 -> 概要）
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, FileSummary>,
+}
+
+impl Manifest {
+    /// ディレクトリのマニフェストを読み込み（存在しない場合は空）
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// マニフェストを一時ファイル経由で書き込み
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// ヘルパー関数：記録ファイルに対応するサイドカーインデックスのパスを生成
+pub fn index_path_for(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".idx");
+    file_path.with_file_name(name)
+}
+
+/// ヘルパー関数：ファイルを閉じた後にインデックスを書き込み、マニフェストを更新
+pub fn write_file_index(file_path: &Path, cipher: Option<&RecordCipher>) -> io::Result<FileIndex> {
+    let index = FileIndex::build(file_path, cipher)?;
+    fs::write(index_path_for(file_path), serde_json::to_string(&index)?)?;
+
+    if let (Some(dir), Some(name)) = (file_path.parent(), file_path.file_name()) {
+        let mut manifest = Manifest::load(dir).unwrap_or_else(|e| {
+            warn!("Failed to read manifest in {:?}, rebuilding: {:?}", dir, e);
+            Manifest::default()
+        });
+        manifest
+            .files
+            .insert(name.to_string_lossy().into_owned(), index.summary.clone());
+        manifest.save(dir)?;
+    }
+
+    debug!(
+        "Wrote index for {:?} ({} messages, {} checkpoints)",
+        file_path,
+        index.summary.message_count,
+        index.checkpoints.len()
+    );
+    Ok(index)
+}
+
+/// ヘルパー関数：追記を再開するファイルのインデックスとマニフェストのエントリを削除
+pub fn remove_file_index(file_path: &Path) -> io::Result<()> {
+    let index_path = index_path_for(file_path);
+    if index_path.exists() {
+        fs::remove_file(index_path)?;
+    }
+
+    if let (Some(dir), Some(name)) = (file_path.parent(), file_path.file_name()) {
+        let mut manifest = Manifest::load(dir)?;
+        if manifest.files.remove(name.to_string_lossy().as_ref()).is_some() {
+            manifest.save(dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// ヘルパー関数：サイドカーインデックスを読み込み
+pub fn read_file_index(file_path: &Path) -> Option<FileIndex> {
+    let content = fs::read_to_string(index_path_for(file_path)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// ヘルパー関数：ファイルの概要を取得（サイドカー優先、なければマニフェストから）
+pub fn read_file_summary(file_path: &Path) -> Option<FileSummary> {
+    if let Some(index) = read_file_index(file_path) {
+        return Some(index.summary);
+    }
+    let dir = file_path.parent()?;
+    let name = file_path.file_name()?.to_string_lossy().into_owned();
+    Manifest::load(dir).ok()?.files.remove(&name)
+}
+
+/// ヘルパー関数：インデックスを使用して指定時刻付近から読み込めるようにファイルを開く
+/// 読み飛ばした行数も返す（次に読み込む行の行番号は行数 + 1）
+pub fn open_at_time(file_path: &Path, time: Option<f64>) -> io::Result<(BufReader<fs::File>, u64)> {
+    let mut file = fs::File::open(file_path)?;
+    let mut skipped = 0;
+    if let Some(time) = time {
+        if let Some(index) = read_file_index(file_path) {
+            let (offset, lines) = index.seek_offset(time);
+            if offset > 0 {
+                debug!("Seeking {:?} to offset {} for time {}", file_path, offset, time);
+                file.seek(SeekFrom::Start(offset))?;
+                skipped = lines;
+            }
+        }
+    }
+    Ok((BufReader::new(file), skipped))
+}
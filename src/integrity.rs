@@ -0,0 +1,408 @@
+use std::{
+    convert::TryInto,
+    fmt, fs,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::*;
+use sha2::{Digest, Sha256};
+use crate::crypto::{is_encrypted_line, open_line, RecordCipher};
+use crate::index::{read_file_summary, FileIndex, Manifest};
+use crate::message::MqttMessage;
+
+/// ヘルパー関数：チェーンの前の値とレコードからハッシュを計算
+pub fn chain_hash(previous: &str, record: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(record.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// ヘルパー関数：ファイル名からチェーンの初期値を計算（ファイルの差し替えを検出するため）
+pub fn chain_genesis(file_path: &Path) -> String {
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    chain_hash("", &name)
+}
+
+/// ファイル単位のハッシュチェーン
+pub struct HashChain {
+    last: String,
+}
+
+impl HashChain {
+    pub fn new(file_path: &Path) -> Self {
+        HashChain {
+            last: chain_genesis(file_path),
+        }
+    }
+
+    /// 既存ファイルの最後のチェーンの値から続ける（追記再開時）
+    pub fn resume(file_path: &Path, last: Option<String>) -> Self {
+        HashChain {
+            last: last.unwrap_or_else(|| chain_genesis(file_path)),
+        }
+    }
+
+    /// メッセージにチェーンの値を付与（チェーンの値を除いたレコードをハッシュ化）
+    /// チェーンの値は書き込む行の最後のフィールドになるため、検証時は行からその部分を除いてハッシュ化する
+    pub fn seal(&mut self, msg: &mut MqttMessage) -> serde_json::Result<()> {
+        msg.chain = None;
+        let record = serde_json::to_string(msg)?;
+        self.last = chain_hash(&self.last, &record);
+        msg.chain = Some(self.last.clone());
+        Ok(())
+    }
+}
+
+/// ヘルパー関数：署名ファイルのパスを生成
+pub fn signature_path_for(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".sig");
+    file_path.with_file_name(name)
+}
+
+/// ヘルパー関数：16進数文字列のキーファイルから32バイトを読み込み
+pub(crate) fn read_key_bytes(path: &Path) -> io::Result<[u8; 32]> {
+    let content = fs::read_to_string(path)?;
+    let bytes = hex::decode(content.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    bytes.try_into().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "key file must contain 32 hex-encoded bytes")
+    })
+}
+
+/// ヘルパー関数：Ed25519の秘密鍵を読み込み
+pub fn load_signing_key(path: &Path) -> io::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key_bytes(path)?))
+}
+
+/// ヘルパー関数：Ed25519の公開鍵を読み込み
+pub fn load_verifying_key(path: &Path) -> io::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&read_key_bytes(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// ヘルパー関数：新しい鍵ペアを生成して保存（公開鍵は `<path>.pub`）
+pub fn generate_key_pair(path: &Path) -> io::Result<PathBuf> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(io::Error::from)?;
+    let signing_key = SigningKey::from_bytes(&seed);
+
+    let mut public_name = path.file_name().unwrap_or_default().to_os_string();
+    public_name.push(".pub");
+    let public_path = path.with_file_name(public_name);
+
+    fs::write(path, hex::encode(signing_key.to_bytes()) + "\n")?;
+    fs::write(&public_path, hex::encode(signing_key.verifying_key().to_bytes()) + "\n")?;
+    Ok(public_path)
+}
+
+/// ヘルパー関数：閉じたファイルのSHA-256ダイジェストに署名して `<file>.sig` に保存
+pub fn sign_file(file_path: &Path, index: &FileIndex, key: &SigningKey) -> io::Result<()> {
+    let digest = hex::decode(&index.summary.sha256)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let signature = key.sign(&digest);
+    fs::write(signature_path_for(file_path), hex::encode(signature.to_bytes()) + "\n")
+}
+
+/// 検証で見つかった問題
+#[derive(Debug)]
+pub enum VerifyIssue {
+    /// ハッシュチェーンが一致しない最初のメッセージ
+    ChainMismatch { line: u64, time: Option<f64> },
+    /// チェーンの値がないメッセージ（チェーン付きファイル内）
+    MissingChain { line: u64 },
+    /// チェーンが必要なのにチェーンの値を持つメッセージがない
+    Unchained,
+    /// インデックスまたはマニフェストのダイジェストと一致しない
+    DigestMismatch { expected: String, actual: String },
+    /// 署名が無効
+    BadSignature,
+    /// 公開鍵が指定されているのに署名ファイルがない
+    MissingSignature,
+    /// 暗号化された行を復号できない（鍵の誤りまたは改ざん）
+    DecryptionFailed { line: u64 },
+}
+
+impl fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyIssue::ChainMismatch { line, time: Some(time) } => {
+                write!(f, "hash chain broken at line {} (message time {:.3})", line, time)
+            }
+            VerifyIssue::ChainMismatch { line, time: None } => {
+                write!(f, "hash chain broken at line {} (unparsable record)", line)
+            }
+            VerifyIssue::MissingChain { line } => write!(f, "missing chain value at line {}", line),
+            VerifyIssue::Unchained => write!(f, "file has no hash chain"),
+            VerifyIssue::DigestMismatch { expected, actual } => {
+                write!(f, "file digest {} does not match recorded digest {}", actual, expected)
+            }
+            VerifyIssue::BadSignature => write!(f, "signature is invalid"),
+            VerifyIssue::MissingSignature => write!(f, "signature file is missing"),
+            VerifyIssue::DecryptionFailed { line } => {
+                write!(f, "failed to decrypt line {}, wrong key or altered data", line)
+            }
+        }
+    }
+}
+
+/// ファイルごとの検証結果
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub path: PathBuf,
+    pub messages: u64,
+    pub chained: bool,
+    /// 暗号化されていて鍵がないためチェーンを検証できなかった
+    pub encrypted: bool,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// ヘルパー関数：記録された行からチェーンの値を除き、`HashChain::seal` でハッシュ化したレコードを復元
+/// （値を解析し直すと浮動小数点数の表記が変わることがあるため、書き込まれたバイト列をそのまま使う）
+fn unchained_record(line: &str, chain: &str) -> Option<String> {
+    let suffix = format!(",\"chain\":{}}}", serde_json::to_string(chain).ok()?);
+    line.trim_end().strip_suffix(&suffix).map(|record| format!("{}}}", record))
+}
+
+/// 記録ファイルのハッシュチェーン、ダイジェスト、署名を検証
+/// `require_chain` の場合、チェーンの値を持つメッセージがないファイルは問題として報告する
+pub fn verify_file(
+    file_path: &Path,
+    key: Option<&VerifyingKey>,
+    cipher: Option<&RecordCipher>,
+    require_chain: bool,
+) -> io::Result<VerifyReport> {
+    let mut reader = BufReader::new(fs::File::open(file_path)?);
+    let mut hasher = Sha256::new();
+    let mut report = VerifyReport {
+        path: file_path.to_path_buf(),
+        messages: 0,
+        chained: false,
+        encrypted: false,
+        issues: Vec::new(),
+    };
+    let mut last = chain_genesis(file_path);
+    let mut chain_ok = true;
+    let mut line_number = 0;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        hasher.update(line.as_bytes());
+        line_number += 1;
+
+        // 最初の不一致のみ報告する
+        if !chain_ok {
+            continue;
+        }
+        if cipher.is_none() && is_encrypted_line(&line) {
+            report.encrypted = true;
+            chain_ok = false;
+            continue;
+        }
+        let plain = match open_line(&line, cipher, file_path, line_number) {
+            Ok(plain) => plain,
+            Err(_) => {
+                report.issues.push(VerifyIssue::DecryptionFailed { line: line_number });
+                chain_ok = false;
+                continue;
+            }
+        };
+        match serde_json::from_str::<MqttMessage>(&plain) {
+            Ok(mut msg) => {
+                report.messages += 1;
+                match msg.chain.take() {
+                    Some(recorded) => {
+                        report.chained = true;
+                        let expected = unchained_record(&plain, &recorded).map(|record| chain_hash(&last, &record));
+                        if expected.as_deref() != Some(recorded.as_str()) {
+                            report.issues.push(VerifyIssue::ChainMismatch {
+                                line: line_number,
+                                time: Some(msg.time),
+                            });
+                            chain_ok = false;
+                        }
+                        last = recorded;
+                    }
+                    None if report.chained => {
+                        report.issues.push(VerifyIssue::MissingChain { line: line_number });
+                        chain_ok = false;
+                    }
+                    None => {}
+                }
+            }
+            Err(_) if report.chained => {
+                report.issues.push(VerifyIssue::ChainMismatch {
+                    line: line_number,
+                    time: None,
+                });
+                chain_ok = false;
+            }
+            Err(_) => {}
+        }
+    }
+
+    // チェーンの値をすべて削除されたファイルを検出（暗号化されていて鍵がない場合は判定できない）
+    if require_chain && !report.chained && !report.encrypted && chain_ok {
+        report.issues.push(VerifyIssue::Unchained);
+    }
+
+    let digest = hasher.finalize();
+    let actual = format!("{:x}", digest);
+    if let Some(summary) = read_file_summary(file_path) {
+        if summary.sha256 != actual {
+            report.issues.push(VerifyIssue::DigestMismatch {
+                expected: summary.sha256,
+                actual,
+            });
+        }
+    }
+
+    if let Some(key) = key {
+        match fs::read_to_string(signature_path_for(file_path)) {
+            Ok(content) => {
+                let valid = hex::decode(content.trim())
+                    .ok()
+                    .and_then(|bytes| Signature::from_slice(&bytes).ok())
+                    .map(|signature| key.verify(&digest, &signature).is_ok())
+                    .unwrap_or(false);
+                if !valid {
+                    report.issues.push(VerifyIssue::BadSignature);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                report.issues.push(VerifyIssue::MissingSignature);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    debug!("Verified {:?}: {} messages, {} issues", file_path, report.messages, report.issues.len());
+    Ok(report)
+}
+
+/// ヘルパー関数：マニフェストに記録されているのに存在しないファイルを検出（ファイルの削除を検出するため）
+pub fn find_missing_files(base_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut missing = Vec::new();
+    if !base_dir.is_dir() {
+        return Ok(missing);
+    }
+    let manifest = Manifest::load(base_dir)?;
+    for name in manifest.files.keys() {
+        let path = base_dir.join(name);
+        if !path.exists() {
+            missing.push(path);
+        }
+    }
+    for entry in fs::read_dir(base_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            missing.extend(find_missing_files(&path)?);
+        }
+    }
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn message(time: f64) -> MqttMessage {
+        MqttMessage {
+            time,
+            qos: 1,
+            retain: false,
+            topic: "s/t".to_string(),
+            msg_b64: base64::encode(b"{\"x\":1}"),
+            seq: Some(0),
+            mono: Some(time + 1e-7),
+            run: Some(7),
+            chain: None,
+        }
+    }
+
+    /// チェーン付きのファイルを書き込み、各行を返す
+    fn write_chained(path: &Path, count: usize) -> Vec<String> {
+        let mut chain = HashChain::new(path);
+        let mut lines = Vec::new();
+        for i in 0..count {
+            // 解析し直すと値が変わりうる桁数の多い時刻
+            let mut msg = message(1_753_405_200.123_456_7 + i as f64 * 0.000_917_3);
+            msg.seq = Some(i as u64);
+            chain.seal(&mut msg).unwrap();
+            lines.push(serde_json::to_string(&msg).unwrap());
+        }
+        write_lines(path, &lines);
+        lines
+    }
+
+    fn write_lines(path: &Path, lines: &[String]) {
+        let mut file = fs::File::create(path).unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mqtt-recorder-integrity-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("mqtt-recorder-2025-07-25-1000.json")
+    }
+
+    #[test]
+    fn verifies_freshly_sealed_file() {
+        let path = temp_file("fresh");
+        write_chained(&path, 2000);
+        let report = verify_file(&path, None, None, true).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert!(report.chained);
+        assert_eq!(report.messages, 2000);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn detects_altered_deleted_and_unchained_records() {
+        let path = temp_file("altered");
+        let lines = write_chained(&path, 5);
+
+        let mut altered = lines.clone();
+        altered[2] = altered[2].replace("\"qos\":1", "\"qos\":0");
+        write_lines(&path, &altered);
+        let report = verify_file(&path, None, None, false).unwrap();
+        assert!(matches!(report.issues[..], [VerifyIssue::ChainMismatch { line: 3, .. }]), "{:?}", report.issues);
+
+        let mut deleted = lines.clone();
+        deleted.remove(1);
+        write_lines(&path, &deleted);
+        let report = verify_file(&path, None, None, false).unwrap();
+        assert!(matches!(report.issues[..], [VerifyIssue::ChainMismatch { line: 2, .. }]), "{:?}", report.issues);
+
+        // チェーンの値をすべて削除したファイルはチェーンを要求した場合のみ失敗
+        let stripped: Vec<String> = lines
+            .iter()
+            .map(|line| {
+                let mut msg: MqttMessage = serde_json::from_str(line).unwrap();
+                msg.chain = None;
+                serde_json::to_string(&msg).unwrap()
+            })
+            .collect();
+        write_lines(&path, &stripped);
+        assert!(verify_file(&path, None, None, false).unwrap().is_ok());
+        let report = verify_file(&path, None, None, true).unwrap();
+        assert!(matches!(report.issues[..], [VerifyIssue::Unchained]), "{:?}", report.issues);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,11 +1,45 @@
+pub mod alert;
+pub mod cbor;
 pub mod config;
+pub mod crypto;
+pub mod decode;
+pub mod error;
 pub mod message;
 pub mod file_manager;
+pub mod heartbeat;
+pub mod index;
+pub mod integrity;
+pub mod metrics;
+pub mod msgpack;
+pub mod protobuf;
+pub mod reader;
+pub mod recorder;
 pub mod replay;
+pub mod schema;
+pub mod sketch;
+pub mod sparkplug;
 pub mod stats;
+pub mod stats_config;
 
+pub use alert::*;
+pub use cbor::*;
 pub use config::*;
+pub use crypto::*;
+pub use decode::*;
+pub use error::*;
 pub use message::*;
 pub use file_manager::*;
+pub use heartbeat::*;
+pub use index::*;
+pub use integrity::*;
+pub use metrics::*;
+pub use msgpack::*;
+pub use protobuf::*;
+pub use reader::*;
+pub use recorder::*;
 pub use replay::*;
+pub use schema::*;
+pub use sketch::*;
+pub use sparkplug::*;
 pub use stats::*;
+pub use stats_config::*;
@@ -1,14 +1,13 @@
 use log::*;
-use rumqttc::{ConnectionError, TlsConfiguration, Transport};
-use rumqttc::{Event, EventLoop, Incoming, MqttOptions, Publish, QoS, Request, Subscribe};
+use rumqttc::{TlsConfiguration, Transport};
+use rumqttc::{Event, EventLoop, Incoming, MqttOptions, Publish, QoS};
 use simple_logger::SimpleLogger;
 use std::{
     fs,
-    io::{self, BufRead, Read, Write},
+    io::{BufRead, Write},
     time::SystemTime,
 };
 use structopt::StructOpt;
-use chrono::{Local, Timelike};
 
 // 内部モジュールをインポート
 use mqtt_recorder_rs::*;
@@ -34,16 +33,13 @@ async fn main() {
         3 => {
             let _e = SimpleLogger::new().with_level(LevelFilter::Trace).init();
         }
-        0 | _ => {}
+        _ => {}
     }
 
     let mut mqttoptions = MqttOptions::new(servername, &opt.address, opt.port);
 
     if let Some(cafile) = opt.cafile {
-        let mut file = fs::OpenOptions::new();
-        let mut file = file.read(true).create_new(false).open(&cafile).unwrap();
-        let mut vec = Vec::new();
-        let _ = file.read_to_end(&mut vec).unwrap();
+        let vec = fs::read(&cafile).unwrap_or_else(|e| exit_with(RecorderError::config(&cafile, e)));
 
         let tlsconfig = TlsConfiguration::Simple {
             ca: vec,
@@ -56,246 +52,434 @@ async fn main() {
     }
 
     mqttoptions.set_keep_alive(5);
-    let mut eventloop = EventLoop::new(mqttoptions, 20 as usize);
-    let requests_tx = eventloop.requests_tx.clone();
+
+    // Prometheus用のメトリクス（全モードで集計し、--metrics-addr で公開）
+    let metrics = Metrics::new(opt.metrics_max_topics);
+    if let Some(addr) = opt.metrics_addr {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(addr).await {
+                error!("Failed to serve metrics on {}: {:?}", addr, e);
+                std::process::exit(1);
+            }
+        });
+    }
 
     match opt.mode {
         Mode::Replay(replay) => {
+            let mut eventloop = EventLoop::new(mqttoptions, 20);
+            let requests_tx = eventloop.requests_tx.clone();
             let (stop_tx, stop_rx) = std::sync::mpsc::channel();
+            let cipher = load_cipher_option(&replay.key_file);
+
+            let mut replayer = Replayer::new(ReplaySource::Directory(replay.directory.clone()));
+            replayer.set_speed(replay.speed);
+            replayer.set_time_range(replay.start_time.clone(), replay.end_time.clone());
+            replayer.set_topics(replay.topic.clone());
+            replayer.set_loop(replay.loop_replay);
+            replayer.set_cipher(cipher);
+            replayer.set_metrics(metrics.clone());
+            if replay.interactive {
+                let controller = replayer.controller(false);
+                tokio::spawn(read_replay_commands(controller));
+            }
+            if replay.progress_interval > 0 || replay.status_file.is_some() {
+                tokio::spawn(report_replay_progress(replayer.monitor(), replay.progress_interval, replay.status_file.clone()));
+            }
 
             // Sends the recorded messages
             tokio::spawn(async move {
-                loop {
-                    let mut previous = -1.0;
-                    
-                    // ディレクトリから再生対象のファイルリストを取得
-                    let files = match get_files_in_range(&replay.directory, replay.start_time.clone(), replay.end_time.clone()) {
-                        Ok(files) => files,
-                        Err(e) => {
-                            error!("Failed to get files in range: {:?}", e);
-                            break;
-                        }
-                    };
-                    
-                    if files.is_empty() {
-                        warn!("No files found in the specified directory or time range");
-                        break;
-                    }
-                    
-                    info!("Found {} files to replay", files.len());
-                    
-                    for file_path in files {
-                        debug!("Processing file: {:?}", file_path);
-                        let file = match fs::OpenOptions::new()
-                            .read(true)
-                            .create_new(false)
-                            .open(&file_path) {
-                            Ok(file) => file,
-                            Err(e) => {
-                                error!("Failed to open file {:?}: {:?}", file_path, e);
-                                continue;
-                            }
-                        };
-                        
-                        for line in io::BufReader::new(&file).lines() {
-                            if let Ok(line) = line {
-                                let msg = serde_json::from_str::<MqttMessage>(&line);
-                                if let Ok(msg) = msg {
-                                    if previous < 0.0 {
-                                        previous = msg.time;
-                                    }
-
-                                    tokio::time::sleep(std::time::Duration::from_millis(
-                                        ((msg.time - previous) * 1000.0 / replay.speed) as u64,
-                                    ))
-                                    .await;
-
-                                    previous = msg.time;
-
-                                    let qos = match msg.qos {
-                                        0 => QoS::AtMostOnce,
-                                        1 => QoS::AtLeastOnce,
-                                        2 => QoS::ExactlyOnce,
-                                        _ => QoS::AtMostOnce,
-                                    };
-                                    let publish = Publish::new(
-                                        msg.topic,
-                                        qos,
-                                        base64::decode(msg.msg_b64).unwrap(),
-                                    );
-                                    let _e = requests_tx.send(publish.into()).await;
+                let result = replayer
+                    .run(|msg| {
+                        let requests_tx = requests_tx.clone();
+                        async move {
+                            let qos = match msg.qos {
+                                0 => QoS::AtMostOnce,
+                                1 => QoS::AtLeastOnce,
+                                2 => QoS::ExactlyOnce,
+                                _ => QoS::AtMostOnce,
+                            };
+                            let payload = match base64::decode(&msg.msg_b64) {
+                                Ok(payload) => payload,
+                                Err(e) => {
+                                    warn!("Failed to decode payload on '{}': {:?}", msg.topic, e);
+                                    return true;
                                 }
-                            }
+                            };
+                            let publish = Publish::new(msg.topic, qos, payload);
+                            let _e = requests_tx.send(publish.into()).await;
+                            true
                         }
-                    }
-
-                    if !replay.loop_replay {
+                    })
+                    .await;
+                match result {
+                    Ok(()) => {
                         let _e = stop_tx.send(());
-                        break;
                     }
+                    Err(e) => exit_with(e),
                 }
             });
 
             // run the eventloop forever
             while let Err(std::sync::mpsc::TryRecvError::Empty) = stop_rx.try_recv() {
-                let _res = eventloop.poll().await.unwrap();
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Incoming::ConnAck(_))) => metrics.connected(),
+                    Ok(_) => {}
+                    // 再生中のメッセージが失われるため、接続エラーで終了
+                    Err(e) => {
+                        metrics.connection_error();
+                        exit_with(RecorderError::Connection(e));
+                    }
+                }
             }
         }
-        // Enter recording mode and open file writeable
-        Mode::Record(record) => {
-            // 最初のファイルパスを生成
-            let mut current_file_path = get_current_file_path(&record.directory);
-            let mut current_minute = Local::now().minute();
+        // Verify recorded files without connecting to the broker
+        Mode::Verify(verify) => {
+            let key = verify.public_key.as_ref().map(|path| {
+                load_verifying_key(path).unwrap_or_else(|e| exit_with(RecorderError::config(path, e)))
+            });
             
-            // ディレクトリを作成
-            if let Some(parent) = current_file_path.parent() {
-                fs::create_dir_all(parent).unwrap();
-            }
+            let cipher = load_cipher_option(&verify.key_file);
             
-            let mut file = fs::OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&current_file_path)
-                .unwrap();
+            let files = get_files_in_range(&verify.directory, None, None).unwrap_or_else(|e| exit_with(e));
             
-            info!("Recording to: {:?}", current_file_path);
-
-            loop {
-                let res = eventloop.poll().await;
-
-                match res {
-                    Ok(Event::Incoming(Incoming::Publish(publish))) => {
-                        // 分が変わったかチェック（ファイル分割のため）
-                        let now = Local::now();
-                        if now.minute() != current_minute {
-                            // 新しいファイルに切り替え
-                            drop(file); // 古いファイルを閉じる
-                            
-                            current_file_path = get_current_file_path(&record.directory);
-                            current_minute = now.minute();
-                            
-                            // 新しいディレクトリを作成
-                            if let Some(parent) = current_file_path.parent() {
-                                fs::create_dir_all(parent).unwrap();
-                            }
-                            
-                            file = fs::OpenOptions::new()
-                                .write(true)
-                                .create_new(true)
-                                .open(&current_file_path)
-                                .unwrap();
-                            
-                            info!("Switched to new file: {:?}", current_file_path);
-                        }
-                        
-                        let qos = match publish.qos {
-                            QoS::AtMostOnce => 0,
-                            QoS::AtLeastOnce => 1,
-                            QoS::ExactlyOnce => 2,
-                        };
-
-                        let msg = MqttMessage {
-                            time: SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs_f64(),
-                            retain: publish.retain,
-                            topic: publish.topic.clone(),
-                            msg_b64: base64::encode(&*publish.payload),
-                            qos,
-                        };
-
-                        let serialized = serde_json::to_string(&msg).unwrap();
-                        writeln!(file, "{}", serialized).unwrap();
-
-                        debug!("{:?}", publish);
+            let mut failed = 0;
+            for file_path in &files {
+                match verify_file(file_path, key.as_ref(), cipher.as_ref(), verify.require_chain) {
+                    Ok(report) if report.is_ok() && report.encrypted => {
+                        info!("OK {:?} (encrypted, pass --key-file to check the hash chain)", report.path);
                     }
-                    Ok(Event::Incoming(Incoming::ConnAck(_connect))) => {
-                        info!("Connected to: {}:{}", opt.address, opt.port);
-
-                        for topic in &record.topic {
-                            let subscription = Subscribe::new(topic, QoS::AtLeastOnce);
-                            let _ = requests_tx.send(Request::Subscribe(subscription)).await;
+                    Ok(report) if report.is_ok() => {
+                        info!("OK {:?} ({} messages, chained: {})", report.path, report.messages, report.chained);
+                    }
+                    Ok(report) => {
+                        failed += 1;
+                        for issue in &report.issues {
+                            error!("FAILED {:?}: {}", report.path, issue);
                         }
                     }
                     Err(e) => {
-                        error!("{:?}", e);
-                        if let ConnectionError::Network(_e) = e {
-                            break;
-                        }
+                        failed += 1;
+                        error!("FAILED {:?}: {:?}", file_path, e);
+                    }
+                }
+            }
+            
+            // マニフェストに記録されているが削除されたファイル
+            match find_missing_files(&verify.directory) {
+                Ok(missing) => {
+                    for path in missing {
+                        failed += 1;
+                        error!("FAILED {:?}: file listed in manifest is missing", path);
+                    }
+                }
+                Err(e) => {
+                    failed += 1;
+                    error!("Failed to read manifests: {:?}", e);
+                }
+            }
+            
+            info!("Verified {} files, {} failed", files.len(), failed);
+            if failed > 0 {
+                std::process::exit(EXIT_INVALID_DATA);
+            }
+        }
+        Mode::Keygen(keygen) if keygen.encryption => {
+            match generate_encryption_key(&keygen.output) {
+                Ok(()) => info!("Wrote encryption key to {:?}", keygen.output),
+                Err(e) => exit_with(RecorderError::io(&keygen.output, e)),
+            }
+        }
+        Mode::Keygen(keygen) => {
+            match generate_key_pair(&keygen.output) {
+                Ok(public_path) => {
+                    info!("Wrote private key to {:?} and public key to {:?}", keygen.output, public_path);
+                }
+                Err(e) => exit_with(RecorderError::io(&keygen.output, e)),
+            }
+        }
+        Mode::Decrypt(decrypt) => {
+            let cipher = load_cipher_option(&Some(decrypt.key_file));
+            let input = &decrypt.input;
+            let file = fs::File::open(input).unwrap_or_else(|e| exit_with(RecorderError::io(input, e)));
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            for (number, line) in std::io::BufReader::new(file).lines().map_while(Result::ok).enumerate() {
+                match open_line(&line, cipher.as_ref(), input, number as u64 + 1) {
+                    Ok(plain) => {
+                        let _ = writeln!(out, "{}", plain);
+                    }
+                    Err(e) => {
+                        error!("Failed to decrypt line {} of {:?}: {}", number + 1, decrypt.input, e);
+                        std::process::exit(EXIT_INVALID_DATA);
                     }
-                    _ => {}
                 }
             }
         }
+        Mode::Stats(recompute) => {
+            let cipher = load_cipher_option(&recompute.key_file);
+            let files = get_files_in_range(&recompute.directory, recompute.start_time.clone(), recompute.end_time.clone())
+                .unwrap_or_else(|e| exit_with(e));
+            info!("Recomputing stats from {} files", files.len());
+
+            let mut stats_manager = StatsManager::new(recompute.output.clone(), true, recompute.stats.stats_interval);
+            stats_manager.set_config(load_stats_config(&recompute.stats));
+            stats_manager.set_payload_decoder(load_payload_decoder(&recompute.stats.decode));
+            stats_manager.set_format(recompute.stats.stats_format);
+            stats_manager.set_emit_empty(recompute.stats.stats_empty_windows);
+            stats_manager.set_schema_drift(recompute.stats.schema_drift, None);
+            stats_manager.set_cipher(cipher.clone());
+            stats_manager.set_overwrite(true);
+
+            let start_ts = recompute.start_time.as_deref().and_then(parse_replay_time);
+            let end_ts = recompute.end_time.as_deref().and_then(parse_replay_time);
+            // 記録ファイルと同じ順序でマージし、時間範囲外のメッセージで終了
+            let messages = MergedRecords::new(files, start_ts, cipher)
+                .take_while(|msg| end_ts.is_none_or(|end| msg.time <= end));
+            let count = stats_manager.add_recorded_messages(messages).unwrap_or_else(|e| exit_with(e));
+            info!("Wrote stats for {} messages to {:?}", count, recompute.output);
+        }
+        Mode::Dump(dump) => {
+            let cipher = load_cipher_option(&dump.key_file);
+            let files = get_files_in_range(&dump.directory, dump.start_time.clone(), dump.end_time.clone())
+                .unwrap_or_else(|e| exit_with(e));
+
+            let start_ts = dump.start_time.as_deref().and_then(parse_replay_time);
+            let end_ts = dump.end_time.as_deref().and_then(parse_replay_time);
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            if dump.format == DumpFormat::Csv {
+                let _ = writeln!(out, "{}", DUMP_CSV_HEADER);
+            }
+            // Sparkplug B のエイリアスを解決するため、フィルタに一致しないメッセージも解析する
+            let mut decoder = load_payload_decoder(&dump.decode);
+            for msg in MergedRecords::new(files, start_ts, cipher).take_while(|msg| end_ts.is_none_or(|end| msg.time <= end)) {
+                let payload = match base64::decode(&msg.msg_b64) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("Failed to decode payload on '{}': {:?}", msg.topic, e);
+                        continue;
+                    }
+                };
+                let decoded = decoder.decode(&msg.topic, &payload);
+                if !dump.topic.iter().any(|filter| topic_matches_filter(filter, &msg.topic)) {
+                    continue;
+                }
+                for line in dump_lines(&msg, &payload, &decoded, dump.format) {
+                    if writeln!(out, "{}", line).is_err() {
+                        // 出力先が閉じられた（パイプなど）
+                        return;
+                    }
+                }
+            }
+        }
+        // Enter recording mode and open file writeable
+        Mode::Record(record) => {
+            let mut sink = MinuteFileManager::new(
+                record.directory.clone(),
+                record.enable_stats,
+                record.stats.stats_interval,
+            );
+            sink.set_hash_chain(record.hash_chain);
+            sink.set_signing_key(load_signing_key_option(&record.sign_key));
+            sink.set_cipher(load_cipher_option(&record.key_file));
+            sink.set_payload_decoder(load_payload_decoder(&record.stats.decode));
+            sink.set_stats_format(record.stats.stats_format);
+            sink.set_stats_empty_windows(record.stats.stats_empty_windows);
+            sink.set_schema_drift(record.stats.schema_drift, record.stats.schema_alert_topic.clone());
+            sink.set_alert_topic(record.stats.alert_topic.clone());
+            sink.set_stats_publish_prefix(record.stats.stats_publish_prefix.clone());
+            let stats_config = load_stats_config(&record.stats);
+
+            run_recorder(mqttoptions, sink, stats_config, &record.topic, &record.heartbeat, &metrics).await;
+        }
         // Enter intelligent recording mode
         Mode::IntelligentRecord(irecord) => {
-            let mut file_manager = TopicFileManager::new(
+            let mut sink = TopicFileManager::new(
                 irecord.directory.clone(), 
                 irecord.sec, 
                 irecord.enable_stats, 
-                irecord.stats_interval
+                irecord.stats.stats_interval
             );
-            let cleanup_interval = tokio::time::Duration::from_secs(irecord.sec / 2); // クリーンアップは半分の間隔で実行
-            let mut cleanup_timer = tokio::time::interval(cleanup_interval);
-            
-            loop {
-                tokio::select! {
-                    // メッセージ処理
-                    res = eventloop.poll() => {
-                        match res {
-                            Ok(Event::Incoming(Incoming::Publish(publish))) => {
-                                let topic = &publish.topic;
-                                
-                                let qos = match publish.qos {
-                                    QoS::AtMostOnce => 0,
-                                    QoS::AtLeastOnce => 1,
-                                    QoS::ExactlyOnce => 2,
-                                };
-
-                                let msg = MqttMessage {
-                                    time: SystemTime::now()
-                                        .duration_since(SystemTime::UNIX_EPOCH)
-                                        .unwrap()
-                                        .as_secs_f64(),
-                                    retain: publish.retain,
-                                    topic: publish.topic.clone(),
-                                    msg_b64: base64::encode(&*publish.payload),
-                                    qos,
-                                };
-
-                                let serialized = serde_json::to_string(&msg).unwrap();
-                                
-                                // 新しい write_message メソッドを使用（統計分析も含む）
-                                if let Err(e) = file_manager.write_message(topic, &serialized) {
-                                    error!("Failed to write message for topic '{}': {:?}", topic, e);
-                                }
+            sink.set_hash_chain(irecord.hash_chain);
+            sink.set_signing_key(load_signing_key_option(&irecord.sign_key));
+            sink.set_cipher(load_cipher_option(&irecord.key_file));
+            sink.set_resume(irecord.resume);
+            sink.set_payload_decoder(load_payload_decoder(&irecord.stats.decode));
+            sink.set_stats_format(irecord.stats.stats_format);
+            sink.set_stats_empty_windows(irecord.stats.stats_empty_windows);
+            sink.set_schema_drift(irecord.stats.schema_drift, irecord.stats.schema_alert_topic.clone());
+            sink.set_alert_topic(irecord.stats.alert_topic.clone());
+            sink.set_stats_publish_prefix(irecord.stats.stats_publish_prefix.clone());
+            let stats_config = load_stats_config(&irecord.stats);
 
-                                debug!("{:?}", publish);
-                            }
-                            Ok(Event::Incoming(Incoming::ConnAck(_connect))) => {
-                                info!("Connected to: {}:{}", opt.address, opt.port);
+            run_recorder(mqttoptions, sink, stats_config, &irecord.topic, &irecord.heartbeat, &metrics).await;
+        }
+    }
+}
 
-                                for topic in &irecord.topic {
-                                    let subscription = Subscribe::new(topic, QoS::AtLeastOnce);
-                                    let _ = requests_tx.send(Request::Subscribe(subscription)).await;
-                                }
-                            }
-                            Err(e) => {
-                                error!("{:?}", e);
-                                if let ConnectionError::Network(_e) = e {
-                                    break;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    // 定期的なファイルクリーンアップ
-                    _ = cleanup_timer.tick() => {
-                        file_manager.cleanup_timeout_files();
-                    }
-                }
+/// ヘルパー関数：記録を実行（Ctrl+Cでファイルを閉じて終了、接続エラーの場合は終了コード付きで終了）
+async fn run_recorder<S: RecordSink + 'static>(
+    mqttoptions: MqttOptions,
+    sink: S,
+    stats_config: StatsConfig,
+    topics: &[String],
+    heartbeat: &HeartbeatOptions,
+    metrics: &Metrics,
+) {
+    let mut builder = Recorder::builder(mqttoptions, sink)
+        .topics(topics.iter().cloned())
+        .stats_config(stats_config)
+        .metrics(metrics.clone());
+    if let Some(topic) = &heartbeat.heartbeat_topic {
+        builder = builder.heartbeat(topic.clone(), heartbeat.heartbeat_interval);
+    }
+    let recorder = builder.build();
+
+    let handle = recorder.handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Interrupted, closing files");
+            handle.shutdown();
+        }
+    });
+
+    if let Err(e) = recorder.run().await {
+        exit_with(e);
+    }
+}
+
+/// ヘルパー関数：エラーを出力し、エラーの種類に応じた終了コードで終了
+fn exit_with(e: RecorderError) -> ! {
+    error!("{}", e);
+    std::process::exit(e.exit_code());
+}
+
+/// ヘルパー関数：標準入力から再生の操作を読み込む（入力が終わると操作なしで再生を続ける）
+async fn read_replay_commands(controller: ReplayController) {
+    use tokio::io::AsyncBufReadExt;
+
+    println!("Replay controls: type \"help\" for commands, Enter to pause or resume");
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Err(e) = run_replay_command(&controller, line.trim()) {
+            println!("{}", e);
+        }
+    }
+}
+
+/// ヘルパー関数：再生の操作を1行実行
+fn run_replay_command(controller: &ReplayController, line: &str) -> Result<(), String> {
+    let (command, arg) = match line.split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+    match command {
+        "" if controller.status().paused => controller.resume(),
+        "" | "p" | "pause" => controller.pause(),
+        "r" | "resume" => controller.resume(),
+        "s" | "step" => {
+            if !controller.status().paused {
+                return Err("step is only available while paused".to_string());
             }
+            controller.step();
+        }
+        "seek" => {
+            // 相対位置（+30、-10 秒）または時刻（YYYY-MM-DD HH:MM[:SS]）
+            let target = if arg.starts_with('+') || arg.starts_with('-') {
+                let offset: f64 = arg.parse().map_err(|_| format!("invalid offset '{}'", arg))?;
+                let position = controller.status().position.ok_or("no position yet, seek to a time instead")?;
+                position + offset
+            } else {
+                parse_replay_time(arg).ok_or_else(|| format!("invalid time '{}', expected YYYY-MM-DD HH:MM[:SS]", arg))?
+            };
+            controller.seek(target);
         }
+        "speed" => match arg.parse::<f64>() {
+            Ok(speed) if is_valid_speed(speed) => controller.set_speed(speed),
+            _ => return Err(format!("invalid speed '{}'", arg)),
+        },
+        "status" | "?" => println!("{}", controller.status()),
+        "h" | "help" => println!(
+            "Commands: pause (p), resume (r), Enter (toggle pause), step (s), \
+             seek <YYYY-MM-DD HH:MM[:SS]|+secs|-secs>, speed <factor>, status (?)"
+        ),
+        _ => return Err(format!("unknown command '{}', type \"help\"", command)),
+    }
+    Ok(())
+}
+
+/// ヘルパー関数：再生の進捗を一定間隔でログに出力し、状態ファイルを毎秒更新
+async fn report_replay_progress(monitor: ReplayMonitor, interval_secs: u64, status_file: Option<std::path::PathBuf>) {
+    let mut timer = tokio::time::interval(tokio::time::Duration::from_secs(1));
+    let mut ticks = 0u64;
+    loop {
+        timer.tick().await;
+        let status = monitor.status();
+        if interval_secs > 0 && ticks > 0 && ticks.is_multiple_of(interval_secs) {
+            info!("Replay progress: {}", status);
+        }
+        if let Some(path) = &status_file {
+            if let Err(e) = write_status_file(path, &status) {
+                warn!("Failed to write status file {:?}: {}", path, e);
+            }
+        }
+        ticks += 1;
+    }
+}
+
+/// ヘルパー関数：状態をJSONで書き込み（読み込み側が途中の内容を読まないよう一時ファイルから置き換え）
+fn write_status_file(path: &std::path::Path, status: &ReplayStatus) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec(status)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// ヘルパー関数：署名用の秘密鍵を読み込み（読み込めない場合は終了）
+fn load_signing_key_option(path: &Option<std::path::PathBuf>) -> Option<ed25519_dalek::SigningKey> {
+    path.as_ref().map(|path| {
+        load_signing_key(path).unwrap_or_else(|e| exit_with(RecorderError::config(path, e)))
+    })
+}
+
+/// ヘルパー関数：暗号化キーを読み込み（読み込めない場合は終了）
+fn load_cipher_option(path: &Option<std::path::PathBuf>) -> Option<RecordCipher> {
+    path.as_ref().map(|path| {
+        RecordCipher::from_key_file(path).unwrap_or_else(|e| exit_with(RecorderError::config(path, e)))
+    })
+}
+
+/// ヘルパー関数：ペイロードの解析方法を作成（Protocol Buffersの設定を読み込めない場合は終了）
+fn load_payload_decoder(options: &DecodeOptions) -> PayloadDecoder {
+    let mut decoder = PayloadDecoder::new();
+    decoder.set_cbor_topics(options.cbor_topic.clone());
+    decoder.set_msgpack_topics(options.msgpack_topic.clone());
+    decoder.set_sniff_binary(!options.no_payload_sniffing);
+    if let Some(path) = &options.protobuf_config {
+        let protobuf = ProtobufDecoder::load(path).unwrap_or_else(|e| exit_with(RecorderError::config(path, e)));
+        decoder.set_protobuf(Some(protobuf));
+    }
+    decoder
+}
+
+/// ヘルパー関数：統計設定を読み込み（コマンドラインの統計量、配列の扱い、キーパスのグロブは設定ファイルの全体の設定を上書き）
+fn load_stats_config(options: &StatsOptions) -> StatsConfig {
+    let mut config = match &options.stats_config {
+        Some(path) => StatsConfig::load(path).unwrap_or_else(|e| exit_with(RecorderError::config(path, e))),
+        None => StatsConfig::default(),
+    };
+    if let Some(metrics) = &options.stats_metrics {
+        config.metrics = metrics.clone();
+    }
+    if let Some(arrays) = options.stats_arrays {
+        config.arrays = arrays;
+    }
+    if let Some(include) = &options.stats_include {
+        config.include = include.clone();
+    }
+    if let Some(exclude) = &options.stats_exclude {
+        config.exclude = exclude.clone();
     }
+    config
 }
@@ -1,10 +1,145 @@
+use std::time::{Instant, SystemTime};
+use rumqttc::{Publish, QoS};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MqttMessage {
     pub time: f64,
     pub qos: u8,
     pub retain: bool,
     pub topic: String,
     pub msg_b64: String,
+    /// レコーダーごとの単調増加するシーケンス番号
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// モノトニッククロックによる時刻（起動時の壁時計時刻 + 経過時間、NTP補正の影響を受けない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mono: Option<f64>,
+    /// レコーダーの起動ごとの識別子（`seq` と `mono` はこの範囲でのみ比較できる）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<u32>,
+    /// ハッシュチェーンの値（`--hash-chain` 有効時のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+}
+
+impl MqttMessage {
+    /// 受信したPublishからメッセージを作成（受信時刻とシーケンス番号を付与）
+    pub fn from_publish(publish: &Publish, clock: &mut MessageClock) -> Self {
+        let qos = match publish.qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        };
+        let (time, seq, mono) = clock.stamp();
+
+        MqttMessage {
+            time,
+            retain: publish.retain,
+            topic: publish.topic.clone(),
+            msg_b64: base64::encode(&*publish.payload),
+            qos,
+            seq: Some(seq),
+            mono: Some(mono),
+            run: Some(clock.run),
+            chain: None,
+        }
+    }
+
+    /// 同じ起動で記録されたメッセージの順序キー（モノトニック時刻、なければ壁時計時刻、同時刻はシーケンス番号順）
+    /// 起動をまたいで比較する場合は `MergedRecords` のように壁時計時刻に合わせる
+    pub fn order_key(&self) -> (f64, u64) {
+        (self.mono.unwrap_or(self.time), self.seq.unwrap_or(0))
+    }
+
+    /// 再生の間隔の計算に使う時刻
+    pub fn message_time(&self) -> MessageTime {
+        MessageTime {
+            time: self.time,
+            mono: self.mono,
+            run: self.run,
+        }
+    }
+}
+
+/// メッセージの記録時刻（壁時計時刻、モノトニック時刻と起動の識別子）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageTime {
+    pub time: f64,
+    pub mono: Option<f64>,
+    pub run: Option<u32>,
+}
+
+impl MessageTime {
+    /// 以前のメッセージからの経過秒数（同じ起動の間はモノトニック時刻、起動をまたぐ場合は壁時計時刻）
+    pub fn seconds_since(&self, earlier: &MessageTime) -> f64 {
+        match (self.mono, earlier.mono) {
+            (Some(mono), Some(earlier_mono)) if self.run == earlier.run => mono - earlier_mono,
+            _ => self.time - earlier.time,
+        }
+    }
+}
+
+/// 受信時刻とシーケンス番号を発行するレコーダー単位の時計
+pub struct MessageClock {
+    run: u32,
+    start_wall: f64,
+    start: Instant,
+    next_seq: u64,
+}
+
+impl MessageClock {
+    pub fn new() -> Self {
+        // 起動の識別子は乱数（取得できない場合は起動時刻のナノ秒）
+        let mut run = [0u8; 4];
+        let run = match getrandom::getrandom(&mut run) {
+            Ok(()) => u32::from_le_bytes(run),
+            Err(_) => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos(),
+        };
+        MessageClock {
+            run,
+            start_wall: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            start: Instant::now(),
+            next_seq: 0,
+        }
+    }
+
+    /// (壁時計時刻, シーケンス番号, モノトニック時刻) を発行
+    pub fn stamp(&mut self) -> (f64, u64, f64) {
+        let wall = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mono = self.start_wall + self.start.elapsed().as_secs_f64();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        (wall, seq, mono)
+    }
+}
+
+impl Default for MessageClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ヘルパー関数：トピックがMQTTのトピックフィルタ（`+` と `#` のワイルドカード）に一致するかチェック
+pub fn topic_matches_filter(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
 }
@@ -0,0 +1,370 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use log::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// トピックのラベル数の上限を超えたトピックをまとめるラベル
+pub const OTHER_TOPIC_LABEL: &str = "__other__";
+
+/// トピックのラベル数のデフォルトの上限
+pub const DEFAULT_MAX_TOPIC_LABELS: usize = 100;
+
+/// `/metrics` のリクエストの読み込みと応答のタイムアウト
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 接続の受け付けに失敗した後の待ち時間（ファイルディスクリプタ不足などで空回りしないため）
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// トピックごとのカウンタ
+#[derive(Debug, Clone, Copy, Default)]
+struct TopicCounters {
+    received: u64,
+    written: u64,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    max_topics: usize,
+    topics: BTreeMap<String, TopicCounters>,
+    other: TopicCounters, // 上限を超えたトピックの合計
+    bytes_written: u64,
+    write_errors: u64,
+    open_files: usize,
+    connected: bool,
+    connections: u64,
+    connection_errors: u64,
+    stats_windows: u64,
+    replay_messages: u64,
+    replay_position: Option<f64>,
+    replay_loops: u64,
+}
+
+impl MetricsState {
+    /// トピックのカウンタ（上限を超えた新しいトピックは `__other__` にまとめる）
+    fn topic(&mut self, topic: &str) -> &mut TopicCounters {
+        if !self.topics.contains_key(topic) && self.topics.len() >= self.max_topics {
+            return &mut self.other;
+        }
+        self.topics.entry(topic.to_string()).or_default()
+    }
+}
+
+/// カウンタの現在の値（トピックごとのカウンタは合計）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub messages_received: u64,
+    pub messages_written: u64,
+    pub bytes_written: u64,
+    pub write_errors: u64,
+    pub open_files: usize,
+    pub connected: bool,
+    pub connections: u64,
+    pub connection_errors: u64,
+    pub stats_windows: u64,
+}
+
+/// レコーダーのPrometheus用メトリクス（複製したハンドルは同じ値を共有）
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Metrics {
+    pub fn new(max_topics: usize) -> Self {
+        Metrics {
+            state: Arc::new(Mutex::new(MetricsState {
+                max_topics,
+                ..MetricsState::default()
+            })),
+        }
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut MetricsState) -> T) -> T {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+
+    /// 受信したメッセージを記録
+    pub fn message_received(&self, topic: &str) {
+        self.with(|state| state.topic(topic).received += 1);
+    }
+
+    /// 書き込んだメッセージを記録（バイト数は暗号化後の行の長さ）
+    pub fn message_written(&self, topic: &str, bytes: usize) {
+        self.with(|state| {
+            state.topic(topic).written += 1;
+            state.bytes_written += bytes as u64;
+        });
+    }
+
+    pub fn write_error(&self) {
+        self.with(|state| state.write_errors += 1);
+    }
+
+    pub fn set_open_files(&self, open_files: usize) {
+        self.with(|state| state.open_files = open_files);
+    }
+
+    /// ブローカーへの接続（ConnAck）を記録
+    pub fn connected(&self) {
+        self.with(|state| {
+            state.connected = true;
+            state.connections += 1;
+        });
+    }
+
+    /// 接続エラーを記録
+    pub fn connection_error(&self) {
+        self.with(|state| {
+            state.connected = false;
+            state.connection_errors += 1;
+        });
+    }
+
+    pub fn stats_windows_emitted(&self, windows: usize) {
+        self.with(|state| state.stats_windows += windows as u64);
+    }
+
+    /// 再生したメッセージと再生位置（記録時刻、UNIX秒）を記録
+    pub fn replay_published(&self, time: f64) {
+        self.with(|state| {
+            state.replay_messages += 1;
+            state.replay_position = Some(time);
+        });
+    }
+
+    /// 再生の周回を記録（`--loop`）
+    pub fn replay_loop_started(&self) {
+        self.with(|state| state.replay_loops += 1);
+    }
+
+    /// カウンタの現在の値を取得
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.with(|state| {
+            let topics = state.topics.values().chain(std::iter::once(&state.other));
+            let (messages_received, messages_written) = topics.fold((0, 0), |(received, written), counters| {
+                (received + counters.received, written + counters.written)
+            });
+            MetricsSnapshot {
+                messages_received,
+                messages_written,
+                bytes_written: state.bytes_written,
+                write_errors: state.write_errors,
+                open_files: state.open_files,
+                connected: state.connected,
+                connections: state.connections,
+                connection_errors: state.connection_errors,
+                stats_windows: state.stats_windows,
+            }
+        })
+    }
+
+    /// Prometheusのテキスト形式で出力
+    pub fn render(&self) -> String {
+        self.with(|state| {
+            let mut out = String::new();
+            let mut topics: Vec<(&str, &TopicCounters)> =
+                state.topics.iter().map(|(topic, counters)| (topic.as_str(), counters)).collect();
+            if state.topics.len() >= state.max_topics {
+                topics.push((OTHER_TOPIC_LABEL, &state.other));
+            }
+
+            write_header(&mut out, "mqtt_recorder_messages_received_total", "counter", "Messages received per topic");
+            for (topic, counters) in &topics {
+                let _ = writeln!(out, "mqtt_recorder_messages_received_total{{topic=\"{}\"}} {}", escape_label(topic), counters.received);
+            }
+            write_header(&mut out, "mqtt_recorder_messages_written_total", "counter", "Messages written to recording files per topic");
+            for (topic, counters) in &topics {
+                let _ = writeln!(out, "mqtt_recorder_messages_written_total{{topic=\"{}\"}} {}", escape_label(topic), counters.written);
+            }
+            write_metric(&mut out, "mqtt_recorder_bytes_written_total", "counter", "Bytes written to recording files", state.bytes_written as f64);
+            write_metric(&mut out, "mqtt_recorder_write_errors_total", "counter", "Failed writes to recording files", state.write_errors as f64);
+            write_metric(&mut out, "mqtt_recorder_open_files", "gauge", "Recording files currently open", state.open_files as f64);
+            write_metric(&mut out, "mqtt_recorder_connected", "gauge", "1 if connected to the broker", if state.connected { 1.0 } else { 0.0 });
+            write_metric(&mut out, "mqtt_recorder_reconnects_total", "counter", "Connections to the broker after the first one", state.connections.saturating_sub(1) as f64);
+            write_metric(&mut out, "mqtt_recorder_connection_errors_total", "counter", "Errors of the broker connection", state.connection_errors as f64);
+            write_metric(&mut out, "mqtt_recorder_stats_windows_total", "counter", "Statistics windows emitted", state.stats_windows as f64);
+            write_metric(&mut out, "mqtt_recorder_replay_messages_total", "counter", "Messages published by replay", state.replay_messages as f64);
+            write_metric(&mut out, "mqtt_recorder_replay_loops_total", "counter", "Replay passes started", state.replay_loops as f64);
+            if let Some(position) = state.replay_position {
+                write_metric(&mut out, "mqtt_recorder_replay_position_seconds", "gauge", "Recorded time of the last replayed message (unix seconds)", position);
+            }
+            out
+        })
+    }
+
+    /// `/metrics` を提供するHTTPサーバーを起動（バインドに失敗した場合を除き終了しない）
+    pub async fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving metrics on http://{}/metrics", addr);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept metrics connection: {:?}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(REQUEST_TIMEOUT, metrics.respond(stream)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => debug!("Metrics request from {} failed: {:?}", peer, e),
+                    Err(_) => debug!("Metrics request from {} timed out", peer),
+                }
+            });
+        }
+    }
+
+    /// 1つのHTTPリクエストに応答（GET /metrics のみ）
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        // リクエストヘッダーの終わりまで読み込み（ボディは使用しない）
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut parts = request.lines().next().unwrap_or_default().split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        let path = path.split('?').next().unwrap_or_default();
+
+        let (status, content_type, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", self.render()),
+            ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_TOPIC_LABELS)
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    write_header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// ヘルパー関数：ラベルの値をエスケープ
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric_lines(out: &str) -> Vec<&str> {
+        out.lines().filter(|line| !line.starts_with('#')).collect()
+    }
+
+    #[test]
+    fn renders_counters_and_caps_topic_labels() {
+        let metrics = Metrics::new(2);
+        metrics.message_received("a");
+        metrics.message_received("b\"q");
+        metrics.message_received("c");
+        metrics.message_received("d");
+        metrics.message_received("a");
+        metrics.message_written("a", 10);
+        metrics.message_written("c", 5);
+        metrics.connected();
+        metrics.connection_error();
+        metrics.connected();
+        metrics.set_open_files(3);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_received, 5);
+        assert_eq!(snapshot.messages_written, 2);
+        assert_eq!(snapshot.bytes_written, 15);
+        assert!(snapshot.connected);
+        assert_eq!(snapshot.connections, 2);
+
+        let out = metrics.render();
+        let lines = metric_lines(&out);
+        for expected in &[
+            "mqtt_recorder_messages_received_total{topic=\"a\"} 2",
+            "mqtt_recorder_messages_received_total{topic=\"b\\\"q\"} 1",
+            "mqtt_recorder_messages_received_total{topic=\"__other__\"} 2",
+            "mqtt_recorder_messages_written_total{topic=\"__other__\"} 1",
+            "mqtt_recorder_bytes_written_total 15",
+            "mqtt_recorder_open_files 3",
+            "mqtt_recorder_connected 1",
+            "mqtt_recorder_reconnects_total 1",
+            "mqtt_recorder_connection_errors_total 1",
+        ] {
+            assert!(lines.contains(expected), "missing {} in\n{}", expected, out);
+        }
+        assert!(out.contains("# TYPE mqtt_recorder_open_files gauge"));
+        assert!(!out.contains("mqtt_recorder_replay_position_seconds"));
+
+        metrics.replay_published(1_753_405_200.5);
+        assert!(metric_lines(&metrics.render()).contains(&"mqtt_recorder_replay_position_seconds 1753405200.5"));
+    }
+
+    async fn request(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_while_another_client_stalls() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let metrics = Metrics::default();
+        metrics.stats_windows_emitted(4);
+        tokio::spawn(metrics.clone().serve(addr));
+
+        // 起動を待って、ヘッダーを送らない接続を開いたままにする
+        let mut stalled = None;
+        for _ in 0..50 {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    stalled = Some(stream);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        assert!(stalled.is_some(), "metrics server did not start");
+
+        let response = request(addr, "GET /metrics?x=1 HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\nmqtt_recorder_stats_windows_total 4\n"), "{}", response);
+        let response = request(addr, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+        let response = request(addr, "POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", response);
+    }
+}
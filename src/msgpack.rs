@@ -0,0 +1,206 @@
+use serde_json::{json, Map, Number, Value};
+use crate::cbor::map_key;
+
+/// 入れ子の最大の深さ（不正なデータによるスタックオーバーフローを防ぐ）
+const MAX_DEPTH: usize = 128;
+
+/// MessagePackのタイムスタンプの拡張型
+const EXT_TIMESTAMP: i8 = -1;
+
+/// MessagePackのペイロードをJSONの値に変換（データ全体が1つの値である必要がある）
+/// バイナリはBase64の文字列、タイムスタンプはUNIX秒、その他の拡張型は `{"type", "data"}` になる
+pub fn decode_msgpack(data: &[u8]) -> Result<Value, String> {
+    let mut reader = MsgpackReader { data, pos: 0 };
+    let value = reader.read_value(0)?;
+    if reader.pos != data.len() {
+        return Err(format!("{} trailing bytes after MessagePack value", data.len() - reader.pos));
+    }
+    Ok(value)
+}
+
+struct MsgpackReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MsgpackReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("truncated MessagePack value at byte {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// ビッグエンディアンの符号なし整数
+    fn read_uint(&mut self, len: usize) -> Result<u64, String> {
+        Ok(self.read_bytes(len)?.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    /// ビッグエンディアンの符号付き整数
+    fn read_int(&mut self, len: usize) -> Result<i64, String> {
+        let shift = 64 - 8 * len as u32;
+        Ok(((self.read_uint(len)? << shift) as i64) >> shift)
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("MessagePack value nested too deeply".to_string());
+        }
+        let marker = self.read_bytes(1)?[0];
+        let float = |f: f64| Number::from_f64(f).map_or(Value::Null, Value::Number);
+        Ok(match marker {
+            0x00..=0x7f => json!(marker),
+            0x80..=0x8f => self.read_map((marker & 0x0f) as usize, depth)?,
+            0x90..=0x9f => self.read_array((marker & 0x0f) as usize, depth)?,
+            0xa0..=0xbf => self.read_str((marker & 0x1f) as usize)?,
+            0xc0 => Value::Null,
+            0xc2 => Value::Bool(false),
+            0xc3 => Value::Bool(true),
+            0xc4..=0xc6 => {
+                let len = self.read_uint(1 << (marker - 0xc4))? as usize;
+                json!(base64::encode(self.read_bytes(len)?))
+            }
+            0xc7..=0xc9 => {
+                let len = self.read_uint(1 << (marker - 0xc7))? as usize;
+                self.read_ext(len)?
+            }
+            0xca => float(f32::from_bits(self.read_uint(4)? as u32) as f64),
+            0xcb => float(f64::from_bits(self.read_uint(8)?)),
+            0xcc..=0xcf => json!(self.read_uint(1 << (marker - 0xcc))?),
+            0xd0..=0xd3 => json!(self.read_int(1 << (marker - 0xd0))?),
+            0xd4..=0xd8 => self.read_ext(1 << (marker - 0xd4))?,
+            0xd9..=0xdb => {
+                let len = self.read_uint(1 << (marker - 0xd9))? as usize;
+                self.read_str(len)?
+            }
+            0xdc | 0xdd => {
+                let len = self.read_uint(if marker == 0xdc { 2 } else { 4 })? as usize;
+                self.read_array(len, depth)?
+            }
+            0xde | 0xdf => {
+                let len = self.read_uint(if marker == 0xde { 2 } else { 4 })? as usize;
+                self.read_map(len, depth)?
+            }
+            0xe0..=0xff => json!(marker as i8),
+            0xc1 => return Err("invalid MessagePack marker 0xc1".to_string()),
+        })
+    }
+
+    fn read_str(&mut self, len: usize) -> Result<Value, String> {
+        std::str::from_utf8(self.read_bytes(len)?)
+            .map(|s| Value::String(s.to_string()))
+            .map_err(|_| "invalid UTF-8 in MessagePack string".to_string())
+    }
+
+    fn read_array(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        // 要素は最低1バイトなので、残りのバイト数より長い配列は不正
+        if len > self.data.len() - self.pos {
+            return Err("truncated MessagePack array".to_string());
+        }
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(self.read_value(depth + 1)?);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn read_map(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        let mut map = Map::new();
+        for _ in 0..len {
+            let key = map_key(self.read_value(depth + 1)?);
+            let value = self.read_value(depth + 1)?;
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn read_ext(&mut self, len: usize) -> Result<Value, String> {
+        let ext_type = self.read_bytes(1)?[0] as i8;
+        let data = self.read_bytes(len)?;
+        if ext_type == EXT_TIMESTAMP {
+            let (secs, nanos) = match data.len() {
+                4 => (u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i64, 0),
+                8 => {
+                    let raw = data.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                    ((raw & 0x3_ffff_ffff) as i64, (raw >> 34) as u32)
+                }
+                12 => {
+                    let nanos = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    let secs = data[4..].iter().fold(0u64, |acc, b| (acc << 8) | *b as u64) as i64;
+                    (secs, nanos)
+                }
+                len => return Err(format!("invalid MessagePack timestamp length {}", len)),
+            };
+            return Ok(json!(secs as f64 + nanos as f64 / 1e9));
+        }
+        Ok(json!({ "type": ext_type, "data": base64::encode(data) }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Value {
+        json!({
+            "temperature": 21.5,
+            "count": 300,
+            "offset": -70000,
+            "big": u64::MAX,
+            "ok": false,
+            "missing": null,
+            "name": "sensor",
+            "readings": [1, -1, 2.25, {"nested": ["a", "b"]}],
+        })
+    }
+
+    #[test]
+    fn round_trips_json_values() {
+        let value = sample();
+        assert_eq!(decode_msgpack(&rmp_serde::to_vec(&value).unwrap()), Ok(value));
+    }
+
+    #[test]
+    fn decodes_msgpack_specific_items() {
+        // バイナリ、タイムスタンプ（32ビットと64ビット）、その他の拡張型、整数のキー
+        assert_eq!(decode_msgpack(&[0xc4, 0x03, 0x01, 0x02, 0x03]), Ok(json!("AQID")));
+        assert_eq!(decode_msgpack(&[0xd6, 0xff, 0x00, 0x00, 0x00, 0x0a]), Ok(json!(10.0)));
+        assert_eq!(
+            decode_msgpack(&[0xd7, 0xff, 0x77, 0x35, 0x94, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            Ok(json!(1.5))
+        );
+        assert_eq!(decode_msgpack(&[0xd4, 0x05, 0xab]), Ok(json!({"type": 5, "data": "qw=="})));
+        assert_eq!(decode_msgpack(&[0x81, 0x01, 0xa1, 0x61]), Ok(json!({"1": "a"})));
+        assert_eq!(decode_msgpack(&[0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0]), Ok(json!(i64::MIN)));
+        assert!(decode_msgpack(&[0xc7, 0x03, 0xff, 0x00, 0x00, 0x00]).is_err());
+        assert!(decode_msgpack(&[0xc1]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let data = rmp_serde::to_vec(&sample()).unwrap();
+        for len in 0..data.len() {
+            assert!(decode_msgpack(&data[..len]).is_err(), "prefix of {} bytes", len);
+        }
+        assert!(decode_msgpack(&[0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        for marker in [0xc6, 0xc9, 0xdb, 0xdd, 0xdf] {
+            assert!(decode_msgpack(&[marker, 0xff, 0xff, 0xff, 0xff, 0x00]).is_err(), "marker 0x{:02x}", marker);
+        }
+    }
+
+    #[test]
+    fn rejects_deeply_nested_values() {
+        let mut data = vec![0x91; 100_000];
+        data.push(0x01);
+        let err = decode_msgpack(&data).unwrap_err();
+        assert!(err.contains("nested too deeply"), "{}", err);
+    }
+}
@@ -0,0 +1,671 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use crate::message::topic_matches_filter;
+
+/// 入れ子のメッセージの最大の深さ（不正なデータによるスタックオーバーフローを防ぐ）
+const MAX_DEPTH: usize = 64;
+
+/// Protocol Buffersのワイヤー形式の値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    LengthDelimited(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> WireValue<'a> {
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            WireValue::Varint(v) | WireValue::Fixed64(v) => Some(v),
+            WireValue::Fixed32(v) => Some(v as u64),
+            WireValue::LengthDelimited(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            WireValue::LengthDelimited(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes().and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            WireValue::Fixed32(v) => Some(f32::from_bits(v)),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            WireValue::Fixed64(v) => Some(f64::from_bits(v)),
+            _ => None,
+        }
+    }
+}
+
+/// メッセージのフィールドを順に読み込む（フィールド番号と値）
+pub struct FieldReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        FieldReader { data, pos: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let (value, len) = decode_varint(&self.data[self.pos..])?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("truncated field at byte {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_field(&mut self) -> Result<(u32, WireValue<'a>), String> {
+        let key = self.read_varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(self.read_varint()?),
+            1 => {
+                let bytes = self.read_bytes(8)?;
+                WireValue::Fixed64(u64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                WireValue::LengthDelimited(self.read_bytes(len)?)
+            }
+            5 => {
+                let bytes = self.read_bytes(4)?;
+                WireValue::Fixed32(u32::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            wire_type => return Err(format!("unsupported wire type {} of field {}", wire_type, field)),
+        };
+        if field == 0 {
+            return Err("invalid field number 0".to_string());
+        }
+        Ok((field, value))
+    }
+}
+
+impl<'a> Iterator for FieldReader<'a> {
+    type Item = Result<(u32, WireValue<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let field = self.read_field();
+        if field.is_err() {
+            // エラー後は読み込みを終了
+            self.pos = self.data.len();
+        }
+        Some(field)
+    }
+}
+
+/// ヘルパー関数：varintを読み込み（値と読み込んだバイト数）
+pub fn decode_varint(data: &[u8]) -> Result<(u64, usize), String> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+    Err("truncated or overlong varint".to_string())
+}
+
+/// ヘルパー関数：packedまたは単独の繰り返しvarintフィールドの値
+pub fn repeated_varints(value: &WireValue) -> Result<Vec<u64>, String> {
+    match value {
+        WireValue::LengthDelimited(mut data) => {
+            let mut values = Vec::new();
+            while !data.is_empty() {
+                let (v, len) = decode_varint(data)?;
+                values.push(v);
+                data = &data[len..];
+            }
+            Ok(values)
+        }
+        other => Ok(other.as_u64().into_iter().collect()),
+    }
+}
+
+/// ヘルパー関数：ZigZagエンコードされた整数を復元（sint32, sint64）
+fn decode_zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+// FieldDescriptorProto.Type
+const TYPE_DOUBLE: u32 = 1;
+const TYPE_FLOAT: u32 = 2;
+const TYPE_INT64: u32 = 3;
+const TYPE_UINT64: u32 = 4;
+const TYPE_INT32: u32 = 5;
+const TYPE_FIXED64: u32 = 6;
+const TYPE_FIXED32: u32 = 7;
+const TYPE_BOOL: u32 = 8;
+const TYPE_STRING: u32 = 9;
+const TYPE_MESSAGE: u32 = 11;
+const TYPE_BYTES: u32 = 12;
+const TYPE_UINT32: u32 = 13;
+const TYPE_ENUM: u32 = 14;
+const TYPE_SFIXED32: u32 = 15;
+const TYPE_SFIXED64: u32 = 16;
+const TYPE_SINT32: u32 = 17;
+const TYPE_SINT64: u32 = 18;
+
+// FieldDescriptorProto.Label
+const LABEL_REPEATED: u32 = 3;
+
+/// メッセージのフィールドの定義
+#[derive(Debug, Clone)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub number: u32,
+    pub field_type: u32,
+    pub repeated: bool,
+    /// メッセージ型と列挙型の完全修飾名（`.package.Message`）
+    pub type_name: String,
+}
+
+/// メッセージの定義
+#[derive(Debug, Clone, Default)]
+pub struct MessageDescriptor {
+    pub name: String,
+    pub fields: Vec<FieldDescriptor>,
+    /// mapフィールドのエントリ（`key` と `value` を持つ）
+    pub map_entry: bool,
+}
+
+impl MessageDescriptor {
+    fn field(&self, number: u32) -> Option<&FieldDescriptor> {
+        self.fields.iter().find(|field| field.number == number)
+    }
+}
+
+/// `FileDescriptorSet` から読み込んだメッセージ型と列挙型
+#[derive(Debug, Clone, Default)]
+pub struct DescriptorPool {
+    messages: HashMap<String, MessageDescriptor>, // 完全修飾名（先頭の `.` なし） -> 定義
+    enums: HashMap<String, HashMap<i32, String>>, // 完全修飾名 -> 値 -> 名前
+}
+
+impl DescriptorPool {
+    /// `protoc --descriptor_set_out`（`--include_imports` 推奨）の出力を解析
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut pool = DescriptorPool::default();
+        for field in FieldReader::new(data) {
+            if let (1, WireValue::LengthDelimited(file)) = field? {
+                pool.add_file(file)?;
+            }
+        }
+        Ok(pool)
+    }
+
+    /// ファイルから読み込み
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Self::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn contains_message(&self, name: &str) -> bool {
+        self.messages.contains_key(name.trim_start_matches('.'))
+    }
+
+    fn add_file(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        for field in FieldReader::new(data) {
+            match field? {
+                (2, value) => package = value.as_str().unwrap_or_default().to_string(),
+                (4, WireValue::LengthDelimited(message)) => messages.push(message),
+                (5, WireValue::LengthDelimited(enum_type)) => enums.push(enum_type),
+                _ => {}
+            }
+        }
+        for message in messages {
+            self.add_message(&package, message)?;
+        }
+        for enum_type in enums {
+            self.add_enum(&package, enum_type)?;
+        }
+        Ok(())
+    }
+
+    fn add_message(&mut self, scope: &str, data: &[u8]) -> Result<(), String> {
+        let mut descriptor = MessageDescriptor::default();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        for field in FieldReader::new(data) {
+            match field? {
+                (1, value) => descriptor.name = value.as_str().unwrap_or_default().to_string(),
+                (2, WireValue::LengthDelimited(field)) => descriptor.fields.push(decode_field_descriptor(field)?),
+                (3, WireValue::LengthDelimited(message)) => nested.push(message),
+                (4, WireValue::LengthDelimited(enum_type)) => enums.push(enum_type),
+                (7, WireValue::LengthDelimited(options)) => {
+                    // MessageOptions.map_entry
+                    for option in FieldReader::new(options) {
+                        if let (7, value) = option? {
+                            descriptor.map_entry = value.as_u64() == Some(1);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let full_name = qualified_name(scope, &descriptor.name);
+        for message in nested {
+            self.add_message(&full_name, message)?;
+        }
+        for enum_type in enums {
+            self.add_enum(&full_name, enum_type)?;
+        }
+        self.messages.insert(full_name, descriptor);
+        Ok(())
+    }
+
+    fn add_enum(&mut self, scope: &str, data: &[u8]) -> Result<(), String> {
+        let mut name = String::new();
+        let mut values = HashMap::new();
+        for field in FieldReader::new(data) {
+            match field? {
+                (1, value) => name = value.as_str().unwrap_or_default().to_string(),
+                (2, WireValue::LengthDelimited(value)) => {
+                    let mut value_name = String::new();
+                    let mut number = 0;
+                    for field in FieldReader::new(value) {
+                        match field? {
+                            (1, v) => value_name = v.as_str().unwrap_or_default().to_string(),
+                            (2, v) => number = v.as_u64().unwrap_or_default() as i32,
+                            _ => {}
+                        }
+                    }
+                    values.insert(number, value_name);
+                }
+                _ => {}
+            }
+        }
+        self.enums.insert(qualified_name(scope, &name), values);
+        Ok(())
+    }
+
+    /// メッセージをJSONのオブジェクトに変換（フィールド名は `.proto` の名前、存在しないフィールドは出力しない）
+    pub fn decode_message(&self, message_name: &str, data: &[u8]) -> Result<Value, String> {
+        self.decode_nested(message_name, data, 0)
+    }
+
+    fn decode_nested(&self, message_name: &str, data: &[u8], depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("message '{}' nested too deeply", message_name));
+        }
+        let descriptor = self
+            .messages
+            .get(message_name.trim_start_matches('.'))
+            .ok_or_else(|| format!("unknown message type '{}'", message_name))?;
+
+        let mut object = Map::new();
+        for field in FieldReader::new(data) {
+            let (number, raw) = field?;
+            let field = match descriptor.field(number) {
+                Some(field) => field,
+                None => continue, // 未知のフィールドは無視
+            };
+            let values = self.field_values(field, &raw, depth)?;
+            if field.repeated {
+                let entry = object.entry(field.name.clone()).or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(items) = entry {
+                    items.extend(values);
+                }
+            } else if let Some(value) = values.into_iter().last() {
+                object.insert(field.name.clone(), value);
+            }
+        }
+
+        // mapフィールド（key と value のエントリの配列）はオブジェクトに変換
+        for field in &descriptor.fields {
+            let is_map = field.field_type == TYPE_MESSAGE
+                && self
+                    .messages
+                    .get(field.type_name.trim_start_matches('.'))
+                    .is_some_and(|entry| entry.map_entry);
+            if !is_map {
+                continue;
+            }
+            if let Some(Value::Array(entries)) = object.remove(&field.name) {
+                let mut map = Map::new();
+                for entry in entries {
+                    let key = match entry.get("key") {
+                        Some(Value::String(key)) => key.clone(),
+                        Some(key) => key.to_string(),
+                        None => String::new(),
+                    };
+                    map.insert(key, entry.get("value").cloned().unwrap_or(Value::Null));
+                }
+                object.insert(field.name.clone(), Value::Object(map));
+            }
+        }
+        Ok(Value::Object(object))
+    }
+
+    /// フィールドの値（packedの繰り返しフィールドは複数の値）
+    fn field_values(&self, field: &FieldDescriptor, raw: &WireValue, depth: usize) -> Result<Vec<Value>, String> {
+        let packable = !matches!(field.field_type, TYPE_STRING | TYPE_BYTES | TYPE_MESSAGE);
+        if let (true, WireValue::LengthDelimited(mut data)) = (packable, *raw) {
+            let mut values = Vec::new();
+            while !data.is_empty() {
+                let (raw, len) = match field.field_type {
+                    TYPE_DOUBLE | TYPE_FIXED64 | TYPE_SFIXED64 if data.len() >= 8 => {
+                        (WireValue::Fixed64(u64::from_le_bytes(data[..8].try_into().unwrap())), 8)
+                    }
+                    TYPE_FLOAT | TYPE_FIXED32 | TYPE_SFIXED32 if data.len() >= 4 => {
+                        (WireValue::Fixed32(u32::from_le_bytes(data[..4].try_into().unwrap())), 4)
+                    }
+                    TYPE_DOUBLE | TYPE_FIXED64 | TYPE_SFIXED64 | TYPE_FLOAT | TYPE_FIXED32 | TYPE_SFIXED32 => {
+                        return Err(format!("truncated packed field '{}'", field.name))
+                    }
+                    _ => {
+                        let (value, len) = decode_varint(data)?;
+                        (WireValue::Varint(value), len)
+                    }
+                };
+                values.push(self.field_value(field, &raw, depth)?);
+                data = &data[len..];
+            }
+            return Ok(values);
+        }
+        Ok(vec![self.field_value(field, raw, depth)?])
+    }
+
+    fn field_value(&self, field: &FieldDescriptor, raw: &WireValue, depth: usize) -> Result<Value, String> {
+        let number = raw.as_u64().unwrap_or_default();
+        Ok(match field.field_type {
+            TYPE_DOUBLE => raw.as_f64().map_or(Value::Null, |v| json!(v)),
+            TYPE_FLOAT => raw.as_f32().map_or(Value::Null, |v| json!(v)),
+            TYPE_INT64 | TYPE_SFIXED64 => json!(number as i64),
+            TYPE_INT32 | TYPE_SFIXED32 => json!(number as i32),
+            TYPE_UINT64 | TYPE_FIXED64 => json!(number),
+            TYPE_UINT32 | TYPE_FIXED32 => json!(number as u32),
+            TYPE_SINT32 | TYPE_SINT64 => json!(decode_zigzag(number)),
+            TYPE_BOOL => json!(number != 0),
+            TYPE_STRING => raw.as_str().map_or(Value::Null, |s| json!(s)),
+            TYPE_BYTES => raw.as_bytes().map_or(Value::Null, |b| json!(base64::encode(b))),
+            TYPE_ENUM => {
+                let name = self
+                    .enums
+                    .get(field.type_name.trim_start_matches('.'))
+                    .and_then(|values| values.get(&(number as i32)));
+                match name {
+                    Some(name) => json!(name),
+                    None => json!(number as i32),
+                }
+            }
+            TYPE_MESSAGE => match raw.as_bytes() {
+                Some(bytes) => self.decode_nested(&field.type_name, bytes, depth + 1)?,
+                None => Value::Null,
+            },
+            other => return Err(format!("unsupported type {} of field '{}'", other, field.name)),
+        })
+    }
+}
+
+fn decode_field_descriptor(data: &[u8]) -> Result<FieldDescriptor, String> {
+    let mut field = FieldDescriptor {
+        name: String::new(),
+        number: 0,
+        field_type: 0,
+        repeated: false,
+        type_name: String::new(),
+    };
+    for value in FieldReader::new(data) {
+        match value? {
+            (1, v) => field.name = v.as_str().unwrap_or_default().to_string(),
+            (3, v) => field.number = v.as_u64().unwrap_or_default() as u32,
+            (4, v) => field.repeated = v.as_u64() == Some(LABEL_REPEATED as u64),
+            (5, v) => field.field_type = v.as_u64().unwrap_or_default() as u32,
+            (6, v) => field.type_name = v.as_str().unwrap_or_default().to_string(),
+            _ => {}
+        }
+    }
+    Ok(field)
+}
+
+fn qualified_name(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// トピックとメッセージ型の対応
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtobufTopic {
+    /// MQTTのトピックフィルタ（`+` と `#` を使用可能）
+    pub filter: String,
+    /// メッセージ型の完全修飾名（`package.Message`）
+    pub message: String,
+}
+
+/// Protocol Buffersのペイロードの設定（`--protobuf-config` のJSONファイル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtobufConfig {
+    /// `FileDescriptorSet` のファイル（相対パスは設定ファイルのディレクトリから）
+    pub descriptor_set: PathBuf,
+    /// トピックごとのメッセージ型（最初に一致したものを使用）
+    pub topics: Vec<ProtobufTopic>,
+}
+
+/// 設定されたトピックのペイロードをメッセージ型に従って解析する
+#[derive(Debug, Clone)]
+pub struct ProtobufDecoder {
+    pool: DescriptorPool,
+    topics: Vec<ProtobufTopic>,
+}
+
+impl ProtobufDecoder {
+    pub fn new(pool: DescriptorPool, topics: Vec<ProtobufTopic>) -> Self {
+        ProtobufDecoder { pool, topics }
+    }
+
+    /// 設定ファイルと `FileDescriptorSet` を読み込み（未定義のメッセージ型はエラー）
+    pub fn load(config_path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(config_path)?;
+        let config: ProtobufConfig =
+            serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let descriptor_path = match config_path.parent() {
+            Some(dir) if config.descriptor_set.is_relative() => dir.join(&config.descriptor_set),
+            _ => config.descriptor_set.clone(),
+        };
+        let pool = DescriptorPool::load(&descriptor_path)?;
+        for topic in &config.topics {
+            if !pool.contains_message(&topic.message) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("message type '{}' is not in {:?}", topic.message, descriptor_path),
+                ));
+            }
+        }
+        Ok(Self::new(pool, config.topics))
+    }
+
+    /// トピックに対応するメッセージ型で解析（対応するメッセージ型がない場合はNone）
+    pub fn decode(&self, topic: &str, data: &[u8]) -> Option<Result<Value, String>> {
+        let mapping = self.topics.iter().find(|t| topic_matches_filter(&t.filter, topic))?;
+        Some(self.pool.decode_message(&mapping.message, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::encoding::{bytes, int32, int64, sint32, string};
+    use prost::Message;
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+        FileDescriptorSet, MessageOptions,
+    };
+
+    fn field(name: &str, number: i32, field_type: Type, label: Label, type_name: &str) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            r#type: Some(field_type as i32),
+            label: Some(label as i32),
+            type_name: if type_name.is_empty() { None } else { Some(type_name.to_string()) },
+            ..Default::default()
+        }
+    }
+
+    /// `message Node { Node child = 1; string name = 2; repeated int32 values = 3; map<string, int64> tags = 4;
+    /// sint32 delta = 5; Color color = 6; }`
+    fn pool() -> DescriptorPool {
+        let entry = DescriptorProto {
+            name: Some("TagsEntry".to_string()),
+            field: vec![
+                field("key", 1, Type::String, Label::Optional, ""),
+                field("value", 2, Type::Int64, Label::Optional, ""),
+            ],
+            options: Some(MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let node = DescriptorProto {
+            name: Some("Node".to_string()),
+            field: vec![
+                field("child", 1, Type::Message, Label::Optional, ".test.Node"),
+                field("name", 2, Type::String, Label::Optional, ""),
+                field("values", 3, Type::Int32, Label::Repeated, ""),
+                field("tags", 4, Type::Message, Label::Repeated, ".test.Node.TagsEntry"),
+                field("delta", 5, Type::Sint32, Label::Optional, ""),
+                field("color", 6, Type::Enum, Label::Optional, ".test.Color"),
+            ],
+            nested_type: vec![entry],
+            ..Default::default()
+        };
+        let color = EnumDescriptorProto {
+            name: Some("Color".to_string()),
+            value: vec![
+                EnumValueDescriptorProto {
+                    name: Some("RED".to_string()),
+                    number: Some(0),
+                    ..Default::default()
+                },
+                EnumValueDescriptorProto {
+                    name: Some("GREEN".to_string()),
+                    number: Some(1),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("test.proto".to_string()),
+                package: Some("test".to_string()),
+                message_type: vec![node],
+                enum_type: vec![color],
+                ..Default::default()
+            }],
+        };
+        DescriptorPool::decode(&set.encode_to_vec()).unwrap()
+    }
+
+    #[test]
+    fn decodes_message_fields() {
+        let mut child = Vec::new();
+        string::encode(2, &"leaf".to_string(), &mut child);
+
+        let mut tag = Vec::new();
+        string::encode(1, &"zone".to_string(), &mut tag);
+        int64::encode(2, &-3, &mut tag);
+
+        let mut data = Vec::new();
+        bytes::encode(1, &child, &mut data);
+        string::encode(2, &"root".to_string(), &mut data);
+        int32::encode_packed(3, &[1, -2, 300], &mut data);
+        int32::encode(3, &4, &mut data);
+        bytes::encode(4, &tag, &mut data);
+        sint32::encode(5, &-7, &mut data);
+        int32::encode(6, &1, &mut data);
+        int32::encode(99, &1, &mut data); // 未知のフィールド
+
+        let value = pool().decode_message("test.Node", &data).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "child": {"name": "leaf"},
+                "name": "root",
+                "values": [1, -2, 300, 4],
+                "tags": {"zone": -3},
+                "delta": -7,
+                "color": "GREEN",
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_messages() {
+        let mut data = Vec::new();
+        string::encode(2, &"leaf".to_string(), &mut data);
+        for _ in 0..10_000 {
+            let mut parent = Vec::new();
+            bytes::encode(1, &data, &mut parent);
+            data = parent;
+        }
+        let err = pool().decode_message(".test.Node", &data).unwrap_err();
+        assert!(err.contains("nested too deeply"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_input() {
+        let pool = pool();
+        let mut data = Vec::new();
+        string::encode(2, &"root".to_string(), &mut data);
+        int32::encode_packed(3, &[1, 2, 3], &mut data);
+        for len in 1..data.len() {
+            // 途中で切れたデータはエラーまたは一部のみ（パニックしない）
+            let _ = pool.decode_message("test.Node", &data[..len]);
+        }
+        assert!(pool.decode_message("test.Node", &data[..3]).is_err());
+        // 長さがデータより大きいフィールド、10バイトを超えるvarint、未対応のワイヤー型
+        assert!(pool.decode_message("test.Node", &[0x12, 0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+        assert!(pool.decode_message("test.Node", &[0x28, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        assert!(pool.decode_message("test.Node", &[0x2b]).is_err());
+        assert!(pool.decode_message("test.Missing", &[]).is_err());
+    }
+
+    #[test]
+    fn decodes_varints() {
+        assert_eq!(decode_varint(&[0xac, 0x02]), Ok((300, 2)));
+        assert!(decode_varint(&[0x80]).is_err());
+        assert_eq!(repeated_varints(&WireValue::LengthDelimited(&[1, 0xac, 0x02])), Ok(vec![1, 300]));
+        assert_eq!(decode_zigzag(3), -2);
+    }
+}
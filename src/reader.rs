@@ -0,0 +1,358 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use crate::crypto::RecordCipher;
+use crate::error::RecorderError;
+use crate::message::{topic_matches_filter, MqttMessage};
+use crate::replay::{get_files_in_range, parse_replay_time, MergedRecords};
+
+/// 記録ファイルの読み込みエラー
+#[derive(Debug)]
+pub enum RecordingError {
+    /// 時刻の形式が正しくない（YYYY-MM-DD HH:MM[:SS]）
+    InvalidTime(String),
+    /// ディレクトリの探索に失敗
+    List { path: PathBuf, message: String },
+    /// ファイルを開けない、または読み込めない
+    Io { path: PathBuf, source: io::Error },
+    /// 暗号化された行を復号できない（鍵がない、鍵の誤りまたは改ざん）
+    Decrypt { path: PathBuf, source: io::Error },
+    /// 行をメッセージとして解析できない
+    Parse { path: PathBuf, source: serde_json::Error },
+    /// ペイロードのBase64が正しくない
+    Payload { path: PathBuf, topic: String, source: base64::DecodeError },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::InvalidTime(time) => {
+                write!(f, "invalid time '{}', expected YYYY-MM-DD HH:MM[:SS]", time)
+            }
+            RecordingError::List { path, message } => write!(f, "failed to list files in {:?}: {}", path, message),
+            RecordingError::Io { path, source } => write!(f, "failed to read {:?}: {}", path, source),
+            RecordingError::Decrypt { path, source } => write!(f, "failed to decrypt {:?}: {}", path, source),
+            RecordingError::Parse { path, source } => write!(f, "invalid record in {:?}: {}", path, source),
+            RecordingError::Payload { path, topic, source } => {
+                write!(f, "invalid payload on '{}' in {:?}: {}", topic, path, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecordingError::Io { source, .. } | RecordingError::Decrypt { source, .. } => Some(source),
+            RecordingError::Parse { source, .. } => Some(source),
+            RecordingError::Payload { source, .. } => Some(source),
+            RecordingError::InvalidTime(_) | RecordingError::List { .. } => None,
+        }
+    }
+}
+
+/// 記録から読み込んだメッセージ（ペイロードはデコード済み）
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    pub topic: String,
+    pub time: f64,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+/// 記録ファイルの取得元
+#[derive(Debug, Clone)]
+enum RecordingSource {
+    Directory(PathBuf),
+    Files(Vec<PathBuf>),
+}
+
+/// 記録ファイルのメッセージを時系列順（モノトニック時刻とシーケンス番号）に読み込む
+#[derive(Clone)]
+pub struct RecordingReader {
+    source: RecordingSource,
+    start_time: Option<String>,
+    end_time: Option<String>,
+    topics: Vec<String>, // 読み込むトピックフィルタ（空の場合はすべて）
+    cipher: Option<RecordCipher>,
+}
+
+impl RecordingReader {
+    /// ディレクトリ内の記録ファイルを再帰的に読み込む
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self::with_source(RecordingSource::Directory(directory.into()))
+    }
+
+    /// 指定された記録ファイルを読み込む
+    pub fn from_files(files: Vec<PathBuf>) -> Self {
+        Self::with_source(RecordingSource::Files(files))
+    }
+
+    fn with_source(source: RecordingSource) -> Self {
+        RecordingReader {
+            source,
+            start_time: None,
+            end_time: None,
+            topics: Vec::new(),
+            cipher: None,
+        }
+    }
+
+    /// 読み込む時間範囲を設定（YYYY-MM-DD HH:MM[:SS]、ローカル時刻）
+    pub fn set_time_range(&mut self, start_time: Option<String>, end_time: Option<String>) {
+        self.start_time = start_time;
+        self.end_time = end_time;
+    }
+
+    /// 読み込むトピックフィルタを設定（空の場合はすべて）
+    pub fn set_topics(&mut self, topics: Vec<String>) {
+        self.topics = topics;
+    }
+
+    /// 暗号化された記録ファイルを復号する鍵を設定
+    pub fn set_cipher(&mut self, cipher: Option<RecordCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// 別のスレッドで読み込み、メッセージを非同期に受け取る
+    pub fn into_stream(self, buffer: usize) -> RecordingStream {
+        let (tx, rx) = mpsc::channel(buffer);
+        tokio::task::spawn_blocking(move || {
+            for item in self {
+                // 受信側が閉じられたら読み込みを終了
+                if tx.blocking_send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        RecordingStream { rx }
+    }
+
+    /// 対象のファイルを取得して読み込みを開始
+    fn open(&self) -> Result<Messages, RecordingError> {
+        let parse_time = |time: &Option<String>| match time {
+            Some(time) => parse_replay_time(time)
+                .map(Some)
+                .ok_or_else(|| RecordingError::InvalidTime(time.clone())),
+            None => Ok(None),
+        };
+        let start_ts = parse_time(&self.start_time)?;
+        let end_ts = parse_time(&self.end_time)?;
+
+        let files = match &self.source {
+            RecordingSource::Directory(directory) => {
+                get_files_in_range(directory, self.start_time.clone(), self.end_time.clone()).map_err(|e| match e {
+                    RecorderError::Io { path, source } => RecordingError::Io { path, source },
+                    other => RecordingError::List {
+                        path: directory.clone(),
+                        message: other.to_string(),
+                    },
+                })?
+            }
+            RecordingSource::Files(files) => files.clone(),
+        };
+        Ok(Messages {
+            records: MergedRecords::new(files, start_ts, self.cipher.clone()),
+            end_time: end_ts,
+            topics: self.topics.clone(),
+        })
+    }
+}
+
+impl IntoIterator for RecordingReader {
+    type Item = Result<RecordedMessage, RecordingError>;
+    type IntoIter = RecordingIter;
+
+    fn into_iter(self) -> RecordingIter {
+        RecordingIter {
+            state: IterState::Pending(Box::new(self)),
+        }
+    }
+}
+
+/// 読み込み中のファイルとフィルタ
+struct Messages {
+    records: MergedRecords,
+    end_time: Option<f64>,
+    topics: Vec<String>,
+}
+
+enum IterState {
+    Pending(Box<RecordingReader>),
+    Reading(Box<Messages>),
+    Done,
+}
+
+/// `RecordingReader` の同期イテレータ
+/// 読み込みエラーを返した後も次のメッセージに進むため、エラーで終了するかは呼び出し側が選べる
+pub struct RecordingIter {
+    state: IterState,
+}
+
+impl Iterator for RecordingIter {
+    type Item = Result<RecordedMessage, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let IterState::Pending(reader) = &self.state {
+            match reader.open() {
+                Ok(messages) => self.state = IterState::Reading(Box::new(messages)),
+                Err(e) => {
+                    self.state = IterState::Done;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let messages = match &mut self.state {
+            IterState::Reading(messages) => messages,
+            _ => return None,
+        };
+
+        loop {
+            let (msg, path) = match messages.records.next_with_path() {
+                Some(Ok(next)) => next,
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            };
+            // 時間範囲外のメッセージで終了
+            if messages.end_time.is_some_and(|end| msg.time > end) {
+                break;
+            }
+            if !messages.topics.is_empty() && !messages.topics.iter().any(|filter| topic_matches_filter(filter, &msg.topic)) {
+                continue;
+            }
+            return Some(decode_message(msg, path));
+        }
+        self.state = IterState::Done;
+        None
+    }
+}
+
+/// ヘルパー関数：記録されたメッセージのペイロードをデコード
+fn decode_message(msg: MqttMessage, path: &Path) -> Result<RecordedMessage, RecordingError> {
+    match base64::decode(&msg.msg_b64) {
+        Ok(payload) => Ok(RecordedMessage {
+            topic: msg.topic,
+            time: msg.time,
+            qos: msg.qos,
+            retain: msg.retain,
+            payload,
+        }),
+        Err(source) => Err(RecordingError::Payload {
+            path: path.to_path_buf(),
+            topic: msg.topic,
+            source,
+        }),
+    }
+}
+
+/// `RecordingReader` の非同期ストリーム（`RecordingReader::into_stream` で作成）
+/// `futures::Stream` を実装しているため、`StreamExt` のアダプタと組み合わせられる
+pub struct RecordingStream {
+    rx: mpsc::Receiver<Result<RecordedMessage, RecordingError>>,
+}
+
+impl RecordingStream {
+    /// 次のメッセージを取得（すべて読み込んだらNone）
+    pub async fn next(&mut self) -> Option<Result<RecordedMessage, RecordingError>> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for RecordingStream {
+    type Item = Result<RecordedMessage, RecordingError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn line(topic: &str, time: f64, payload: &str) -> String {
+        format!(
+            "{{\"time\":{},\"qos\":1,\"retain\":false,\"topic\":\"{}\",\"msg_b64\":\"{}\"}}",
+            time, topic, payload
+        )
+    }
+
+    fn write_recording(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mqtt-recorder-reader-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mqtt-recorder-20250725-010000.json");
+        let lines = [
+            line("a", 1.0, &base64::encode("first")),
+            "not json".to_string(),
+            line("a", 2.0, "%%%"),
+            String::new(),
+            line("b", 3.0, &base64::encode("last")),
+        ];
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        path
+    }
+
+    #[test]
+    fn yields_line_errors_and_continues() {
+        let path = write_recording("errors");
+        let items: Vec<_> = RecordingReader::from_files(vec![path.clone()]).into_iter().collect();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_ref().unwrap().payload, b"first");
+        assert!(matches!(&items[1], Err(RecordingError::Parse { path: p, .. }) if *p == path));
+        assert!(matches!(&items[2], Err(RecordingError::Payload { topic, .. }) if topic == "a"));
+        let last = items[3].as_ref().unwrap();
+        assert_eq!((last.topic.as_str(), last.time, last.qos), ("b", 3.0, 1));
+        assert_eq!(last.payload, b"last");
+
+        // トピックフィルタ
+        let mut reader = RecordingReader::from_files(vec![path.clone()]);
+        reader.set_topics(vec!["b".to_string()]);
+        let topics: Vec<String> = reader.into_iter().filter_map(Result::ok).map(|msg| msg.topic).collect();
+        assert_eq!(topics, vec!["b"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reports_invalid_time_once() {
+        let mut reader = RecordingReader::new(std::env::temp_dir());
+        reader.set_time_range(Some("yesterday".to_string()), None);
+        let items: Vec<_> = reader.into_iter().collect();
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0], Err(RecordingError::InvalidTime(time)) if time == "yesterday"));
+    }
+
+    #[tokio::test]
+    async fn stream_yields_the_same_items() {
+        let path = write_recording("stream");
+        let mut stream = RecordingReader::from_files(vec![path.clone()]).into_stream(1);
+        let mut ok = 0;
+        let mut errors = 0;
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(_) => ok += 1,
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!((ok, errors), (2, 2));
+
+        // Streamとして読み込む
+        let mut stream = RecordingReader::from_files(vec![path.clone()]).into_stream(1);
+        let mut items = Vec::new();
+        while let Some(item) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            items.push(item);
+        }
+        assert_eq!(items.len(), 4);
+        assert!(items[1].is_err());
+        assert_eq!(items[3].as_ref().unwrap().payload, b"last");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+use log::*;
+use rumqttc::{ConnectionError, Event, EventLoop, Incoming, MqttOptions, Publish, QoS, Request, Sender, Subscribe};
+use tokio::sync::Notify;
+use tokio::time::{interval, Duration};
+use crate::error::RecorderError;
+use crate::file_manager::{MinuteFileManager, TopicFileManager};
+use crate::heartbeat::HeartbeatReport;
+use crate::message::{MessageClock, MqttMessage};
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::stats::OutboundMessage;
+use crate::stats_config::StatsConfig;
+
+/// 受信したメッセージの書き込み先
+pub trait RecordSink: Send {
+    /// メッセージを書き込む（時刻とシーケンス番号は付与済み）
+    fn write_message(&mut self, msg: &mut MqttMessage) -> Result<(), RecorderError>;
+
+    /// 1秒ごとに呼び出される（統計期間の出力やファイルのクリーンアップ）
+    fn tick(&mut self) {}
+
+    /// ブローカーに送信するメッセージ（統計やアラート）を取得
+    fn take_outbound(&mut self) -> Vec<OutboundMessage> {
+        Vec::new()
+    }
+
+    /// ハートビートで送信する記録の状態（Noneの場合は送信しない）
+    fn heartbeat(&mut self) -> Option<HeartbeatReport> {
+        None
+    }
+
+    /// 記録の終了時に呼び出される（開いているファイルを閉じる）
+    fn close(&mut self) {}
+
+    /// 書き込みなどを記録するメトリクスを設定
+    fn set_metrics(&mut self, _metrics: Metrics) {}
+
+    /// 計算する統計量の設定
+    fn set_stats_config(&mut self, _config: StatsConfig) {}
+}
+
+impl RecordSink for MinuteFileManager {
+    fn write_message(&mut self, msg: &mut MqttMessage) -> Result<(), RecorderError> {
+        MinuteFileManager::write_message(self, msg)
+    }
+
+    fn tick(&mut self) {
+        self.check_stats_windows();
+    }
+
+    fn take_outbound(&mut self) -> Vec<OutboundMessage> {
+        self.take_stats_outbound()
+    }
+
+    fn heartbeat(&mut self) -> Option<HeartbeatReport> {
+        Some(MinuteFileManager::heartbeat(self))
+    }
+
+    fn close(&mut self) {
+        MinuteFileManager::close(self);
+    }
+
+    fn set_metrics(&mut self, metrics: Metrics) {
+        MinuteFileManager::set_metrics(self, metrics);
+    }
+
+    fn set_stats_config(&mut self, config: StatsConfig) {
+        MinuteFileManager::set_stats_config(self, config);
+    }
+}
+
+impl RecordSink for TopicFileManager {
+    fn write_message(&mut self, msg: &mut MqttMessage) -> Result<(), RecorderError> {
+        TopicFileManager::write_message(self, msg)
+    }
+
+    fn tick(&mut self) {
+        TopicFileManager::tick(self);
+    }
+
+    fn take_outbound(&mut self) -> Vec<OutboundMessage> {
+        self.take_stats_outbound()
+    }
+
+    fn heartbeat(&mut self) -> Option<HeartbeatReport> {
+        Some(TopicFileManager::heartbeat(self))
+    }
+
+    fn close(&mut self) {
+        self.close_all();
+    }
+
+    fn set_metrics(&mut self, metrics: Metrics) {
+        TopicFileManager::set_metrics(self, metrics);
+    }
+
+    fn set_stats_config(&mut self, config: StatsConfig) {
+        TopicFileManager::set_stats_config(self, config);
+    }
+}
+
+/// `Recorder` の設定（`Recorder::builder` で作成）
+pub struct RecorderBuilder {
+    options: MqttOptions,
+    sink: Box<dyn RecordSink>,
+    topics: Vec<String>,
+    stats_config: Option<StatsConfig>,
+    heartbeat: Option<(String, u64)>,
+    metrics: Metrics,
+}
+
+impl RecorderBuilder {
+    /// 購読するトピックフィルタを追加
+    pub fn topic<S: Into<String>>(mut self, topic: S) -> Self {
+        self.topics.push(topic.into());
+        self
+    }
+
+    /// 購読するトピックフィルタをまとめて追加（指定しない場合は "#"）
+    pub fn topics<I, S>(mut self, topics: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.topics.extend(topics.into_iter().map(Into::into));
+        self
+    }
+
+    /// 計算する統計量を設定
+    pub fn stats_config(mut self, config: StatsConfig) -> Self {
+        self.stats_config = Some(config);
+        self
+    }
+
+    /// 記録の状態を一定間隔（秒）でトピックに送信
+    pub fn heartbeat<S: Into<String>>(mut self, topic: S, interval_secs: u64) -> Self {
+        self.heartbeat = Some((topic.into(), interval_secs));
+        self
+    }
+
+    /// カウンタを共有するメトリクスを設定（Prometheusで公開する場合など）
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn build(self) -> Recorder {
+        let mut sink = self.sink;
+        if let Some(config) = self.stats_config {
+            sink.set_stats_config(config);
+        }
+        sink.set_metrics(self.metrics.clone());
+        let topics = if self.topics.is_empty() { vec!["#".to_string()] } else { self.topics };
+        Recorder {
+            options: self.options,
+            sink,
+            topics,
+            heartbeat: self.heartbeat,
+            metrics: self.metrics,
+            shutdown: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// ブローカーに接続してメッセージを記録する（CLIの `record` と `irecord` と同じ処理）
+pub struct Recorder {
+    options: MqttOptions,
+    sink: Box<dyn RecordSink>,
+    topics: Vec<String>,
+    heartbeat: Option<(String, u64)>,
+    metrics: Metrics,
+    shutdown: Arc<Notify>,
+}
+
+impl Recorder {
+    /// ブローカーの接続設定と書き込み先から設定を開始
+    pub fn builder<S: RecordSink + 'static>(options: MqttOptions, sink: S) -> RecorderBuilder {
+        RecorderBuilder {
+            options,
+            sink: Box::new(sink),
+            topics: Vec::new(),
+            stats_config: None,
+            heartbeat: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// 実行中の記録を停止し、カウンタを取得するハンドル
+    pub fn handle(&self) -> RecorderHandle {
+        RecorderHandle {
+            shutdown: self.shutdown.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// `RecorderHandle::shutdown` が呼ばれるまで記録（ネットワークエラーで終了）
+    pub async fn run(self) -> Result<(), RecorderError> {
+        let Recorder {
+            options,
+            mut sink,
+            topics,
+            heartbeat,
+            metrics,
+            shutdown,
+        } = self;
+        let (host, port) = options.broker_address();
+        let mut eventloop = EventLoop::new(options, 20);
+        let requests_tx = eventloop.requests_tx.clone();
+        let mut clock = MessageClock::new();
+        // 統計期間の区切りを逃さないよう1秒ごとにチェック
+        let mut tick_timer = interval(Duration::from_secs(1));
+        let heartbeat_secs = heartbeat.as_ref().map_or(1, |(_, secs)| (*secs).max(1));
+        let mut heartbeat_timer = interval(Duration::from_secs(heartbeat_secs));
+
+        loop {
+            tokio::select! {
+                _ = shutdown.notified() => break,
+                res = eventloop.poll() => match res {
+                    Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                        // 受信時点で時刻とシーケンス番号を付与
+                        let mut msg = MqttMessage::from_publish(&publish, &mut clock);
+                        metrics.message_received(&msg.topic);
+                        if let Err(e) = sink.write_message(&mut msg) {
+                            metrics.write_error();
+                            error!("Failed to write message for topic '{}': {}", msg.topic, e);
+                        }
+                        send_outbound(&requests_tx, sink.take_outbound()).await;
+
+                        debug!("{:?}", publish);
+                    }
+                    Ok(Event::Incoming(Incoming::ConnAck(_connect))) => {
+                        info!("Connected to: {}:{}", host, port);
+                        metrics.connected();
+
+                        for topic in &topics {
+                            let subscription = Subscribe::new(topic, QoS::AtLeastOnce);
+                            let _ = requests_tx.send(Request::Subscribe(subscription)).await;
+                        }
+                    }
+                    Err(e) => {
+                        metrics.connection_error();
+                        if let ConnectionError::Network(_) = e {
+                            sink.close();
+                            return Err(RecorderError::Connection(e));
+                        }
+                        error!("{:?}", e);
+                    }
+                    _ => {}
+                },
+                _ = tick_timer.tick() => {
+                    sink.tick();
+                    send_outbound(&requests_tx, sink.take_outbound()).await;
+                }
+                _ = heartbeat_timer.tick(), if heartbeat.is_some() => {
+                    if let (Some((topic, _)), Some(report)) = (&heartbeat, sink.heartbeat()) {
+                        match serde_json::to_vec(&report) {
+                            Ok(payload) => {
+                                let _ = requests_tx.send(Publish::new(topic, QoS::AtLeastOnce, payload).into()).await;
+                            }
+                            Err(e) => error!("Failed to serialize heartbeat: {}", e),
+                        }
+                    }
+                }
+            }
+        }
+
+        info!("Recorder stopped");
+        sink.close();
+        Ok(())
+    }
+}
+
+/// ヘルパー関数：統計やアラートのメッセージをブローカーに送信
+async fn send_outbound(requests_tx: &Sender<Request>, outbound: Vec<OutboundMessage>) {
+    for outbound in outbound {
+        let publish = Publish::new(outbound.topic, QoS::AtLeastOnce, outbound.payload);
+        let _ = requests_tx.send(publish.into()).await;
+    }
+}
+
+/// 実行中の `Recorder` を操作するハンドル（複製可能）
+#[derive(Clone)]
+pub struct RecorderHandle {
+    shutdown: Arc<Notify>,
+    metrics: Metrics,
+}
+
+impl RecorderHandle {
+    /// 記録を停止（開いているファイルを閉じてから `Recorder::run` が終了）
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    /// 受信、書き込み、接続などのカウンタの現在の値
+    pub fn counters(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+}
@@ -1,23 +1,45 @@
-use std::{fs, path::PathBuf};
-use chrono::NaiveDateTime;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, VecDeque},
+    fs,
+    future::Future,
+    io::{BufRead, BufReader, Lines, Seek},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use log::*;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use crate::crypto::{open_line, RecordCipher};
+use crate::error::RecorderError;
+use crate::index::{open_at_time, read_file_summary};
+use crate::message::{topic_matches_filter, MessageTime, MqttMessage};
+use crate::metrics::Metrics;
+use crate::reader::RecordingError;
 
 /// ヘルパー関数：ディレクトリ内の指定された時間範囲のファイルを取得
+/// 時刻（YYYY-MM-DD HH:MM[:SS]）の形式が正しくない場合はエラー
 pub fn get_files_in_range(
     base_dir: &PathBuf, 
     start_time: Option<String>, 
     end_time: Option<String>
-) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+) -> Result<Vec<PathBuf>, RecorderError> {
     let mut files = Vec::new();
     
     // ディレクトリを再帰的に探索
-    fn collect_json_files(dir: &PathBuf, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    fn collect_json_files(dir: &PathBuf, files: &mut Vec<PathBuf>) -> Result<(), RecorderError> {
         if dir.is_dir() {
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?;
+            for entry in fs::read_dir(dir).map_err(|e| RecorderError::io(dir, e))? {
+                let entry = entry.map_err(|e| RecorderError::io(dir, e))?;
                 let path = entry.path();
                 if path.is_dir() {
                     collect_json_files(&path, files)?;
-                } else if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                } else if path.extension().and_then(|s| s.to_str()) == Some("json") && !is_legacy_schema_file(&path) {
                     files.push(path);
                 }
             }
@@ -25,71 +47,38 @@ pub fn get_files_in_range(
         Ok(())
     }
     
+    let parse_time = |time: &Option<String>| match time {
+        Some(s) => parse_replay_time(s).map(Some).ok_or_else(|| RecorderError::InvalidTime(s.clone())),
+        None => Ok(None),
+    };
+    let start_ts = parse_time(&start_time)?;
+    let end_ts = parse_time(&end_time)?;
+
     collect_json_files(base_dir, &mut files)?;
     
-    // ファイル名に基づいて時間範囲でフィルタリング
-    if start_time.is_some() || end_time.is_some() {
-        let start_dt = start_time.as_ref().and_then(|s| {
-            NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").ok()
-        });
-        let end_dt = end_time.as_ref().and_then(|s| {
-            NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").ok()
-        });
-        
+    // インデックス（なければファイル名）に基づいて時間範囲でフィルタリング
+    if start_ts.is_some() || end_ts.is_some() {
         files.retain(|path| {
-            if let Some(filename) = path.file_stem().and_then(|s| s.to_str()) {
-                if let Some(time_part) = filename.strip_prefix("mqtt-recorder-") {
-                    // 複数のパターンに対応
-                    // 1. 標準記録: mqtt-recorder-yyyy-mm-dd-hhmm.json
-                    // 2. インテリジェント記録（番号なし）: mqtt-recorder-{topic}-yyyymmdd-hhmmss.json
-                    // 3. インテリジェント記録（番号付き）: mqtt-recorder-{topic}-yyyymmdd-hhmmss-{number}.json
-                    
-                    // 標準記録のパターン（yyyy-mm-dd-hhmm）
-                    if let Ok(file_dt) = NaiveDateTime::parse_from_str(time_part, "%Y-%m-%d-%H%M") {
-                        let mut keep = true;
-                        if let Some(start) = start_dt {
-                            keep &= file_dt >= start;
-                        }
-                        if let Some(end) = end_dt {
-                            keep &= file_dt <= end;
-                        }
-                        return keep;
-                    }
-                    
-                    // インテリジェント記録のパターンを処理
-                    // 最後の部分がタイムスタンプかタイムスタンプ-番号かを判定
-                    let parts: Vec<&str> = time_part.split('-').collect();
-                    if parts.len() >= 3 {
-                        // 最後の要素が数字かチェック（ファイル番号の可能性）
-                        let last_part = parts[parts.len() - 1];
-                        
-                        let timestamp_part = if last_part.chars().all(|c| c.is_ascii_digit()) && parts.len() >= 4 {
-                            // ファイル番号付きのパターン: mqtt-recorder-{topic}-yyyymmdd-hhmmss-{number}
-                            // 最後から2つ目の部分までを結合してタイムスタンプとする
-                            parts[parts.len() - 4..parts.len() - 1].join("-")
-                        } else {
-                            // 番号なしのパターン: mqtt-recorder-{topic}-yyyymmdd-hhmmss
-                            // 最後から2つの部分を結合してタイムスタンプとする
-                            if parts.len() >= 3 {
-                                parts[parts.len() - 2..].join("-")
-                            } else {
-                                return false;
-                            }
-                        };
-                        
-                        // yyyymmdd-hhmmss形式のタイムスタンプをパース
-                        if let Ok(file_dt) = NaiveDateTime::parse_from_str(&timestamp_part, "%Y%m%d-%H%M%S") {
-                            let mut keep = true;
-                            if let Some(start) = start_dt {
-                                keep &= file_dt >= start;
-                            }
-                            if let Some(end) = end_dt {
-                                keep &= file_dt <= end;
-                            }
-                            return keep;
-                        }
-                    }
+            // インデックスがある場合はファイル内の最初と最後のタイムスタンプで判定
+            if let Some(summary) = read_file_summary(path) {
+                return summary.overlaps(start_ts, end_ts);
+            }
+            
+            let file_ts = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(parse_file_time)
+                .and_then(naive_to_timestamp);
+            if let Some(file_ts) = file_ts {
+                let mut keep = true;
+                // ファイル名の時刻は分単位のため、開始時刻は分に切り捨てて比較
+                if let Some(start) = start_ts {
+                    keep &= file_ts >= start - start.rem_euclid(60.0);
                 }
+                if let Some(end) = end_ts {
+                    keep &= file_ts <= end;
+                }
+                return keep;
             }
             false
         });
@@ -99,3 +88,884 @@ pub fn get_files_in_range(
     files.sort();
     Ok(files)
 }
+
+/// ヘルパー関数：以前のバージョンが記録ディレクトリに書き込んだスキーマファイル（`-schema.json`）か
+fn is_legacy_schema_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("mqtt-recorder-") && name.ends_with("-schema.json"))
+}
+
+/// ヘルパー関数：再生範囲の時刻文字列（YYYY-MM-DD HH:MM[:SS]、ローカル時刻）をUNIX秒に変換
+pub fn parse_replay_time(time: &str) -> Option<f64> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+        .and_then(naive_to_timestamp)
+}
+
+/// ヘルパー関数：再生速度として使える値か（正の有限の値）
+pub fn is_valid_speed(speed: f64) -> bool {
+    speed > 0.0 && speed.is_finite()
+}
+
+/// ヘルパー関数：待機する秒数を変換（負の値やNaNは0、大きすぎる値は最大値に丸める）
+fn delay_duration(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(if secs > 0.0 { Duration::MAX } else { Duration::ZERO })
+}
+
+/// ヘルパー関数：UNIX秒をローカル時刻（YYYY-MM-DD HH:MM:SS.sss）の文字列に変換
+pub fn format_replay_time(time: f64) -> String {
+    Local
+        .timestamp_millis_opt((time * 1000.0) as i64)
+        .single()
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
+
+/// ヘルパー関数：ローカル時刻をUNIX秒に変換
+fn naive_to_timestamp(dt: NaiveDateTime) -> Option<f64> {
+    Local
+        .from_local_datetime(&dt)
+        .earliest()
+        .map(|dt| dt.timestamp_millis() as f64 / 1000.0)
+}
+
+/// ヘルパー関数：ファイル名から記録開始時刻を取得（インデックスがない場合のフォールバック）
+fn parse_file_time(filename: &str) -> Option<NaiveDateTime> {
+    let time_part = filename.strip_prefix("mqtt-recorder-")?;
+    // 複数のパターンに対応
+    // 1. 標準記録: mqtt-recorder-yyyy-mm-dd-hhmm.json
+    // 2. インテリジェント記録（番号なし）: mqtt-recorder-{topic}-yyyymmdd-hhmmss.json
+    // 3. インテリジェント記録（番号付き）: mqtt-recorder-{topic}-yyyymmdd-hhmmss-{number}.json
+    
+    // 標準記録のパターン（yyyy-mm-dd-hhmm）
+    if let Ok(file_dt) = NaiveDateTime::parse_from_str(time_part, "%Y-%m-%d-%H%M") {
+        return Some(file_dt);
+    }
+    
+    // インテリジェント記録のパターンを処理
+    // 最後の部分がタイムスタンプかタイムスタンプ-番号かを判定
+    let parts: Vec<&str> = time_part.split('-').collect();
+    if parts.len() < 3 {
+        return None;
+    }
+    // 番号なしのパターン: 最後から2つの部分を結合してタイムスタンプとする
+    let timestamp_part = parts[parts.len() - 2..].join("-");
+    if let Ok(file_dt) = NaiveDateTime::parse_from_str(&timestamp_part, "%Y%m%d-%H%M%S") {
+        return Some(file_dt);
+    }
+    
+    // ファイル番号付きのパターン: 最後の番号を除いた2つの部分をタイムスタンプとする
+    let timestamp_part = parts[parts.len() - 3..parts.len() - 1].join("-");
+    
+    // yyyymmdd-hhmmss形式のタイムスタンプをパース
+    NaiveDateTime::parse_from_str(&timestamp_part, "%Y%m%d-%H%M%S").ok()
+}
+
+/// 再生時に先読みでファイルを開く余裕（秒）。壁時計とモノトニック時刻のずれを吸収する
+const MERGE_OPEN_MARGIN_SECS: f64 = 60.0;
+
+/// マージ中のファイルの次のメッセージ
+struct MergeEntry {
+    key: (f64, u32, u64), // (壁時計時刻に合わせたモノトニック時刻, 起動, シーケンス番号)
+    source: usize,
+    msg: MqttMessage,
+}
+
+impl PartialEq for MergeEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeEntry {}
+
+impl PartialOrd for MergeEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergeEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .0
+            .total_cmp(&other.key.0)
+            .then(self.key.1.cmp(&other.key.1))
+            .then(self.key.2.cmp(&other.key.2))
+            .then(self.source.cmp(&other.source))
+    }
+}
+
+/// 複数の記録ファイルを時系列順（モノトニック時刻とシーケンス番号）にマージして読み込む
+/// モノトニック時刻の起点は起動ごとに異なるため、起動ごとに最初のメッセージの壁時計時刻に合わせて比較する
+/// ファイルは開始時刻の見積もりが近づいた時点で開くため、同時に開くファイル数は重なっているものに限られる
+pub struct MergedRecords {
+    pending: Vec<(f64, PathBuf)>, // 未オープンのファイル（開始時刻の降順、末尾から取り出す）
+    readers: Vec<Option<Lines<BufReader<fs::File>>>>,
+    paths: Vec<PathBuf>,
+    line_numbers: Vec<u64>, // ファイルごとに最後に読み込んだ行の行番号
+    heap: BinaryHeap<Reverse<MergeEntry>>,
+    start_time: Option<f64>,
+    cipher: Option<RecordCipher>,
+    errors: VecDeque<RecordingError>, // 次のメッセージより先に返す読み込みエラー
+    run_offsets: HashMap<u32, f64>, // 起動ごとの壁時計時刻とモノトニック時刻の差
+    bytes_read: Arc<AtomicU64>, // 読み込んだ位置の合計（シークした分を含む）
+}
+
+impl MergedRecords {
+    pub fn new(files: Vec<PathBuf>, start_time: Option<f64>, cipher: Option<RecordCipher>) -> Self {
+        let mut pending: Vec<(f64, PathBuf)> = files
+            .into_iter()
+            .map(|path| (estimate_file_start(&path), path))
+            .collect();
+        pending.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        MergedRecords {
+            pending,
+            readers: Vec::new(),
+            paths: Vec::new(),
+            line_numbers: Vec::new(),
+            heap: BinaryHeap::new(),
+            start_time,
+            cipher,
+            errors: VecDeque::new(),
+            run_offsets: HashMap::new(),
+            bytes_read: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// 読み込んだバイト数のカウンタ（別のタスクから進捗を取得するため共有）
+    pub(crate) fn bytes_read(&self) -> Arc<AtomicU64> {
+        self.bytes_read.clone()
+    }
+
+    /// ファイルを開いて最初のメッセージをヒープに追加
+    fn open_next_file(&mut self) {
+        if let Some((_, path)) = self.pending.pop() {
+            debug!("Processing file: {:?}", path);
+            let source = self.readers.len();
+            // インデックスがあれば開始時刻付近までシーク
+            match open_at_time(&path, self.start_time) {
+                Ok((mut reader, skipped)) => {
+                    // インデックスでシークした位置までは読み込み済みとして数える
+                    let offset = reader.stream_position().unwrap_or(0);
+                    self.bytes_read.fetch_add(offset, AtomicOrdering::Relaxed);
+                    self.readers.push(Some(reader.lines()));
+                    self.line_numbers.push(skipped);
+                }
+                Err(source) => {
+                    self.errors.push_back(RecordingError::Io { path: path.clone(), source });
+                    self.readers.push(None);
+                    self.line_numbers.push(0);
+                }
+            }
+            self.paths.push(path);
+            self.advance(source);
+        }
+    }
+
+    /// 指定されたファイルから次のメッセージを読み込んでヒープに追加
+    fn advance(&mut self, source: usize) {
+        let reader = match self.readers[source].as_mut() {
+            Some(reader) => reader,
+            None => return,
+        };
+        for line in reader.by_ref() {
+            let path = &self.paths[source];
+            let line = match line {
+                Ok(line) => line,
+                Err(source) => {
+                    self.errors.push_back(RecordingError::Io { path: path.clone(), source });
+                    break;
+                }
+            };
+            self.bytes_read.fetch_add(line.len() as u64 + 1, AtomicOrdering::Relaxed);
+            self.line_numbers[source] += 1;
+            let line = match open_line(&line, self.cipher.as_ref(), path, self.line_numbers[source]) {
+                Ok(line) if line.is_empty() => continue,
+                Ok(line) => line,
+                Err(source) => {
+                    self.errors.push_back(RecordingError::Decrypt { path: path.clone(), source });
+                    break;
+                }
+            };
+            match serde_json::from_str::<MqttMessage>(&line) {
+                Ok(msg) => {
+                    // 開始時刻より前のメッセージはスキップ
+                    if self.start_time.is_some_and(|start| msg.time < start) {
+                        continue;
+                    }
+                    let key = merge_key(&mut self.run_offsets, &msg);
+                    self.heap.push(Reverse(MergeEntry { key, source, msg }));
+                    return;
+                }
+                Err(source) => self.errors.push_back(RecordingError::Parse { path: path.clone(), source }),
+            }
+        }
+        // ファイルの終端に達したので閉じる
+        self.readers[source] = None;
+    }
+
+    /// 次のメッセージと読み込んだファイル、または読み込みエラーを取得
+    pub(crate) fn next_with_path(&mut self) -> Option<Result<(MqttMessage, &Path), RecordingError>> {
+        // 次のメッセージより前に始まる可能性のあるファイルをすべて開く
+        while let Some(&(next_start, _)) = self.pending.last() {
+            if !self.errors.is_empty() {
+                break;
+            }
+            match self.heap.peek() {
+                Some(Reverse(entry)) if entry.key.0 + MERGE_OPEN_MARGIN_SECS < next_start => break,
+                _ => self.open_next_file(),
+            }
+        }
+        if let Some(e) = self.errors.pop_front() {
+            return Some(Err(e));
+        }
+
+        let Reverse(entry) = self.heap.pop()?;
+        self.advance(entry.source);
+        Some(Ok((entry.msg, &self.paths[entry.source])))
+    }
+}
+
+impl Iterator for MergedRecords {
+    type Item = MqttMessage;
+
+    /// 読み込みエラーはログに出力して次のメッセージに進む（JSONとして解析できない行は無視）
+    fn next(&mut self) -> Option<MqttMessage> {
+        loop {
+            match self.next_with_path()? {
+                Ok((msg, _)) => return Some(msg),
+                Err(e @ RecordingError::Parse { .. }) => debug!("Skipping record: {}", e),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+}
+
+/// ヘルパー関数：マージの順序キー（起動の識別子がない記録はモノトニック時刻をそのまま使う）
+fn merge_key(run_offsets: &mut HashMap<u32, f64>, msg: &MqttMessage) -> (f64, u32, u64) {
+    let (time, seq) = msg.order_key();
+    match (msg.run, msg.mono) {
+        (Some(run), Some(mono)) => {
+            let offset = *run_offsets.entry(run).or_insert(msg.time - mono);
+            (mono + offset, run, seq)
+        }
+        _ => (time, 0, seq),
+    }
+}
+
+/// ヘルパー関数：ファイルの開始時刻を見積もる（インデックス優先、なければファイル名、不明なら0）
+fn estimate_file_start(path: &Path) -> f64 {
+    if let Some(summary) = read_file_summary(path) {
+        return summary.first_time;
+    }
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(parse_file_time)
+        .and_then(naive_to_timestamp)
+        .unwrap_or(0.0)
+}
+
+/// 1回分の再生で取り出すメッセージのイテレータ
+pub type ReplayMessages = Box<dyn Iterator<Item = MqttMessage> + Send>;
+
+/// 再生するメッセージの取得元
+pub enum ReplaySource {
+    /// ディレクトリ内の記録ファイル（時間範囲はインデックスまたはファイル名で絞り込む）
+    Directory(PathBuf),
+    /// 指定された記録ファイル
+    Files(Vec<PathBuf>),
+    /// 任意のメッセージ列（ループ再生のたびに呼び出して最初から取り出す）
+    Messages(Box<dyn Fn() -> ReplayMessages + Send + Sync>),
+}
+
+impl ReplaySource {
+    /// メッセージ列から取得元を作成（時系列順に並んでいる必要がある）
+    pub fn messages<I>(messages: I) -> Self
+    where
+        I: IntoIterator<Item = MqttMessage>,
+        I::IntoIter: Clone + Send + Sync + 'static,
+    {
+        let messages = messages.into_iter();
+        ReplaySource::Messages(Box::new(move || Box::new(messages.clone())))
+    }
+}
+
+/// 再生中の操作（`ReplayController` から送信）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayCommand {
+    /// 一時停止
+    Pause,
+    /// 再開
+    Resume,
+    /// 一時停止中に次のメッセージを1件だけ再生
+    Step,
+    /// 記録時刻（UNIX秒）の位置に移動
+    Seek(f64),
+    /// 再生速度を変更
+    Speed(f64),
+}
+
+/// 再生の現在の状態と進捗
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ReplayStatus {
+    pub paused: bool,
+    pub speed: f64,
+    pub position: Option<f64>, // 最後に再生したメッセージの記録時刻（UNIX秒）
+    pub replayed: u64, // 再生したメッセージ数（ループ全体）
+    pub messages_per_sec: f64, // 直近の再生レート（状態の取得時に計算）
+    pub bytes_done: u64, // 今回の再生で読み込んだバイト数
+    pub bytes_total: Option<u64>, // 今回の再生の対象ファイルの合計サイズ（メッセージ列の場合はNone）
+    pub percent: Option<f64>, // 読み込んだバイト数の割合
+    pub eta_secs: Option<f64>, // 現在の速度での残り時間（秒）
+}
+
+impl ReplayStatus {
+    fn new(speed: f64) -> Self {
+        ReplayStatus {
+            paused: false,
+            speed,
+            position: None,
+            replayed: 0,
+            messages_per_sec: 0.0,
+            bytes_done: 0,
+            bytes_total: None,
+            percent: None,
+            eta_secs: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ReplayStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let position = self.position.map(format_replay_time).unwrap_or_else(|| "-".to_string());
+        write!(f, "{} at {}", if self.paused { "Paused" } else { "Playing" }, position)?;
+        if let Some(percent) = self.percent {
+            write!(f, ", {:.1}% done", percent)?;
+        }
+        write!(f, ", {:.1} msg/s, speed {}x", self.messages_per_sec, self.speed)?;
+        if let Some(eta) = self.eta_secs {
+            let eta = eta.round() as u64;
+            write!(f, ", ETA {}:{:02}:{:02}", eta / 3600, eta / 60 % 60, eta % 60)?;
+        }
+        write!(f, ", {} messages replayed", self.replayed)
+    }
+}
+
+/// 再生レートを計算する最短の区間（秒）。1秒ごとの取得で毎回計算し直せるよう1秒より短くする
+const RATE_MIN_INTERVAL_SECS: f64 = 0.5;
+
+/// `Replayer` とハンドルで共有する再生の状態（再生レートは取得時に計算する）
+struct SharedStatus {
+    status: ReplayStatus,
+    rate_started: Instant, // 再生レートを計算する区間の開始時刻
+    rate_replayed: u64, // 区間の開始時の再生したメッセージ数
+}
+
+impl SharedStatus {
+    fn new(speed: f64) -> Self {
+        SharedStatus {
+            status: ReplayStatus::new(speed),
+            rate_started: Instant::now(),
+            rate_replayed: 0,
+        }
+    }
+
+    /// 現在の状態を取得（再生が止まってもレートが下がるよう、前回の計算から一定時間経っていれば計算し直す）
+    fn snapshot(&mut self) -> ReplayStatus {
+        let elapsed = self.rate_started.elapsed().as_secs_f64();
+        if elapsed >= RATE_MIN_INTERVAL_SECS {
+            let replayed = self.status.replayed.saturating_sub(self.rate_replayed);
+            self.status.messages_per_sec = replayed as f64 / elapsed;
+            self.rate_started = Instant::now();
+            self.rate_replayed = self.status.replayed;
+        }
+        self.status.clone()
+    }
+}
+
+/// ヘルパー関数：共有する再生の状態を取得
+fn status_snapshot(status: &Mutex<SharedStatus>) -> ReplayStatus {
+    status.lock().unwrap_or_else(|e| e.into_inner()).snapshot()
+}
+
+/// 再生の状態と進捗を取得するハンドル（`Replayer::monitor` で作成、複製可能）
+#[derive(Clone)]
+pub struct ReplayMonitor {
+    status: Arc<Mutex<SharedStatus>>,
+}
+
+impl ReplayMonitor {
+    /// 再生の現在の状態と進捗を取得
+    pub fn status(&self) -> ReplayStatus {
+        status_snapshot(&self.status)
+    }
+}
+
+/// 実行中の `Replayer` を操作するハンドル（複製可能）
+/// すべてのハンドルが破棄されると、一時停止中の再生は再開する
+#[derive(Clone)]
+pub struct ReplayController {
+    tx: mpsc::UnboundedSender<ReplayCommand>,
+    status: Arc<Mutex<SharedStatus>>,
+}
+
+impl ReplayController {
+    /// 操作を送信（再生が終了している場合は無視）
+    pub fn send(&self, command: ReplayCommand) {
+        let _ = self.tx.send(command);
+    }
+
+    pub fn pause(&self) {
+        self.send(ReplayCommand::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(ReplayCommand::Resume);
+    }
+
+    pub fn step(&self) {
+        self.send(ReplayCommand::Step);
+    }
+
+    /// 記録時刻（UNIX秒）の位置に移動（前後どちらにも移動できる）
+    pub fn seek(&self, time: f64) {
+        self.send(ReplayCommand::Seek(time));
+    }
+
+    /// 再生速度を変更（正の有限の値以外は無視）
+    pub fn set_speed(&self, speed: f64) {
+        if !is_valid_speed(speed) {
+            warn!("Ignoring invalid replay speed {}", speed);
+            return;
+        }
+        self.send(ReplayCommand::Speed(speed));
+    }
+
+    /// 再生の現在の状態と進捗を取得
+    pub fn status(&self) -> ReplayStatus {
+        status_snapshot(&self.status)
+    }
+}
+
+/// 1回分の再生の進捗の計算に使う値
+struct PassProgress {
+    bytes_read: Option<Arc<AtomicU64>>,
+    bytes_total: Option<u64>,
+    end: Option<f64>, // 最後のメッセージの記録時刻（終了時刻またはインデックスから）
+    first: Option<f64>, // 最初に再生したメッセージの記録時刻
+}
+
+/// 記録されたメッセージを元の間隔（速度で調整）で取り出す
+/// MQTTクライアントへの送信は呼び出し側で行うため、独自のクライアントやプロセス内の処理に渡せる
+pub struct Replayer {
+    source: ReplaySource,
+    speed: f64,
+    start_time: Option<String>,
+    end_time: Option<String>,
+    topics: Vec<String>, // 再生するトピックフィルタ（空の場合はすべて）
+    loop_replay: bool,
+    cipher: Option<RecordCipher>,
+    metrics: Metrics,
+    status: Arc<Mutex<SharedStatus>>,
+    commands: Option<mpsc::UnboundedReceiver<ReplayCommand>>,
+}
+
+impl Replayer {
+    pub fn new(source: ReplaySource) -> Self {
+        Replayer {
+            source,
+            speed: 1.0,
+            start_time: None,
+            end_time: None,
+            topics: Vec::new(),
+            loop_replay: false,
+            cipher: None,
+            metrics: Metrics::default(),
+            status: Arc::new(Mutex::new(SharedStatus::new(1.0))),
+            commands: None,
+        }
+    }
+
+    /// 再生速度を設定（2.0で2倍速、正の有限の値以外は無視）
+    pub fn set_speed(&mut self, speed: f64) {
+        if !is_valid_speed(speed) {
+            warn!("Ignoring invalid replay speed {}", speed);
+            return;
+        }
+        self.speed = speed;
+        self.with_status(|status| status.speed = speed);
+    }
+
+    /// 再生する時間範囲を設定（YYYY-MM-DD HH:MM[:SS]、ローカル時刻）
+    pub fn set_time_range(&mut self, start_time: Option<String>, end_time: Option<String>) {
+        self.start_time = start_time;
+        self.end_time = end_time;
+    }
+
+    /// 再生するトピックフィルタを設定（空の場合はすべて）
+    pub fn set_topics(&mut self, topics: Vec<String>) {
+        self.topics = topics;
+    }
+
+    /// 最後まで再生したら最初から繰り返すかを設定
+    pub fn set_loop(&mut self, loop_replay: bool) {
+        self.loop_replay = loop_replay;
+    }
+
+    /// 暗号化された記録ファイルを復号する鍵を設定
+    pub fn set_cipher(&mut self, cipher: Option<RecordCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// 再生したメッセージ数と再生位置を記録するメトリクスを設定
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// 再生を操作するハンドルを作成（一時停止した状態で開始する場合は `paused`）
+    pub fn controller(&mut self, paused: bool) -> ReplayController {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.commands = Some(rx);
+        self.with_status(|status| status.paused = paused);
+        ReplayController {
+            tx,
+            status: self.status.clone(),
+        }
+    }
+
+    /// 再生の状態と進捗を取得するハンドルを作成
+    pub fn monitor(&self) -> ReplayMonitor {
+        ReplayMonitor {
+            status: self.status.clone(),
+        }
+    }
+
+    fn with_status<T>(&self, f: impl FnOnce(&mut ReplayStatus) -> T) -> T {
+        f(&mut self.status.lock().unwrap_or_else(|e| e.into_inner()).status)
+    }
+
+    /// 1回分の再生のメッセージと進捗の計算に使う値を取得（時間範囲とトピックで絞り込み済み）
+    /// `seek` を指定した場合は開始時刻の代わりにその記録時刻から取得
+    fn messages(&self, seek: Option<f64>) -> Result<(ReplayMessages, PassProgress), RecorderError> {
+        let parse_time = |time: &Option<String>| match time {
+            Some(time) => parse_replay_time(time)
+                .map(Some)
+                .ok_or_else(|| RecorderError::InvalidTime(time.clone())),
+            None => Ok(None),
+        };
+        let start_ts = seek.or(parse_time(&self.start_time)?);
+        let end_ts = parse_time(&self.end_time)?;
+
+        let mut progress = PassProgress {
+            bytes_read: None,
+            bytes_total: None,
+            end: end_ts,
+            first: None,
+        };
+        let messages: ReplayMessages = match &self.source {
+            ReplaySource::Directory(directory) => {
+                let files = get_files_in_range(directory, self.start_time.clone(), self.end_time.clone())?;
+                if files.is_empty() {
+                    warn!("No files found in the specified directory or time range");
+                } else if seek.is_none() {
+                    info!("Found {} files to replay", files.len());
+                }
+                self.file_records(files, start_ts, &mut progress)
+            }
+            ReplaySource::Files(files) => self.file_records(files.clone(), start_ts, &mut progress),
+            ReplaySource::Messages(messages) => {
+                Box::new(messages().filter(move |msg| !start_ts.is_some_and(|start| msg.time < start)))
+            }
+        };
+
+        let topics = self.topics.clone();
+        let messages = messages
+            // 時間範囲外のメッセージで終了
+            .take_while(move |msg| !end_ts.is_some_and(|end| msg.time > end))
+            .filter(move |msg| topics.is_empty() || topics.iter().any(|filter| topic_matches_filter(filter, &msg.topic)));
+        Ok((Box::new(messages), progress))
+    }
+
+    /// 記録ファイルを開き、進捗の計算に使う合計サイズと最後の記録時刻を取得
+    fn file_records(&self, files: Vec<PathBuf>, start_ts: Option<f64>, progress: &mut PassProgress) -> ReplayMessages {
+        progress.bytes_total = Some(files.iter().filter_map(|path| fs::metadata(path).ok()).map(|m| m.len()).sum());
+        // 終了時刻がない場合は、すべてのファイルのインデックスから最後のメッセージの時刻を取得
+        if progress.end.is_none() {
+            progress.end = files
+                .iter()
+                .map(|path| read_file_summary(path).map(|summary| summary.last_time))
+                .collect::<Option<Vec<f64>>>()
+                .and_then(|times| times.into_iter().reduce(f64::max));
+        }
+        let records = MergedRecords::new(files, start_ts, self.cipher.clone());
+        progress.bytes_read = Some(records.bytes_read());
+        Box::new(records)
+    }
+
+    /// 再生したメッセージの記録時刻から状態と進捗を更新
+    fn update_progress(&self, progress: &mut PassProgress, time: f64) {
+        let first = *progress.first.get_or_insert(time);
+        let bytes_done = progress.bytes_read.as_ref().map_or(0, |bytes| bytes.load(AtomicOrdering::Relaxed));
+        let percent = progress
+            .bytes_total
+            .filter(|total| *total > 0)
+            .map(|total| (bytes_done as f64 * 100.0 / total as f64).min(100.0));
+        // 最後の記録時刻が分かる場合はその時刻まで、分からない場合は読み込んだ割合から推定
+        let remaining = match (progress.end, percent) {
+            (Some(end), _) => Some((end - time).max(0.0)),
+            (None, Some(percent)) if percent > 0.0 => Some((time - first) * (100.0 - percent) / percent),
+            _ => None,
+        };
+
+        let speed = self.speed;
+        self.with_status(|status| {
+            status.position = Some(time);
+            status.replayed += 1;
+            status.bytes_done = bytes_done;
+            status.bytes_total = progress.bytes_total;
+            status.percent = percent;
+            status.eta_secs = remaining.map(|remaining| remaining / speed);
+        });
+    }
+
+    /// メッセージを予定の時刻に `on_message` に渡す（`false` を返すと再生を終了）
+    pub async fn run<F, Fut>(mut self, mut on_message: F) -> Result<(), RecorderError>
+    where
+        F: FnMut(MqttMessage) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let mut commands = self.commands.take();
+        let mut seek = None;
+        let mut replayed = 0u64; // 今回の再生で再生したメッセージ数（位置の移動後も数え続ける）
+        loop {
+            let mut previous: Option<MessageTime> = None;
+            if seek.is_none() {
+                replayed = 0;
+                self.metrics.replay_loop_started();
+            }
+
+            let (messages, mut progress) = self.messages(seek.take())?;
+            for msg in messages {
+                // 送信間隔は同じ起動の間はモノトニック時刻で計算（NTP補正による時刻の飛びを避ける）
+                let replay_time = msg.message_time();
+                let delay = previous.map_or(0.0, |previous| replay_time.seconds_since(&previous));
+
+                seek = self.wait(&mut commands, delay).await;
+                if seek.is_some() {
+                    break;
+                }
+
+                previous = Some(replay_time);
+                replayed += 1;
+
+                let time = msg.time;
+                if !on_message(msg).await {
+                    return Ok(());
+                }
+                self.metrics.replay_published(time);
+                self.update_progress(&mut progress, time);
+            }
+
+            // 移動した位置から再生を続ける
+            if seek.is_some() {
+                continue;
+            }
+            info!("Replayed {} messages", replayed);
+            // 再生するメッセージがない場合は繰り返さない
+            if !self.loop_replay || replayed == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// 記録時刻で `delay` 秒後（速度で調整）まで待機し、その間に届いた操作を処理
+    /// 位置の移動を指示された場合はその記録時刻を返す
+    async fn wait(&mut self, commands: &mut Option<mpsc::UnboundedReceiver<ReplayCommand>>, delay: f64) -> Option<f64> {
+        let mut remaining = delay.max(0.0);
+        loop {
+            let rx = match commands.as_mut() {
+                Some(rx) => rx,
+                None => {
+                    tokio::time::sleep(delay_duration(remaining / self.speed)).await;
+                    return None;
+                }
+            };
+
+            let command = if self.with_status(|status| status.paused) {
+                rx.recv().await
+            } else if remaining <= 0.0 {
+                return None;
+            } else {
+                let started = Instant::now();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay_duration(remaining / self.speed)) => return None,
+                    command = rx.recv() => {
+                        remaining -= started.elapsed().as_secs_f64() * self.speed;
+                        command
+                    }
+                }
+            };
+
+            match command {
+                Some(ReplayCommand::Pause) => {
+                    info!("Replay paused");
+                    self.with_status(|status| {
+                        status.paused = true;
+                        status.messages_per_sec = 0.0;
+                    });
+                }
+                Some(ReplayCommand::Resume) => {
+                    info!("Replay resumed");
+                    self.with_status(|status| status.paused = false);
+                }
+                Some(ReplayCommand::Step) => {
+                    if self.with_status(|status| status.paused) {
+                        return None;
+                    }
+                }
+                Some(ReplayCommand::Speed(speed)) if is_valid_speed(speed) => {
+                    info!("Replay speed set to {}", speed);
+                    self.speed = speed;
+                    // 残り時間は速度に反比例
+                    self.with_status(|status| {
+                        status.eta_secs = status.eta_secs.map(|eta| eta * status.speed / speed);
+                        status.speed = speed;
+                    });
+                }
+                Some(ReplayCommand::Speed(speed)) => warn!("Ignoring invalid replay speed {}", speed),
+                Some(ReplayCommand::Seek(time)) => {
+                    info!("Replay seeking to {}", format_replay_time(time));
+                    self.with_status(|status| status.position = Some(time));
+                    return Some(time);
+                }
+                // 操作するハンドルがなくなったら、一時停止を解除して通常の再生に戻す
+                None => {
+                    self.with_status(|status| status.paused = false);
+                    *commands = None;
+                }
+            }
+        }
+    }
+
+    /// 別のタスクで再生し、予定の時刻になったメッセージをチャネルで受け取る
+    /// 受信側を閉じると再生を終了する
+    pub fn spawn(self, buffer: usize) -> mpsc::Receiver<MqttMessage> {
+        let (tx, rx) = mpsc::channel(buffer);
+        tokio::spawn(async move {
+            let result = self
+                .run(|msg| {
+                    let tx = tx.clone();
+                    async move { tx.send(msg).await.is_ok() }
+                })
+                .await;
+            if let Err(e) = result {
+                error!("Failed to replay: {}", e);
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mqtt-recorder-replay-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn skips_schema_files_in_recording_tree() {
+        let dir = temp_dir("schema");
+        let topic_dir = dir.join("s/t");
+        fs::create_dir_all(&topic_dir).unwrap();
+        let recording = topic_dir.join("mqtt-recorder-s-t-20250725-100000.json");
+        fs::write(&recording, "").unwrap();
+        fs::write(topic_dir.join("mqtt-recorder-s-t-schema.json"), "{}\n").unwrap();
+        fs::write(topic_dir.join("mqtt-recorder-s-t-schema.jsonl"), "{}\n").unwrap();
+        assert_eq!(get_files_in_range(&dir, None, None).unwrap(), vec![recording]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn record(topic: &str, time: f64, mono: f64, seq: u64, run: u32) -> String {
+        serde_json::to_string(&MqttMessage {
+            time,
+            qos: 0,
+            retain: false,
+            topic: topic.to_string(),
+            msg_b64: String::new(),
+            seq: Some(seq),
+            mono: Some(mono),
+            run: Some(run),
+            chain: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn merges_files_by_monotonic_time_within_a_run_and_wall_time_across_runs() {
+        let dir = temp_dir("merge");
+        let files = vec![dir.join("a.json"), dir.join("b.json"), dir.join("c.json")];
+        let contents = [
+            // 2件目の前に壁時計が戻った
+            vec![record("a", 100.0, 100.0, 0, 1), record("a", 99.9, 100.3, 3, 1)],
+            vec![record("b", 100.1, 100.1, 1, 1), record("b", 100.3, 100.3, 4, 1)],
+            // 同時に動いていた別の起動（モノトニック時刻の起点は無関係）
+            vec![record("c", 100.2, 5000.0, 0, 2), record("c", 100.35, 5000.15, 1, 2)],
+        ];
+        for (path, lines) in files.iter().zip(&contents) {
+            fs::write(path, lines.join("\n") + "\n").unwrap();
+        }
+
+        let order: Vec<(String, u64)> = MergedRecords::new(files, None, None)
+            .map(|msg| (msg.topic, msg.seq.unwrap()))
+            .collect();
+        let expected = [("a", 0), ("b", 1), ("c", 0), ("a", 3), ("b", 4), ("c", 1)];
+        let expected: Vec<(String, u64)> = expected.iter().map(|(topic, seq)| (topic.to_string(), *seq)).collect();
+        assert_eq!(order, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn measures_replay_delays_per_run() {
+        let time = |time, mono, run| MessageTime { time, mono: Some(mono), run: Some(run) };
+        assert!((time(99.9, 100.3, 1).seconds_since(&time(100.0, 100.0, 1)) - 0.3).abs() < 1e-9);
+        assert!((time(100.2, 5000.0, 2).seconds_since(&time(100.0, 100.0, 1)) - 0.2).abs() < 1e-9);
+        let legacy = MessageTime { time: 101.0, mono: None, run: None };
+        assert!((legacy.seconds_since(&time(100.0, 100.5, 1)) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn recomputes_rate_when_status_is_read() {
+        let mut shared = SharedStatus::new(1.0);
+        shared.status.replayed = 10;
+        shared.rate_started -= Duration::from_secs(2);
+        assert!((shared.snapshot().messages_per_sec - 5.0).abs() < 0.1);
+        // 前回の計算の直後は前回の値を返す
+        assert!((shared.snapshot().messages_per_sec - 5.0).abs() < 0.1);
+        // 再生が止まるとレートは0に下がる
+        shared.rate_started -= Duration::from_secs(2);
+        assert_eq!(shared.snapshot().messages_per_sec, 0.0);
+    }
+
+    #[test]
+    fn rejects_invalid_speeds() {
+        let mut replayer = Replayer::new(ReplaySource::Files(Vec::new()));
+        replayer.set_speed(2.0);
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            replayer.set_speed(speed);
+            assert_eq!(replayer.speed, 2.0);
+        }
+        assert_eq!(delay_duration(f64::NAN), Duration::ZERO);
+        assert_eq!(delay_duration(-1.0), Duration::ZERO);
+        assert_eq!(delay_duration(f64::INFINITY), Duration::MAX);
+        assert_eq!(delay_duration(1.5), Duration::from_millis(1500));
+    }
+}
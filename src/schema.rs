@@ -0,0 +1,359 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+/// JSONの値の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueKind {
+    Null,
+    Boolean,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl ValueKind {
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => ValueKind::Null,
+            Value::Bool(_) => ValueKind::Boolean,
+            Value::Number(_) => ValueKind::Number,
+            Value::String(_) => ValueKind::String,
+            Value::Array(_) => ValueKind::Array,
+            Value::Object(_) => ValueKind::Object,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueKind::Null => "null",
+            ValueKind::Boolean => "boolean",
+            ValueKind::Number => "number",
+            ValueKind::String => "string",
+            ValueKind::Array => "array",
+            ValueKind::Object => "object",
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// キーパスごとの推定スキーマ（配列の要素は `path[]` にまとめる）
+#[derive(Debug, Clone, Serialize)]
+pub struct KeySchema {
+    /// null以外で観測された型
+    pub types: BTreeSet<ValueKind>,
+    pub nullable: bool,
+    /// 存在しないメッセージがあった
+    pub optional: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+    #[serde(skip)]
+    parent: String,
+    #[serde(skip)]
+    in_array: bool, // 配列の要素（空の配列では存在しないため欠落を検出しない）
+}
+
+impl KeySchema {
+    fn new(parent: &str, in_array: bool, optional: bool) -> Self {
+        KeySchema {
+            types: BTreeSet::new(),
+            nullable: false,
+            optional,
+            min_items: None,
+            max_items: None,
+            parent: parent.to_string(),
+            in_array,
+        }
+    }
+}
+
+/// スキーマの変化の種類
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum DriftKind {
+    /// 以前のメッセージになかったキーパス
+    NewKey { observed: ValueKind },
+    /// これまで常に存在していたキーパスが欠落
+    RemovedKey,
+    /// これまでと異なる型の値
+    TypeChange {
+        previous: Vec<ValueKind>,
+        observed: ValueKind,
+    },
+}
+
+/// スキーマの変化
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftEvent {
+    pub topic: String,
+    /// 変化を検出したメッセージの時刻（UNIX秒）
+    pub time: f64,
+    pub key: String,
+    #[serde(flatten)]
+    pub kind: DriftKind,
+}
+
+impl fmt::Display for DriftEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            DriftKind::NewKey { observed } => {
+                write!(f, "new key '{}' ({}) on '{}'", self.key, observed, self.topic)
+            }
+            DriftKind::RemovedKey => write!(f, "key '{}' missing on '{}'", self.key, self.topic),
+            DriftKind::TypeChange { previous, observed } => {
+                let previous: Vec<&str> = previous.iter().map(|kind| kind.name()).collect();
+                write!(
+                    f,
+                    "key '{}' changed type from {} to {} on '{}'",
+                    self.key,
+                    previous.join("|"),
+                    observed,
+                    self.topic
+                )
+            }
+        }
+    }
+}
+
+/// トピックごとのスキーマ推定と変化の検出
+#[derive(Debug, Clone, Serialize)]
+pub struct TopicSchema {
+    #[serde(skip)]
+    topic: String,
+    /// 推定に使用したメッセージ数
+    pub messages: u64,
+    pub keys: BTreeMap<String, KeySchema>,
+}
+
+impl TopicSchema {
+    pub fn new(topic: &str) -> Self {
+        TopicSchema {
+            topic: topic.to_string(),
+            messages: 0,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// JSONのペイロードを観測し、スキーマを更新して変化を返す（最初のメッセージは変化として扱わない）
+    pub fn observe(&mut self, payload: &Value, time: f64) -> Vec<DriftEvent> {
+        let mut events = Vec::new();
+        let mut seen = HashSet::new();
+        let first = self.messages == 0;
+        self.walk("", "", false, payload, first, time, &mut seen, &mut events);
+
+        // 親が存在するのに欠落した必須のキーパス（親ごと欠落した場合は任意にするのみ）
+        if !first {
+            for (key, schema) in self.keys.iter_mut() {
+                if schema.optional || seen.contains(key.as_str()) {
+                    continue;
+                }
+                schema.optional = true;
+                let parent_present = schema.parent.is_empty() || seen.contains(schema.parent.as_str());
+                if parent_present && !schema.in_array {
+                    events.push(DriftEvent {
+                        topic: self.topic.clone(),
+                        time,
+                        key: key.clone(),
+                        kind: DriftKind::RemovedKey,
+                    });
+                }
+            }
+        }
+
+        self.messages += 1;
+        events
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        path: &str,
+        parent: &str,
+        in_array: bool,
+        value: &Value,
+        first: bool,
+        time: f64,
+        seen: &mut HashSet<String>,
+        events: &mut Vec<DriftEvent>,
+    ) {
+        if !path.is_empty() {
+            self.record(path, parent, in_array, value, first, time, events);
+            seen.insert(path.to_string());
+        }
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map {
+                    let child_path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    self.walk(&child_path, path, in_array, child, first, time, seen, events);
+                }
+            }
+            Value::Array(items) => {
+                let child_path = format!("{}[]", path);
+                for child in items {
+                    self.walk(&child_path, path, true, child, first, time, seen, events);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &mut self,
+        path: &str,
+        parent: &str,
+        in_array: bool,
+        value: &Value,
+        first: bool,
+        time: f64,
+        events: &mut Vec<DriftEvent>,
+    ) {
+        let kind = ValueKind::of(value);
+        let is_new = !self.keys.contains_key(path);
+        let schema = self
+            .keys
+            .entry(path.to_string())
+            .or_insert_with(|| KeySchema::new(parent, in_array, !first));
+
+        if let Value::Array(items) = value {
+            schema.min_items = Some(schema.min_items.map_or(items.len(), |min| min.min(items.len())));
+            schema.max_items = Some(schema.max_items.map_or(items.len(), |max| max.max(items.len())));
+        }
+
+        if kind == ValueKind::Null {
+            schema.nullable = true;
+            if is_new && !first {
+                events.push(self.event(path, time, DriftKind::NewKey { observed: kind }));
+            }
+            return;
+        }
+        if schema.types.contains(&kind) {
+            return;
+        }
+
+        let previous: Vec<ValueKind> = schema.types.iter().cloned().collect();
+        schema.types.insert(kind);
+        if first {
+            return;
+        }
+        let drift = if is_new {
+            DriftKind::NewKey { observed: kind }
+        } else if previous.is_empty() {
+            // これまでnullのみだったキーパスに値が入った場合は変化として扱わない
+            return;
+        } else {
+            DriftKind::TypeChange { previous, observed: kind }
+        };
+        events.push(self.event(path, time, drift));
+    }
+
+    fn event(&self, key: &str, time: f64, kind: DriftKind) -> DriftEvent {
+        DriftEvent {
+            topic: self.topic.clone(),
+            time,
+            key: key.to_string(),
+            kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn drift(events: &[DriftEvent]) -> Vec<(&str, DriftKind)> {
+        events.iter().map(|event| (event.key.as_str(), event.kind.clone())).collect()
+    }
+
+    #[test]
+    fn first_message_sets_baseline_without_events() {
+        let mut schema = TopicSchema::new("t");
+        assert!(schema.observe(&json!({"a": 1, "b": {"c": "x"}, "d": [1, 2]}), 1.0).is_empty());
+        assert!(schema.observe(&json!({"a": 2, "b": {"c": "y"}, "d": [3]}), 2.0).is_empty());
+        assert_eq!(schema.messages, 2);
+        let keys: Vec<&str> = schema.keys.keys().map(|key| key.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b", "b.c", "d", "d[]"]);
+        assert_eq!(schema.keys["d"].min_items, Some(1));
+        assert_eq!(schema.keys["d"].max_items, Some(2));
+    }
+
+    #[test]
+    fn reports_new_removed_and_retyped_keys() {
+        let mut schema = TopicSchema::new("t");
+        schema.observe(&json!({"a": 1, "b": "x"}), 1.0);
+
+        let events = schema.observe(&json!({"a": 1, "b": "x", "c": true}), 2.0);
+        assert_eq!(drift(&events), vec![("c", DriftKind::NewKey { observed: ValueKind::Boolean })]);
+        assert_eq!(events[0].topic, "t");
+        assert_eq!(events[0].time, 2.0);
+        assert!(schema.keys["c"].optional);
+
+        let events = schema.observe(&json!({"a": "1", "c": true}), 3.0);
+        assert_eq!(
+            drift(&events),
+            vec![
+                (
+                    "a",
+                    DriftKind::TypeChange {
+                        previous: vec![ValueKind::Number],
+                        observed: ValueKind::String
+                    }
+                ),
+                ("b", DriftKind::RemovedKey),
+            ]
+        );
+        assert_eq!(
+            events[0].to_string(),
+            "key 'a' changed type from number to string on 't'"
+        );
+
+        // 一度報告した変化は繰り返さない
+        assert!(schema.observe(&json!({"a": 2, "c": false}), 4.0).is_empty());
+        let types: Vec<ValueKind> = schema.keys["a"].types.iter().cloned().collect();
+        assert_eq!(types, vec![ValueKind::Number, ValueKind::String]);
+    }
+
+    #[test]
+    fn nulls_and_missing_parents_are_not_drift() {
+        let mut schema = TopicSchema::new("t");
+        schema.observe(&json!({"a": null, "b": {"c": 1}, "d": [{"e": 1}]}), 1.0);
+        // nullのみだったキーに値が入っても変化ではない
+        // 親ごと欠落したキーと空の配列の要素は欠落として報告しない
+        let events = schema.observe(&json!({"a": 5, "d": []}), 2.0);
+        assert_eq!(drift(&events), vec![("b", DriftKind::RemovedKey)]);
+        assert!(schema.keys["a"].nullable);
+        assert!(schema.keys["b.c"].optional);
+        assert!(schema.keys["d[].e"].optional);
+    }
+
+    #[test]
+    fn serializes_events_with_kind_tag() {
+        let event = DriftEvent {
+            topic: "t".to_string(),
+            time: 1.5,
+            key: "a".to_string(),
+            kind: DriftKind::NewKey { observed: ValueKind::Number },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({"topic": "t", "time": 1.5, "key": "a", "event": "new_key", "observed": "number"})
+        );
+    }
+}
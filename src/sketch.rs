@@ -0,0 +1,460 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+/// 正確なユニーク数を保持する最大要素数（これを超えるとHyperLogLogに切り替え）
+pub const DISTINCT_EXACT_LIMIT: usize = 1024;
+
+/// HyperLogLogの精度（レジスタ数 = 2^HLL_PRECISION、標準誤差は約1.6%）
+const HLL_PRECISION: u32 = 12;
+
+/// Welford法による平均・分散の逐次計算（メモリ使用量は一定）
+#[derive(Debug, Clone)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        RunningStats {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// 値を追加
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// 最小値（値がない場合は0）
+    pub fn min(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.min }
+    }
+
+    /// 最大値（値がない場合は0）
+    pub fn max(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.max }
+    }
+
+    /// 母標準偏差
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// 母分散（値がない場合は0）
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// P²アルゴリズムによる分位数の逐次推定（5つのマーカーのみ保持）
+#[derive(Debug, Clone)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    /// `p` は 0.0〜1.0 の分位（例：0.95）
+    pub fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    /// 値を追加
+    pub fn push(&mut self, value: f64) {
+        // 最初の5つの値はそのまま保持
+        if self.count < 5 {
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(|a, b| a.total_cmp(b));
+            }
+            return;
+        }
+        self.count += 1;
+
+        // 値が入るセルを探し、必要なら両端のマーカーを更新
+        let cell = if value < self.heights[0] {
+            self.heights[0] = value;
+            0
+        } else if value >= self.heights[4] {
+            self.heights[4] = value;
+            3
+        } else {
+            (1..5).find(|&i| value < self.heights[i]).unwrap_or(4) - 1
+        };
+        for position in self.positions.iter_mut().skip(cell + 1) {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments.iter()) {
+            *desired += increment;
+        }
+
+        // 中間のマーカーを理想的な位置に近づける
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            let can_move_up = d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0;
+            let can_move_down = d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0;
+            if can_move_up || can_move_down {
+                let d = d.signum();
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                    parabolic
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i] + d * (self.heights[j] - self.heights[i]) / (self.positions[j] - self.positions[i])
+    }
+
+    /// 分位数の推定値（値が5つ未満の場合は保持している値から計算）
+    pub fn estimate(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        if self.count == 0 {
+            return 0.0;
+        }
+        let mut values = self.heights[..self.count].to_vec();
+        values.sort_by(|a, b| a.total_cmp(b));
+        values[(self.p * (self.count - 1) as f64).round() as usize]
+    }
+}
+
+/// Space-Savingアルゴリズムによる頻出値の集計（保持する値の数は一定）
+#[derive(Debug, Clone)]
+pub struct TopK {
+    capacity: usize,
+    counters: Vec<(String, u64)>,
+}
+
+impl TopK {
+    /// `capacity` 個までの値を追跡する（上位k件の精度を上げるため k より大きくする）
+    pub fn new(capacity: usize) -> Self {
+        TopK {
+            capacity: capacity.max(1),
+            counters: Vec::new(),
+        }
+    }
+
+    /// 値を追加
+    pub fn insert(&mut self, value: &str) {
+        if let Some(counter) = self.counters.iter_mut().find(|(v, _)| v == value) {
+            counter.1 += 1;
+        } else if self.counters.len() < self.capacity {
+            self.counters.push((value.to_string(), 1));
+        } else if let Some(min) = self.counters.iter_mut().min_by_key(|(_, count)| *count) {
+            // 最小のカウンタを置き換える（カウントは上限値として引き継ぐ）
+            *min = (value.to_string(), min.1 + 1);
+        }
+    }
+
+    /// 出現回数の多い順に上位k件を取得
+    pub fn top(&self, k: usize) -> Vec<(String, u64)> {
+        let mut counters = self.counters.clone();
+        counters.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counters.truncate(k);
+        counters
+    }
+}
+
+/// HyperLogLogによるユニーク数の推定
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    /// ハッシュ値を追加
+    pub fn insert_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// ユニーク数の推定値
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+
+        // 小さい値では線形カウンティングで補正
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ユニーク数カウンタ（少数のうちは正確なハッシュ集合、上限を超えるとHyperLogLog）
+#[derive(Debug, Clone)]
+pub enum DistinctCounter {
+    Exact(HashSet<u64>),
+    Approximate(HyperLogLog),
+}
+
+impl DistinctCounter {
+    pub fn new() -> Self {
+        DistinctCounter::Exact(HashSet::new())
+    }
+
+    /// 値を追加
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        let hash = hash_value(value);
+        match self {
+            DistinctCounter::Exact(set) => {
+                set.insert(hash);
+                if set.len() > DISTINCT_EXACT_LIMIT {
+                    let mut hll = HyperLogLog::new();
+                    for hash in set.iter() {
+                        hll.insert_hash(*hash);
+                    }
+                    *self = DistinctCounter::Approximate(hll);
+                }
+            }
+            DistinctCounter::Approximate(hll) => hll.insert_hash(hash),
+        }
+    }
+
+    /// ユニーク数（上限を超えた後は推定値）
+    pub fn count(&self) -> f64 {
+        match self {
+            DistinctCounter::Exact(set) => set.len() as f64,
+            DistinctCounter::Approximate(hll) => hll.estimate().round(),
+        }
+    }
+}
+
+impl Default for DistinctCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ヘルパー関数：値の64ビットハッシュを計算（固定キーのSipHash）
+fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 再現性のある疑似乱数（xorshift64*）
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// [0, 1) の一様分布
+        fn uniform(&mut self) -> f64 {
+            (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        /// 標準正規分布（Box-Muller法）
+        fn normal(&mut self) -> f64 {
+            let u1 = 1.0 - self.uniform();
+            let u2 = self.uniform();
+            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+        }
+    }
+
+    fn quantiles(values: impl Iterator<Item = f64>) -> (f64, f64) {
+        let mut p50 = P2Quantile::new(0.50);
+        let mut p99 = P2Quantile::new(0.99);
+        for value in values {
+            p50.push(value);
+            p99.push(value);
+        }
+        (p50.estimate(), p99.estimate())
+    }
+
+    #[test]
+    fn running_stats_match_exact_values() {
+        let mut stats = RunningStats::new();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.push(value);
+        }
+        assert_eq!(stats.count(), 8);
+        assert!((stats.mean() - 5.0).abs() < 1e-12);
+        assert!((stats.stddev() - 2.0).abs() < 1e-12);
+        assert_eq!((stats.min(), stats.max()), (2.0, 9.0));
+        assert_eq!(RunningStats::new().min(), 0.0);
+    }
+
+    #[test]
+    fn p2_estimates_quantiles_of_known_distributions() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        // 一様分布 [0, 100): 中央値 50、p99 99
+        let (p50, p99) = quantiles((0..100_000).map(|_| rng.uniform() * 100.0));
+        assert!((p50 - 50.0).abs() < 1.0, "uniform p50 {}", p50);
+        assert!((p99 - 99.0).abs() < 0.5, "uniform p99 {}", p99);
+
+        // 標準正規分布: 中央値 0、p99 2.326
+        let (p50, p99) = quantiles((0..100_000).map(|_| rng.normal()));
+        assert!(p50.abs() < 0.02, "normal p50 {}", p50);
+        assert!((p99 - 2.326).abs() < 0.05, "normal p99 {}", p99);
+
+        // 指数分布（平均 1）: 中央値 ln 2、p99 ln 100
+        let (p50, p99) = quantiles((0..100_000).map(|_| -(1.0 - rng.uniform()).ln()));
+        assert!((p50 - 2f64.ln()).abs() < 0.02, "exponential p50 {}", p50);
+        assert!((p99 - 100f64.ln()).abs() < 0.1, "exponential p99 {}", p99);
+    }
+
+    #[test]
+    fn p2_uses_exact_values_below_five_samples() {
+        let mut p50 = P2Quantile::new(0.5);
+        assert_eq!(p50.estimate(), 0.0);
+        for value in [3.0, 1.0, 2.0] {
+            p50.push(value);
+        }
+        assert_eq!(p50.estimate(), 2.0);
+    }
+
+    #[test]
+    fn top_k_finds_heavy_hitters() {
+        let mut rng = Rng(42);
+        let mut top = TopK::new(20);
+        for i in 0..100_000u64 {
+            // 3つの頻出値（30%、20%、10%）と多数の低頻度の値
+            let r = rng.uniform();
+            let value = if r < 0.3 {
+                "a".to_string()
+            } else if r < 0.5 {
+                "b".to_string()
+            } else if r < 0.6 {
+                "c".to_string()
+            } else {
+                format!("rare-{}", i % 5000)
+            };
+            top.insert(&value);
+        }
+        let top = top.top(3);
+        let names: Vec<&str> = top.iter().map(|(value, _)| value.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        // Space-Savingのカウントは過大評価のみで、誤差は最大で 値の数 / capacity
+        assert!(top[0].1 >= 29_000 && top[0].1 <= 31_000 + 100_000 / 20, "{:?}", top);
+    }
+
+    #[test]
+    fn hyperloglog_error_is_within_two_percent() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..100_000u64 {
+            hll.insert_hash(hash_value(&i));
+        }
+        let error = (hll.estimate() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.02, "estimate {} (error {:.3})", hll.estimate(), error);
+
+        // 少数の値は線形カウンティングでほぼ正確
+        let mut hll = HyperLogLog::new();
+        for i in 0..100u64 {
+            hll.insert_hash(hash_value(&i));
+        }
+        assert!((hll.estimate() - 100.0).abs() < 2.0, "estimate {}", hll.estimate());
+    }
+
+    #[test]
+    fn distinct_counter_switches_to_hyperloglog_above_limit() {
+        let mut counter = DistinctCounter::new();
+        for i in 0..DISTINCT_EXACT_LIMIT {
+            counter.insert(&i);
+            counter.insert(&i);
+        }
+        assert!(matches!(counter, DistinctCounter::Exact(_)));
+        assert_eq!(counter.count(), DISTINCT_EXACT_LIMIT as f64);
+
+        counter.insert(&DISTINCT_EXACT_LIMIT);
+        assert!(matches!(counter, DistinctCounter::Approximate(_)));
+        let count = counter.count();
+        assert!((count - 1025.0).abs() / 1025.0 < 0.05, "count {}", count);
+
+        // 標準誤差は約1.6%のため、約3倍を許容
+        for i in 0..50_000usize {
+            counter.insert(&i);
+        }
+        let count = counter.count();
+        assert!((count - 50_000.0).abs() / 50_000.0 < 0.05, "count {}", count);
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use crate::protobuf::{repeated_varints, FieldReader, WireValue};
+
+/// Templateの入れ子の最大の深さ（不正なデータによるスタックオーバーフローを防ぐ）
+const MAX_DEPTH: usize = 32;
+
+/// Sparkplug Bのトピックの名前空間
+pub const SPARKPLUG_NAMESPACE: &str = "spBv1.0";
+
+/// Sparkplug Bのトピック（`spBv1.0/<group>/<message type>/<edge node>[/<device>]`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparkplugTopic {
+    pub group: String,
+    pub message_type: String,
+    pub edge_node: String,
+    pub device: Option<String>,
+}
+
+impl SparkplugTopic {
+    /// Protocol Buffersのペイロードを持つトピックを解析（`STATE` などはNone）
+    pub fn parse(topic: &str) -> Option<Self> {
+        let mut levels = topic.split('/');
+        if levels.next()? != SPARKPLUG_NAMESPACE {
+            return None;
+        }
+        let group = levels.next()?;
+        let message_type = levels.next()?;
+        let edge_node = levels.next()?;
+        let device = levels.next();
+        if levels.next().is_some() {
+            return None;
+        }
+        let is_device = matches!(message_type, "DBIRTH" | "DDEATH" | "DDATA" | "DCMD");
+        let is_node = matches!(message_type, "NBIRTH" | "NDEATH" | "NDATA" | "NCMD");
+        if !(is_device && device.is_some() || is_node && device.is_none()) {
+            return None;
+        }
+        Some(SparkplugTopic {
+            group: group.to_string(),
+            message_type: message_type.to_string(),
+            edge_node: edge_node.to_string(),
+            device: device.map(|d| d.to_string()),
+        })
+    }
+
+    /// エイリアスの定義の範囲（エッジノードまたはデバイス）
+    fn alias_scope(&self) -> String {
+        match &self.device {
+            Some(device) => format!("{}/{}/{}", self.group, self.edge_node, device),
+            None => format!("{}/{}", self.group, self.edge_node),
+        }
+    }
+
+    pub fn is_birth(&self) -> bool {
+        self.message_type == "NBIRTH" || self.message_type == "DBIRTH"
+    }
+}
+
+/// Sparkplug Bのメトリクス
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparkplugMetric {
+    pub name: Option<String>,
+    pub alias: Option<u64>,
+    pub timestamp: Option<u64>,
+    pub datatype: u32,
+    pub is_historical: bool,
+    pub value: Value,
+}
+
+/// Sparkplug Bのペイロード
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparkplugPayload {
+    pub timestamp: Option<u64>,
+    pub seq: Option<u64>,
+    pub metrics: Vec<SparkplugMetric>,
+}
+
+impl SparkplugPayload {
+    /// Protocol Buffersのペイロードを解析
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut payload = SparkplugPayload::default();
+        for field in FieldReader::new(data) {
+            match field? {
+                (1, value) => payload.timestamp = value.as_u64(),
+                (2, WireValue::LengthDelimited(bytes)) => payload.metrics.push(decode_metric(bytes, 0)?),
+                (3, value) => payload.seq = value.as_u64(),
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+}
+
+// Sparkplug Bの符号付き整数のデータ型（値は符号なしのフィールドに2の補数で格納される）
+const DATATYPE_INT8: u32 = 1;
+const DATATYPE_INT16: u32 = 2;
+const DATATYPE_INT32: u32 = 3;
+const DATATYPE_INT64: u32 = 4;
+
+fn decode_metric(data: &[u8], depth: usize) -> Result<SparkplugMetric, String> {
+    if depth > MAX_DEPTH {
+        return Err("Sparkplug template nested too deeply".to_string());
+    }
+    let mut metric = SparkplugMetric {
+        name: None,
+        alias: None,
+        timestamp: None,
+        datatype: 0,
+        is_historical: false,
+        value: Value::Null,
+    };
+    let mut is_null = false;
+    let mut raw_value = None;
+    for field in FieldReader::new(data) {
+        match field? {
+            (1, value) => metric.name = value.as_str().map(|s| s.to_string()),
+            (2, value) => metric.alias = value.as_u64(),
+            (3, value) => metric.timestamp = value.as_u64(),
+            (4, value) => metric.datatype = value.as_u64().unwrap_or_default() as u32,
+            (5, value) => metric.is_historical = value.as_u64() == Some(1),
+            (7, value) => is_null = value.as_u64() == Some(1),
+            (field @ 10..=19, value) => raw_value = Some((field, value)),
+            _ => {}
+        }
+    }
+    if !is_null {
+        if let Some((field, value)) = raw_value {
+            metric.value = metric_value(field, &value, metric.datatype, depth)?;
+        }
+    }
+    Ok(metric)
+}
+
+/// メトリクスの値をJSONに変換（整数はデータ型に従って符号付きに変換）
+fn metric_value(field: u32, value: &WireValue, datatype: u32, depth: usize) -> Result<Value, String> {
+    Ok(match field {
+        10 => {
+            let v = value.as_u64().unwrap_or_default() as u32;
+            match datatype {
+                DATATYPE_INT8 => json!(v as u8 as i8),
+                DATATYPE_INT16 => json!(v as u16 as i16),
+                DATATYPE_INT32 => json!(v as i32),
+                _ => json!(v),
+            }
+        }
+        11 => {
+            let v = value.as_u64().unwrap_or_default();
+            if datatype == DATATYPE_INT64 {
+                json!(v as i64)
+            } else {
+                json!(v)
+            }
+        }
+        12 => value.as_f32().map_or(Value::Null, |v| json!(v)),
+        13 => value.as_f64().map_or(Value::Null, |v| json!(v)),
+        14 => json!(value.as_u64() == Some(1)),
+        15 => value.as_str().map_or(Value::Null, |s| json!(s)),
+        16 => value.as_bytes().map_or(Value::Null, |b| json!(base64::encode(b))),
+        17 => value.as_bytes().map_or(Ok(Value::Null), |data| decode_dataset(data, depth + 1))?,
+        18 => value.as_bytes().map_or(Ok(Value::Null), |data| decode_template(data, depth + 1))?,
+        _ => Value::Null,
+    })
+}
+
+/// DataSetを行ごとのオブジェクトの配列に変換
+fn decode_dataset(data: &[u8], depth: usize) -> Result<Value, String> {
+    let mut columns = Vec::new();
+    let mut types = Vec::new();
+    let mut rows = Vec::new();
+    for field in FieldReader::new(data) {
+        match field? {
+            (2, value) => columns.push(value.as_str().unwrap_or_default().to_string()),
+            (3, value) => types.extend(repeated_varints(&value)?),
+            (4, WireValue::LengthDelimited(row)) => rows.push(row),
+            _ => {}
+        }
+    }
+
+    let mut result = Vec::new();
+    for row in rows {
+        let mut object = Map::new();
+        let mut column = 0;
+        for element in FieldReader::new(row) {
+            if let (1, WireValue::LengthDelimited(element)) = element? {
+                let datatype = types.get(column).copied().unwrap_or_default() as u32;
+                let mut value = Value::Null;
+                for field in FieldReader::new(element) {
+                    let (number, raw) = field?;
+                    // DataSetValueのフィールド番号はMetricの値のフィールド番号から9を引いたもの
+                    value = metric_value(number.saturating_add(9), &raw, datatype, depth)?;
+                }
+                let name = columns.get(column).cloned().unwrap_or_else(|| column.to_string());
+                object.insert(name, value);
+                column += 1;
+            }
+        }
+        result.push(Value::Object(object));
+    }
+    Ok(Value::Array(result))
+}
+
+/// Templateをメトリクス名のオブジェクトに変換
+fn decode_template(data: &[u8], depth: usize) -> Result<Value, String> {
+    let mut object = Map::new();
+    for field in FieldReader::new(data) {
+        if let (2, WireValue::LengthDelimited(bytes)) = field? {
+            let metric = decode_metric(bytes, depth)?;
+            if let Some(name) = metric.name {
+                insert_metric(&mut object, &name, metric.value);
+            }
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+/// ヘルパー関数：`/` で区切られたメトリクス名を入れ子のオブジェクトに追加
+fn insert_metric(object: &mut Map<String, Value>, name: &str, value: Value) {
+    let mut levels: Vec<&str> = name.split('/').filter(|level| !level.is_empty()).collect();
+    let leaf = match levels.pop() {
+        Some(leaf) => leaf,
+        None => return,
+    };
+    let mut current = object;
+    for level in levels {
+        let entry = current.entry(level.to_string()).or_insert_with(|| Value::Object(Map::new()));
+        if !entry.is_object() {
+            // フォルダと同名のメトリクスがある場合はフォルダを優先
+            *entry = Value::Object(Map::new());
+        }
+        current = entry.as_object_mut().unwrap();
+    }
+    current.insert(leaf.to_string(), value);
+}
+
+/// Sparkplug Bのデコーダー（NBIRTH と DBIRTH で定義されたエイリアスを記録全体で解決）
+#[derive(Debug, Default)]
+pub struct SparkplugDecoder {
+    aliases: HashMap<String, HashMap<u64, String>>, // エッジノードまたはデバイス -> エイリアス -> メトリクス名
+}
+
+impl SparkplugDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ペイロードをメトリクス名（`/` は階層）のJSONオブジェクトに変換（Sparkplug Bのトピックでない場合はNone）
+    pub fn decode(&mut self, topic: &str, data: &[u8]) -> Option<Result<Value, String>> {
+        let topic = SparkplugTopic::parse(topic)?;
+        let payload = match SparkplugPayload::decode(data) {
+            Ok(payload) => payload,
+            Err(e) => return Some(Err(e)),
+        };
+        let scope = topic.alias_scope();
+
+        // BIRTHはエイリアスを定義し直す
+        if topic.is_birth() {
+            let aliases: HashMap<u64, String> = payload
+                .metrics
+                .iter()
+                .filter_map(|metric| Some((metric.alias?, metric.name.clone()?)))
+                .collect();
+            self.aliases.insert(scope.clone(), aliases);
+        }
+
+        let mut object = Map::new();
+        for metric in payload.metrics {
+            let name = match (metric.name, metric.alias) {
+                (Some(name), _) => name,
+                (None, Some(alias)) => self
+                    .aliases
+                    .get(&scope)
+                    .and_then(|aliases| aliases.get(&alias))
+                    .cloned()
+                    .unwrap_or_else(|| format!("alias_{}", alias)),
+                (None, None) => continue,
+            };
+            insert_metric(&mut object, &name, metric.value);
+        }
+        Some(Ok(Value::Object(object)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::encoding::{bytes, string, uint32, uint64};
+
+    fn metric(name: &str, datatype: u32, value: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut buf = Vec::new();
+        string::encode(1, &name.to_string(), &mut buf);
+        uint32::encode(4, &datatype, &mut buf);
+        value(&mut buf);
+        buf
+    }
+
+    fn payload(metrics: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        uint64::encode(1, &1_700_000_000_000, &mut buf);
+        for metric in metrics {
+            bytes::encode(2, metric, &mut buf);
+        }
+        uint64::encode(3, &7, &mut buf);
+        buf
+    }
+
+    /// `depth` 段の入れ子のTemplateを持つメトリクス
+    fn nested_template(depth: usize) -> Vec<u8> {
+        let mut inner = metric("leaf", 3, |buf| uint32::encode(10, &1, buf));
+        for _ in 0..depth {
+            let mut template = Vec::new();
+            bytes::encode(2, &inner, &mut template);
+            inner = metric("t", 19, |buf| bytes::encode(18, &template, buf));
+        }
+        inner
+    }
+
+    #[test]
+    fn decodes_typed_metrics() {
+        let data = payload(&[
+            metric("Inputs/Temperature", 10, |buf| bytes::encode(13, &21.5f64.to_le_bytes().to_vec(), buf)),
+            metric("Inputs/Offset", 1, |buf| uint32::encode(10, &0xff, buf)),
+            metric("Counter", 4, |buf| uint64::encode(11, &u64::MAX, buf)),
+            metric("Label", 12, |buf| string::encode(15, &"ok".to_string(), buf)),
+        ]);
+        let payload = SparkplugPayload::decode(&data).unwrap();
+        assert_eq!(payload.timestamp, Some(1_700_000_000_000));
+        assert_eq!(payload.seq, Some(7));
+        assert_eq!(payload.metrics.len(), 4);
+        assert_eq!(payload.metrics[1].value, json!(-1));
+        assert_eq!(payload.metrics[2].value, json!(-1));
+        assert_eq!(payload.metrics[3].value, json!("ok"));
+    }
+
+    #[test]
+    fn resolves_aliases_from_birth() {
+        let mut decoder = SparkplugDecoder::new();
+        let birth = payload(&[{
+            let mut m = metric("Inputs/Count", 3, |buf| uint32::encode(10, &1, buf));
+            uint64::encode(2, &5, &mut m);
+            m
+        }]);
+        decoder.decode("spBv1.0/plant/NBIRTH/edge1", &birth).unwrap().unwrap();
+
+        let mut data_metric = Vec::new();
+        uint64::encode(2, &5, &mut data_metric);
+        uint32::encode(4, &3, &mut data_metric);
+        uint32::encode(10, &9, &mut data_metric);
+        let value = decoder.decode("spBv1.0/plant/NDATA/edge1", &payload(&[data_metric])).unwrap().unwrap();
+        assert_eq!(value, json!({"Inputs": {"Count": 9}}));
+        assert!(decoder.decode("spBv1.0/plant/STATE/edge1", &[]).is_none());
+    }
+
+    #[test]
+    fn decodes_nested_templates() {
+        let value = SparkplugDecoder::new()
+            .decode("spBv1.0/g/NDATA/e", &payload(&[nested_template(2)]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, json!({"t": {"t": {"leaf": 1}}}));
+    }
+
+    #[test]
+    fn rejects_deeply_nested_templates() {
+        let data = payload(&[nested_template(10_000)]);
+        let err = SparkplugPayload::decode(&data).unwrap_err();
+        assert!(err.contains("nested too deeply"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_input() {
+        let data = payload(&[metric("m", 12, |buf| string::encode(15, &"value".to_string(), buf))]);
+        for len in 1..data.len() {
+            // 途中で切れたデータはエラーまたは一部のみ（パニックしない）
+            let _ = SparkplugPayload::decode(&data[..len]);
+        }
+        // seq（2バイト）の前のメトリクスの途中で切れている
+        assert!(SparkplugPayload::decode(&data[..data.len() - 3]).is_err());
+        // 長さがデータより大きいフィールド
+        assert!(SparkplugPayload::decode(&[0x12, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x00]).is_err());
+    }
+
+    #[test]
+    fn ignores_out_of_range_dataset_fields() {
+        // フィールド番号が最大値のDataSetValue
+        let mut element = Vec::new();
+        prost::encoding::encode_key(u32::MAX >> 3, prost::encoding::WireType::Varint, &mut element);
+        prost::encoding::encode_varint(1, &mut element);
+        let mut row = Vec::new();
+        bytes::encode(1, &element, &mut row);
+        let mut dataset = Vec::new();
+        string::encode(2, &"c".to_string(), &mut dataset);
+        bytes::encode(4, &row, &mut dataset);
+        let data = payload(&[metric("d", 16, |buf| bytes::encode(17, &dataset, buf))]);
+        let payload = SparkplugPayload::decode(&data).unwrap();
+        assert_eq!(payload.metrics[0].value, json!([{"c": null}]));
+    }
+}
@@ -1,120 +1,652 @@
 use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
-    io::Write,
-    path::PathBuf,
-    time::Instant,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    fs,
+    path::{Path, PathBuf},
 };
-use serde_json::Value;
-use chrono::Local;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use chrono::{DateTime, Duration, Local, SecondsFormat, TimeZone};
 use log::*;
-use base64;
+use crate::alert::{AlertEngine, AlertEvent};
+use crate::crypto::{seal_line, RecordCipher, SealedFile};
+use crate::decode::PayloadDecoder;
+use crate::error::RecorderError;
+use crate::message::MqttMessage;
+use crate::metrics::Metrics;
+use crate::schema::{DriftEvent, TopicSchema};
+use crate::sketch::{DistinctCounter, P2Quantile, RunningStats, TopK};
+use crate::stats_config::{ArrayMode, KeySelection, StatMetric, StatsConfig, StatsFormat};
 
-/// JSONの値の種類を表す
+/// Space-Savingで追跡する値の数（上位k件に対する倍率）
+const TOP_K_CAPACITY_FACTOR: usize = 4;
+
+/// 数値の統計
+#[derive(Debug, Clone)]
+pub struct NumberStats {
+    pub running: RunningStats,
+    pub p50: P2Quantile,
+    pub p95: P2Quantile,
+    pub p99: P2Quantile,
+}
+
+impl NumberStats {
+    pub fn new() -> Self {
+        NumberStats {
+            running: RunningStats::new(),
+            p50: P2Quantile::new(0.50),
+            p95: P2Quantile::new(0.95),
+            p99: P2Quantile::new(0.99),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.running.push(value);
+        self.p50.push(value);
+        self.p95.push(value);
+        self.p99.push(value);
+    }
+}
+
+impl Default for NumberStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 文字列の統計
+#[derive(Debug, Clone)]
+pub struct StringStats {
+    pub count: u64,
+    pub distinct: DistinctCounter,
+    pub top: TopK,
+}
+
+impl StringStats {
+    pub fn new(top_k: usize) -> Self {
+        StringStats {
+            count: 0,
+            distinct: DistinctCounter::new(),
+            top: TopK::new(top_k * TOP_K_CAPACITY_FACTOR),
+        }
+    }
+}
+
+/// ペイロードの形式によらない統計（サイズ、到着間隔、重複）
+#[derive(Debug, Clone)]
+pub struct PayloadStats {
+    pub size: NumberStats,
+    pub interval: RunningStats,
+    pub distinct: DistinctCounter,
+    last_arrival: Option<f64>, // 期間をまたいで保持する
+}
+
+impl PayloadStats {
+    pub fn new() -> Self {
+        PayloadStats {
+            size: NumberStats::new(),
+            interval: RunningStats::new(),
+            distinct: DistinctCounter::new(),
+            last_arrival: None,
+        }
+    }
+
+    /// ペイロードと到着時刻（UNIX秒）を追加
+    pub fn push(&mut self, payload: &[u8], arrival: f64) {
+        self.size.push(payload.len() as f64);
+        self.distinct.insert(payload);
+        if let Some(last) = self.last_arrival {
+            self.interval.push((arrival - last).max(0.0));
+        }
+        self.last_arrival = Some(arrival);
+    }
+
+    /// メッセージ数
+    pub fn count(&self) -> u64 {
+        self.size.running.count()
+    }
+
+    /// 期間の長さ（秒）から集計結果を作成
+    pub fn summary(&self, duration_secs: f64) -> PayloadSummary {
+        let messages = self.count();
+        let duplicate_rate = if messages == 0 {
+            0.0
+        } else {
+            (1.0 - self.distinct.count() / messages as f64).max(0.0)
+        };
+        PayloadSummary {
+            messages,
+            rate: if duration_secs > 0.0 { messages as f64 / duration_secs } else { 0.0 },
+            size_min: self.size.running.min(),
+            size_max: self.size.running.max(),
+            size_mean: self.size.running.mean(),
+            size_p50: self.size.p50.estimate(),
+            size_p95: self.size.p95.estimate(),
+            interval_mean: self.interval.mean(),
+            jitter: self.interval.stddev(),
+            duplicate_rate,
+        }
+    }
+
+    /// 次の統計期間に備えてクリア（前回の到着時刻は保持）
+    pub fn reset(&mut self) {
+        *self = PayloadStats {
+            last_arrival: self.last_arrival,
+            ..PayloadStats::new()
+        };
+    }
+}
+
+impl Default for PayloadStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ペイロードの統計のキーパス（JSONのキーパスと区別するため `$` で始める）
+pub const PAYLOAD_STATS_KEY: &str = "$payload";
+
+/// 1つの統計期間のペイロードの統計
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PayloadSummary {
+    /// メッセージ数（JSON以外を含む）
+    pub messages: u64,
+    /// 1秒あたりのメッセージ数
+    pub rate: f64,
+    /// ペイロードサイズ（バイト）
+    pub size_min: f64,
+    pub size_max: f64,
+    pub size_mean: f64,
+    pub size_p50: f64,
+    pub size_p95: f64,
+    /// 到着間隔の平均（秒）
+    pub interval_mean: f64,
+    /// 到着間隔の標準偏差（秒）
+    pub jitter: f64,
+    /// 期間内で同じペイロードが繰り返された割合
+    pub duplicate_rate: f64,
+}
+
+impl PayloadSummary {
+    /// 統計量の名前と値
+    pub fn rows(&self) -> [(&'static str, f64); 10] {
+        [
+            ("messages", self.messages as f64),
+            ("rate", self.rate),
+            ("size_min", self.size_min),
+            ("size_max", self.size_max),
+            ("size_mean", self.size_mean),
+            ("size_p50", self.size_p50),
+            ("size_p95", self.size_p95),
+            ("interval_mean", self.interval_mean),
+            ("jitter", self.jitter),
+            ("duplicate_rate", self.duplicate_rate),
+        ]
+    }
+}
+
+/// JSONの値の種類を表す（値は保持せず逐次集計するため、キーパスごとのメモリ使用量は一定）
 #[derive(Debug, Clone)]
 pub enum JsonValueType {
-    Number(Vec<f64>),
-    String(Vec<String>),
-    Boolean(Vec<bool>),
+    Number(Box<NumberStats>),
+    String(StringStats),
+    Boolean { trues: u64, falses: u64 },
     Other,
 }
 
+/// 統計量の値
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatValue {
+    Number(f64),
+    Top(Vec<(String, u64)>),
+}
+
+impl fmt::Display for StatValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatValue::Number(value) => write!(f, "{:.3}", value),
+            StatValue::Top(values) => {
+                let parts: Vec<String> = values.iter().map(|(v, c)| format!("{}={}", v, c)).collect();
+                f.write_str(&parts.join("|"))
+            }
+        }
+    }
+}
+
+impl StatValue {
+    /// JSONでの表現（頻出値は `{"value", "count"}` の配列）
+    pub fn to_json(&self) -> Value {
+        match self {
+            StatValue::Number(value) => json!(value),
+            StatValue::Top(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|(value, count)| json!({ "value": value, "count": count }))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// CSV形式の統計ファイルのヘッダー
+pub const STATS_CSV_HEADER: &str = "window_start,window_end,window_start_iso,window_end_iso,topic,key,metric,value";
+
+/// 1つの統計期間の計算結果
+#[derive(Debug, Clone)]
+pub struct StatsWindow {
+    pub topic: String,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    /// 期間内に解析したJSONメッセージ数
+    pub messages: u64,
+    /// ペイロードの形式によらない統計
+    pub payload: PayloadSummary,
+    /// (キーパス, 統計量, 値)。キーパス順に並ぶ
+    pub values: Vec<(String, StatMetric, StatValue)>,
+}
+
+impl StatsWindow {
+    /// 指定された形式で出力する行を生成
+    pub fn format_lines(&self, format: StatsFormat) -> Vec<String> {
+        match format {
+            StatsFormat::Text => vec![self.to_text_line()],
+            StatsFormat::Jsonl => vec![self.to_json().to_string()],
+            StatsFormat::Csv => self.to_csv_lines(),
+        }
+    }
+
+    /// テキスト形式（`start - end, key{metric}:value, ...`）
+    pub fn to_text_line(&self) -> String {
+        let time_range = format!(
+            "{} - {}",
+            self.start.format("%Y-%m-%d %H:%M:%S"),
+            self.end.format("%Y-%m-%d %H:%M:%S")
+        );
+        let mut parts = vec![time_range];
+        for (name, value) in self.payload.rows().iter() {
+            parts.push(format!("{}{{{}}}:{:.3}", PAYLOAD_STATS_KEY, name, value));
+        }
+        for (key, metric, value) in &self.values {
+            parts.push(format!("{}{{{}}}:{}", key, metric, value));
+        }
+        parts.join(", ")
+    }
+
+    /// JSON形式（期間はUNIX秒とISO 8601の両方で表現）
+    pub fn to_json(&self) -> Value {
+        let mut stats = Map::new();
+        for (key, metric, value) in &self.values {
+            let entry = stats
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(metrics) = entry {
+                metrics.insert(metric.name().to_string(), value.to_json());
+            }
+        }
+        json!({
+            "topic": self.topic,
+            "window_start": epoch_secs(&self.start),
+            "window_end": epoch_secs(&self.end),
+            "window_start_iso": self.start.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "window_end_iso": self.end.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "messages": self.messages,
+            "payload": self.payload,
+            "stats": stats,
+        })
+    }
+
+    /// CSV形式（1統計量1行、列は `STATS_CSV_HEADER` の通り）
+    pub fn to_csv_lines(&self) -> Vec<String> {
+        let prefix = [
+            epoch_secs(&self.start).to_string(),
+            epoch_secs(&self.end).to_string(),
+            self.start.to_rfc3339_opts(SecondsFormat::Millis, false),
+            self.end.to_rfc3339_opts(SecondsFormat::Millis, false),
+            csv_field(&self.topic),
+        ]
+        .join(",");
+        let payload = self
+            .payload
+            .rows()
+            .iter()
+            .map(|(name, value)| format!("{},{},{},{}", prefix, PAYLOAD_STATS_KEY, name, value))
+            .collect::<Vec<_>>();
+        let values = self.values.iter().map(|(key, metric, value)| {
+            let value = match value {
+                StatValue::Number(n) => n.to_string(),
+                StatValue::Top(_) => csv_field(&value.to_string()),
+            };
+            format!("{},{},{},{}", prefix, csv_field(key), metric, value)
+        });
+        payload.into_iter().chain(values).collect()
+    }
+}
+
+/// ヘルパー関数：UNIX秒をローカル時刻に変換
+pub fn timestamp_to_local(timestamp: f64) -> DateTime<Local> {
+    Local
+        .timestamp_millis_opt((timestamp * 1000.0).round() as i64)
+        .single()
+        .unwrap_or_else(Local::now)
+}
+
+/// ヘルパー関数：時刻をミリ秒精度のUNIX秒に変換
+fn epoch_secs(time: &DateTime<Local>) -> f64 {
+    time.timestamp_millis() as f64 / 1000.0
+}
+
+/// ヘルパー関数：CSVのフィールドを必要に応じてクォート（RFC 4180）
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// ヘルパー関数：時刻を含む統計期間の開始時刻を計算（ローカル時刻の区切りに揃える）
+pub fn aligned_window_start(time: DateTime<Local>, interval_secs: u64) -> DateTime<Local> {
+    let interval = interval_secs.max(1) as i64;
+    let timestamp = time.timestamp();
+    let local_secs = timestamp + time.offset().local_minus_utc() as i64;
+    let start = timestamp - local_secs.rem_euclid(interval);
+    Local.timestamp_opt(start, 0).single().unwrap_or(time)
+}
+
 impl JsonValueType {
+    /// 値から型を決定して空の集計を作成（nullなど型が決まらない場合はOther）
+    pub fn for_value(value: &Value, top_k: usize) -> Self {
+        match value {
+            Value::Number(_) => JsonValueType::Number(Box::default()),
+            Value::String(_) => JsonValueType::String(StringStats::new(top_k)),
+            Value::Bool(_) => JsonValueType::Boolean { trues: 0, falses: 0 },
+            _ => JsonValueType::Other,
+        }
+    }
+
     /// 新しい値を追加
     pub fn add_value(&mut self, value: &Value) {
         match (self, value) {
-            (JsonValueType::Number(ref mut vec), Value::Number(n)) => {
+            (JsonValueType::Number(ref mut stats), Value::Number(n)) => {
                 if let Some(f) = n.as_f64() {
-                    vec.push(f);
+                    stats.push(f);
                 }
             }
-            (JsonValueType::String(ref mut vec), Value::String(s)) => {
-                vec.push(s.clone());
+            (JsonValueType::String(ref mut stats), Value::String(s)) => {
+                stats.count += 1;
+                stats.distinct.insert(s.as_str());
+                stats.top.insert(s);
             }
-            (JsonValueType::Boolean(ref mut vec), Value::Bool(b)) => {
-                vec.push(*b);
+            (JsonValueType::Boolean { trues, falses }, Value::Bool(b)) => {
+                if *b {
+                    *trues += 1;
+                } else {
+                    *falses += 1;
+                }
             }
             _ => {} // 型が一致しない場合は何もしない
         }
     }
 
+    /// 値の数
+    pub fn count(&self) -> u64 {
+        match self {
+            JsonValueType::Number(stats) => stats.running.count(),
+            JsonValueType::String(stats) => stats.count,
+            JsonValueType::Boolean { trues, falses } => trues + falses,
+            JsonValueType::Other => 0,
+        }
+    }
+
     /// 統計を計算（分散 for 数値、ユニーク数 for その他）
     pub fn calculate_stat(&self) -> f64 {
         match self {
-            JsonValueType::Number(values) => {
-                if values.is_empty() {
-                    return 0.0;
-                }
-                
-                let mean = values.iter().sum::<f64>() / values.len() as f64;
-                let variance = values.iter()
-                    .map(|x| (x - mean).powi(2))
-                    .sum::<f64>() / values.len() as f64;
-                variance
-            }
-            JsonValueType::String(values) => {
-                let unique_count = values.iter().collect::<std::collections::HashSet<_>>().len();
-                unique_count as f64
-            }
-            JsonValueType::Boolean(values) => {
-                let unique_count = values.iter().collect::<std::collections::HashSet<_>>().len();
-                unique_count as f64
+            JsonValueType::Number(stats) => stats.running.variance(),
+            JsonValueType::String(stats) => stats.distinct.count(),
+            JsonValueType::Boolean { trues, falses } => {
+                ((*trues > 0) as u8 + (*falses > 0) as u8) as f64
             }
             JsonValueType::Other => 0.0,
         }
     }
+
+    /// 型に固有の統計量を計算（型に当てはまらない統計量はNone）
+    pub fn metric(&self, metric: StatMetric, top_k: usize) -> Option<StatValue> {
+        let number = |v: f64| Some(StatValue::Number(v));
+        match (self, metric) {
+            (_, StatMetric::Count) => number(self.count() as f64),
+            (JsonValueType::Number(stats), StatMetric::Min) => number(stats.running.min()),
+            (JsonValueType::Number(stats), StatMetric::Max) => number(stats.running.max()),
+            (JsonValueType::Number(stats), StatMetric::Mean) => number(stats.running.mean()),
+            (JsonValueType::Number(stats), StatMetric::Stddev) => number(stats.running.stddev()),
+            (JsonValueType::Number(stats), StatMetric::Variance) => number(stats.running.variance()),
+            (JsonValueType::Number(stats), StatMetric::P50) => number(stats.p50.estimate()),
+            (JsonValueType::Number(stats), StatMetric::P95) => number(stats.p95.estimate()),
+            (JsonValueType::Number(stats), StatMetric::P99) => number(stats.p99.estimate()),
+            (JsonValueType::String(_), StatMetric::Unique)
+            | (JsonValueType::Boolean { .. }, StatMetric::Unique) => number(self.calculate_stat()),
+            (JsonValueType::String(stats), StatMetric::Top) => Some(StatValue::Top(stats.top.top(top_k))),
+            (JsonValueType::Boolean { trues, falses }, StatMetric::Top) => {
+                let mut values = vec![("true".to_string(), *trues), ("false".to_string(), *falses)];
+                values.retain(|(_, count)| *count > 0);
+                values.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                values.truncate(top_k);
+                Some(StatValue::Top(values))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// キーパスごとの統計
+#[derive(Debug, Clone)]
+pub struct KeyStats {
+    pub value: JsonValueType,
+    pub nulls: u64,
+    /// キーパスが存在したメッセージの数（`[*]` は1メッセージに複数の値を持つ）
+    pub present: u64,
+    last_message: u64, // 最後に値を追加したメッセージの番号
+}
+
+impl KeyStats {
+    /// 統計量を計算（存在しなかった数は期間内のメッセージ数から計算）
+    pub fn metric(&self, metric: StatMetric, messages: u64, top_k: usize) -> Option<StatValue> {
+        match metric {
+            StatMetric::Nulls => Some(StatValue::Number(self.nulls as f64)),
+            StatMetric::Missing => Some(StatValue::Number(messages.saturating_sub(self.present) as f64)),
+            _ => self.value.metric(metric, top_k),
+        }
+    }
+}
+
+/// アラートログのファイル名（出力ディレクトリ直下）
+pub const ALERT_LOG_FILE_NAME: &str = "mqtt-recorder-alerts.jsonl";
+
+/// 統計から生成されたブローカーへ送信するメッセージ（アラートなど）
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
 }
 
 /// 各トピックの統計情報を管理
 pub struct TopicStats {
-    data: HashMap<String, JsonValueType>, // キーパス -> 値のリスト
-    last_stats_time: Instant,
-    stats_start_time: Instant, // 統計開始時刻
-    stats_file: Option<File>,
+    topic: String,
+    data: HashMap<String, KeyStats>, // キーパス -> 集計値
+    messages: u64, // 期間内に解析したJSONメッセージ数
+    payload: PayloadStats, // 全メッセージのサイズ、到着間隔、重複
+    metrics: Vec<StatMetric>, // 出力する統計量
+    top_k: usize,
+    known_keys: BTreeSet<String>, // これまでに出現したキーパス（空の期間の出力用）
+    keys: KeySelection, // 集計するキーパスと配列の扱い
+    emit_empty: bool, // メッセージのない期間も出力するか
+    window_start: DateTime<Local>, // 統計期間の開始時刻
+    stats_file: Option<SealedFile>,
+    stats_path: PathBuf,
+    format: StatsFormat,
+    schema: Option<TopicSchema>, // スキーマの推定（有効時のみ）
+    schema_path: PathBuf,
+    drift_file: Option<SealedFile>,
+    drift_path: PathBuf,
+    drift_events: Vec<DriftEvent>, // 未取得のスキーマの変化
+    cipher: Option<RecordCipher>, // 統計ファイルの暗号化（有効時のみ）
     stats_interval_secs: u64, // 統計計算間隔（秒）
 }
 
 impl TopicStats {
-    pub fn new(stats_file_path: PathBuf, stats_interval_secs: u64) -> Result<Self, std::io::Error> {
-        let stats_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(stats_file_path)?;
+    pub fn new(
+        topic: &str,
+        stats_file_path: PathBuf,
+        stats_interval_secs: u64,
+        cipher: Option<RecordCipher>,
+        format: StatsFormat,
+    ) -> Result<Self, RecorderError> {
+        let io_error = |e| RecorderError::io(&stats_file_path, e);
+        let mut stats_file = SealedFile::append(&stats_file_path, cipher.clone()).map_err(io_error)?;
+
+        // CSVは新しいファイルの先頭にヘッダーを書き込む
+        if format == StatsFormat::Csv && stats_file.is_empty().map_err(io_error)? {
+            stats_file.write_line(STATS_CSV_HEADER).map_err(io_error)?;
+        }
             
-        let now = Instant::now();
+        let defaults = StatsConfig::default();
         Ok(TopicStats {
+            topic: topic.to_string(),
             data: HashMap::new(),
-            last_stats_time: now,
-            stats_start_time: now,
+            messages: 0,
+            payload: PayloadStats::new(),
+            metrics: defaults.metrics,
+            top_k: defaults.top_k,
+            known_keys: BTreeSet::new(),
+            keys: KeySelection::default(),
+            emit_empty: false,
+            window_start: aligned_window_start(Local::now(), stats_interval_secs),
             stats_file: Some(stats_file),
+            stats_path: stats_file_path,
+            format,
+            schema: None,
+            schema_path: PathBuf::new(),
+            drift_file: None,
+            drift_path: PathBuf::new(),
+            drift_events: Vec::new(),
+            cipher,
             stats_interval_secs,
         })
     }
 
-    /// JSONメッセージを解析して統計データに追加
-    pub fn add_message(&mut self, json_str: &str) {
-        if let Ok(mqtt_message) = serde_json::from_str::<serde_json::Value>(json_str) {
-            // MQTTメッセージの構造から msg_b64 フィールドを取得
-            if let Some(msg_b64) = mqtt_message.get("msg_b64").and_then(|v| v.as_str()) {
-                // Base64デコードしてからJSONとしてパース
-                if let Ok(decoded_bytes) = base64::decode(msg_b64) {
-                    if let Ok(decoded_str) = String::from_utf8(decoded_bytes) {
-                        if let Ok(payload_json) = serde_json::from_str::<serde_json::Value>(&decoded_str) {
-                            self.extract_values("", &payload_json);
-                        } else {
-                            // JSON以外のペイロードの場合は統計対象外
-                            debug!("Payload is not JSON: {}", decoded_str);
-                        }
-                    }
+    /// 出力する統計量と頻出値の件数を設定
+    pub fn set_metrics(&mut self, metrics: Vec<StatMetric>, top_k: usize) {
+        self.metrics = metrics;
+        self.top_k = top_k;
+    }
+
+    /// 集計するキーパスと配列の扱いを設定
+    pub fn set_keys(&mut self, keys: KeySelection) {
+        self.keys = keys;
+    }
+
+    /// メッセージのない期間も件数0として出力するかを設定
+    pub fn set_emit_empty(&mut self, enabled: bool) {
+        self.emit_empty = enabled;
+    }
+
+    /// スキーマの推定を有効化（推定したスキーマと変化のログをファイルに出力）
+    pub fn enable_schema(&mut self, schema_path: PathBuf, drift_path: &Path) -> Result<(), RecorderError> {
+        let drift_file =
+            SealedFile::append(drift_path, self.cipher.clone()).map_err(|e| RecorderError::io(drift_path, e))?;
+        self.schema = Some(TopicSchema::new(&self.topic));
+        self.schema_path = schema_path;
+        self.drift_file = Some(drift_file);
+        self.drift_path = drift_path.to_path_buf();
+        Ok(())
+    }
+
+    /// 検出したスキーマの変化を取得
+    pub fn take_drift_events(&mut self) -> Vec<DriftEvent> {
+        std::mem::take(&mut self.drift_events)
+    }
+
+    /// 推定したスキーマをファイルに出力
+    fn write_schema(&self) -> Result<(), RecorderError> {
+        if let Some(schema) = &self.schema {
+            let schema_line = serde_json::to_string(schema)?;
+            seal_line(&schema_line, self.cipher.as_ref(), &self.schema_path, 1)
+                .and_then(|line| fs::write(&self.schema_path, format!("{}\n", line)))
+                .map_err(|e| RecorderError::io(&self.schema_path, e))?;
+        }
+        Ok(())
+    }
+
+    /// スキーマを観測し、変化があればログとスキーマファイルに出力
+    fn observe_schema(&mut self, payload: &Value, time: f64) -> Result<(), RecorderError> {
+        let schema = match self.schema.as_mut() {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        let first = schema.messages == 0;
+        let events = schema.observe(payload, time);
+        if events.is_empty() && !first {
+            return Ok(());
+        }
+
+        self.write_schema()?;
+        for event in &events {
+            warn!("Schema drift: {}", event);
+            if let Some(ref mut file) = self.drift_file {
+                let line = serde_json::to_string(event)?;
+                file.write_line(&line).map_err(|e| RecorderError::io(&self.drift_path, e))?;
+            }
+        }
+        if let Some(ref mut file) = self.drift_file {
+            file.flush().map_err(|e| RecorderError::io(&self.drift_path, e))?;
+        }
+        self.drift_events.extend(events);
+        Ok(())
+    }
+
+    /// メッセージを統計に追加（JSONのペイロードはキーパスごとにも集計し、解析したJSONを返す）
+    pub fn add_message(&mut self, msg: &MqttMessage) -> Option<Value> {
+        let payload = match base64::decode(&msg.msg_b64) {
+            Ok(payload) => payload,
+            Err(e) => {
+                debug!("Failed to decode payload on '{}': {:?}", msg.topic, e);
+                return None;
+            }
+        };
+        let value = std::str::from_utf8(&payload)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(text).ok());
+        self.add_decoded(msg, &payload, value)
+    }
+
+    /// 解析済みのメッセージを統計に追加（値のないペイロードはキーパスごとの統計の対象外）
+    pub fn add_decoded(&mut self, msg: &MqttMessage, payload: &[u8], value: Option<Value>) -> Option<Value> {
+        self.payload.push(payload, msg.order_key().0);
+
+        match value {
+            Some(payload_json) => {
+                self.messages += 1;
+                self.extract_values("", &payload_json, false);
+                if let Err(e) = self.observe_schema(&payload_json, msg.time) {
+                    error!("Failed to write schema for topic '{}': {}", self.topic, e);
                 }
+                Some(payload_json)
+            }
+            None => {
+                debug!("Payload on '{}' is not JSON", msg.topic);
+                None
             }
-        } else {
-            debug!("Failed to parse MQTT message JSON: {}", json_str);
         }
     }
 
-    /// JSON値を再帰的に解析してキーパスと値を抽出
-    fn extract_values(&mut self, prefix: &str, value: &Value) {
+    /// JSON値を再帰的に解析してキーパスと値を抽出（included は祖先が include に一致したか）
+    fn extract_values(&mut self, prefix: &str, value: &Value, included: bool) {
+        // 除外されたキーパスは子孫ごと集計しない
+        if !prefix.is_empty() && self.keys.is_excluded(prefix) {
+            return;
+        }
+        let included = included || (!prefix.is_empty() && self.keys.is_included(prefix));
+
         match value {
             Value::Object(map) => {
                 for (key, val) in map {
@@ -123,18 +655,32 @@ impl TopicStats {
                     } else {
                         format!("{}.{}", prefix, key)
                     };
-                    self.extract_values(&new_prefix, val);
+                    self.extract_values(&new_prefix, val, included);
                 }
             }
-            Value::Array(arr) => {
-                for (index, val) in arr.iter().enumerate() {
-                    let new_prefix = format!("{}[{}]", prefix, index);
-                    self.extract_values(&new_prefix, val);
+            Value::Array(arr) => match self.keys.arrays {
+                ArrayMode::Index => {
+                    for (index, val) in arr.iter().enumerate() {
+                        let new_prefix = format!("{}[{}]", prefix, index);
+                        self.extract_values(&new_prefix, val, included);
+                    }
                 }
-            }
+                ArrayMode::First(limit) => {
+                    for (index, val) in arr.iter().enumerate().take(limit) {
+                        let new_prefix = format!("{}[{}]", prefix, index);
+                        self.extract_values(&new_prefix, val, included);
+                    }
+                }
+                ArrayMode::Aggregate => {
+                    let new_prefix = format!("{}[*]", prefix);
+                    for val in arr {
+                        self.extract_values(&new_prefix, val, included);
+                    }
+                }
+            },
             _ => {
                 // リーフノードの値を記録
-                if !prefix.is_empty() {
+                if !prefix.is_empty() && included {
                     self.add_value_to_stats(prefix, value);
                 }
             }
@@ -143,67 +689,167 @@ impl TopicStats {
 
     /// 統計データに値を追加
     fn add_value_to_stats(&mut self, key_path: &str, value: &Value) {
-        let entry = self.data.entry(key_path.to_string()).or_insert_with(|| {
-            match value {
-                Value::Number(_) => JsonValueType::Number(Vec::new()),
-                Value::String(_) => JsonValueType::String(Vec::new()),
-                Value::Bool(_) => JsonValueType::Boolean(Vec::new()),
-                _ => JsonValueType::Other,
-            }
+        let top_k = self.top_k;
+        let message = self.messages;
+        if self.emit_empty && !self.known_keys.contains(key_path) {
+            self.known_keys.insert(key_path.to_string());
+        }
+        let entry = self.data.entry(key_path.to_string()).or_insert_with(|| KeyStats {
+            value: JsonValueType::for_value(value, top_k),
+            nulls: 0,
+            present: 0,
+            last_message: 0,
         });
+        if entry.last_message != message {
+            entry.present += 1;
+            entry.last_message = message;
+        }
         
-        entry.add_value(value);
+        if value.is_null() {
+            entry.nulls += 1;
+            return;
+        }
+        // nullのみで型が決まっていなかった場合は最初の値で型を決める
+        if let JsonValueType::Other = entry.value {
+            entry.value = JsonValueType::for_value(value, top_k);
+        }
+        entry.value.add_value(value);
     }
 
-    /// 統計計算間隔が経過したかチェック
-    pub fn should_calculate_stats(&self) -> bool {
-        self.last_stats_time.elapsed().as_secs() >= self.stats_interval_secs
+    /// 現在の統計期間の終了時刻（次の区切り）
+    pub fn window_end(&self) -> DateTime<Local> {
+        aligned_window_start(self.window_start, self.stats_interval_secs)
+            + Duration::seconds(self.stats_interval_secs.max(1) as i64)
     }
 
-    /// 統計を計算してファイルに出力
-    pub fn calculate_and_write_stats(&mut self) -> Result<(), std::io::Error> {
-        if self.data.is_empty() {
-            return Ok(());
+    /// 統計期間が終了したかチェック
+    pub fn should_calculate_stats(&self, now: DateTime<Local>) -> bool {
+        now >= self.window_end()
+    }
+
+    /// 指定時刻で現在の期間を閉じて統計を計算し、次の統計期間に備えてデータをクリア
+    pub fn take_window(&mut self, end: DateTime<Local>) -> Option<StatsWindow> {
+        let empty = self.payload.count() == 0;
+        if empty && !self.emit_empty {
+            self.window_start = end;
+            return None;
         }
 
-        let now = Local::now();
-        let start_time_chrono = now - chrono::Duration::seconds(self.last_stats_time.elapsed().as_secs() as i64);
-        let start_timestamp = start_time_chrono.format("%Y-%m-%d %H:%M:%S").to_string();
-        let end_timestamp = now.format("%Y-%m-%d %H:%M:%S").to_string();
-        let time_range = format!("{} - {}", start_timestamp, end_timestamp);
-        
-        let mut stats_parts = vec![time_range];
-        
-        // キーパスでソートして一貫した出力順序を保つ
-        let mut sorted_keys: Vec<_> = self.data.keys().collect();
-        sorted_keys.sort();
-        
-        for key in sorted_keys {
-            if let Some(value_type) = self.data.get(key) {
-                let stat = value_type.calculate_stat();
-                stats_parts.push(format!("{}:{:.3}", key, stat));
+        let mut values = Vec::new();
+        if empty {
+            // 空の期間はこれまでに出現したキーパスを件数0で出力
+            let zero_metrics = [StatMetric::Count, StatMetric::Nulls, StatMetric::Missing];
+            for key in &self.known_keys {
+                for metric in zero_metrics.iter() {
+                    if *metric == StatMetric::Count || self.metrics.contains(metric) {
+                        values.push((key.clone(), *metric, StatValue::Number(0.0)));
+                    }
+                }
+            }
+        } else {
+            // キーパスでソートして一貫した出力順序を保つ
+            let mut sorted_keys: Vec<_> = self.data.keys().collect();
+            sorted_keys.sort();
+
+            for key in sorted_keys {
+                let key_stats = &self.data[key];
+                for metric in &self.metrics {
+                    if let Some(value) = key_stats.metric(*metric, self.messages, self.top_k) {
+                        values.push((key.clone(), *metric, value));
+                    }
+                }
             }
         }
-        
-        let stats_line = stats_parts.join(", ") + "\n";
-        
+
+        let duration_secs = (end - self.window_start).num_milliseconds() as f64 / 1000.0;
+        let window = StatsWindow {
+            topic: self.topic.clone(),
+            start: self.window_start,
+            end,
+            messages: self.messages,
+            payload: self.payload.summary(duration_secs),
+            values,
+        };
+
+        self.data.clear();
+        self.messages = 0;
+        self.payload.reset();
+        self.window_start = end;
+        Some(window)
+    }
+
+    /// 終了した統計期間を閉じる（空の期間を出力しない場合は現在の期間まで飛ばす）
+    pub fn take_due_windows(&mut self, now: DateTime<Local>) -> Vec<StatsWindow> {
+        let mut windows = Vec::new();
+        while self.should_calculate_stats(now) {
+            if self.payload.count() == 0 && !self.emit_empty {
+                self.window_start = aligned_window_start(now, self.stats_interval_secs);
+                break;
+            }
+            let end = self.window_end();
+            windows.extend(self.take_window(end));
+        }
+        windows
+    }
+
+    /// 統計期間をファイルに出力
+    fn write_window(&mut self, window: &StatsWindow) -> Result<(), RecorderError> {
         if let Some(ref mut file) = self.stats_file {
-            file.write_all(stats_line.as_bytes())?;
-            file.flush()?;
-            info!("Wrote stats: {}", stats_line.trim());
+            let stats_path = &self.stats_path;
+            let io_error = |e| RecorderError::io(stats_path, e);
+            for line in window.format_lines(self.format) {
+                file.write_line(&line).map_err(io_error)?;
+            }
+            file.flush().map_err(io_error)?;
+            // 暗号化している場合は統計の値をログに出さない
+            if self.cipher.is_some() {
+                info!("Wrote stats for topic '{}': {} messages", window.topic, window.payload.messages);
+            } else {
+                info!("Wrote stats: {}", window.to_text_line());
+            }
         }
-        
-        // データをクリアして次の統計期間に備える
-        self.data.clear();
-        self.last_stats_time = Instant::now();
-        self.stats_start_time = Instant::now();
-        
-        Ok(())
+        self.write_schema()
     }
 
-    /// 強制的に統計を計算（ファイル分割時など）
-    pub fn force_calculate_stats(&mut self) -> Result<(), std::io::Error> {
-        self.calculate_and_write_stats()
+    /// 終了した統計期間の統計を計算してファイルに出力（出力した期間を返す）
+    pub fn calculate_and_write_stats(&mut self, now: DateTime<Local>) -> Result<Vec<StatsWindow>, RecorderError> {
+        let windows = self.take_due_windows(now);
+        for window in &windows {
+            self.write_window(window)?;
+        }
+        Ok(windows)
+    }
+
+    /// 強制的に統計を計算（ファイル分割時など、期間の途中までを出力）
+    pub fn force_calculate_stats(&mut self) -> Result<Vec<StatsWindow>, RecorderError> {
+        let now = Local::now();
+        let mut windows = self.calculate_and_write_stats(now)?;
+        if self.payload.count() == 0 {
+            return Ok(windows);
+        }
+        if let Some(window) = self.take_window(now) {
+            self.write_window(&window)?;
+            windows.push(window);
+        }
+        Ok(windows)
+    }
+
+    /// 現在の期間を区切りまでの期間として出力（記録済みファイルの再計算の終了時）
+    pub fn flush(&mut self) -> Result<Vec<StatsWindow>, RecorderError> {
+        if self.payload.count() == 0 {
+            return Ok(Vec::new());
+        }
+        let end = self.window_end();
+        let windows: Vec<StatsWindow> = self.take_window(end).into_iter().collect();
+        for window in &windows {
+            self.write_window(window)?;
+        }
+        Ok(windows)
+    }
+
+    /// 統計期間の開始時刻を設定（区切りに揃える）
+    pub fn set_window_start(&mut self, time: DateTime<Local>) {
+        self.window_start = aligned_window_start(time, self.stats_interval_secs);
     }
 }
 
@@ -213,6 +859,20 @@ pub struct StatsManager {
     base_dir: PathBuf,
     stats_enabled: bool,
     stats_interval_secs: u64,
+    cipher: Option<RecordCipher>,
+    config: StatsConfig,
+    format: StatsFormat,
+    emit_empty: bool,
+    overwrite: bool,
+    schema_drift: bool,
+    schema_alert_topic: Option<String>,
+    alerts: AlertEngine,
+    alert_topic: Option<String>,
+    alert_log: Option<SealedFile>,
+    publish_prefix: Option<String>,
+    outbound: Vec<OutboundMessage>,
+    metrics: Metrics,
+    decoder: PayloadDecoder, // 全トピックで共有（Sparkplug B のエイリアスはBIRTHのトピックで定義される）
 }
 
 impl StatsManager {
@@ -222,54 +882,299 @@ impl StatsManager {
             base_dir,
             stats_enabled,
             stats_interval_secs,
+            cipher: None,
+            config: StatsConfig::default(),
+            format: StatsFormat::default(),
+            emit_empty: false,
+            overwrite: false,
+            schema_drift: false,
+            schema_alert_topic: None,
+            alerts: AlertEngine::new(Vec::new()),
+            alert_topic: None,
+            alert_log: None,
+            publish_prefix: None,
+            outbound: Vec::new(),
+            metrics: Metrics::default(),
+            decoder: PayloadDecoder::new(),
         }
     }
 
-    /// メッセージを統計に追加
-    pub fn add_message(&mut self, topic: &str, json_payload: &str) {
+    /// スキーマの推定と変化の検出を設定（変化はアラートトピックにも送信）
+    pub fn set_schema_drift(&mut self, enabled: bool, alert_topic: Option<String>) {
+        self.schema_drift = enabled;
+        self.schema_alert_topic = alert_topic;
+    }
+
+    /// ペイロードの解析方法を設定（Protocol Buffersのメッセージ型など）
+    pub fn set_payload_decoder(&mut self, decoder: PayloadDecoder) {
+        self.decoder = decoder;
+    }
+
+    /// 出力した統計期間を数えるメトリクスを設定
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// アラートを送信するMQTTトピックを設定（ルールは設定ファイルの `alerts`）
+    pub fn set_alert_topic(&mut self, alert_topic: Option<String>) {
+        self.alert_topic = alert_topic;
+    }
+
+    /// 統計期間をJSONで `<prefix>/<topic>` に送信するよう設定
+    pub fn set_publish_prefix(&mut self, prefix: Option<String>) {
+        self.publish_prefix = prefix.map(|prefix| prefix.trim_end_matches('/').to_string());
+    }
+
+    /// ブローカーに送信するメッセージを取得
+    pub fn take_outbound(&mut self) -> Vec<OutboundMessage> {
+        std::mem::take(&mut self.outbound)
+    }
+
+    /// 既存の統計ファイルに追記せず置き換えるかを設定（再計算時）
+    pub fn set_overwrite(&mut self, enabled: bool) {
+        self.overwrite = enabled;
+    }
+
+    /// 計算する統計量の設定
+    pub fn set_config(&mut self, config: StatsConfig) {
+        self.alerts = AlertEngine::new(config.alerts.clone());
+        self.config = config;
+    }
+
+    /// 統計ファイルの出力形式を設定
+    pub fn set_format(&mut self, format: StatsFormat) {
+        self.format = format;
+    }
+
+    /// メッセージのない期間も件数0として出力するかを設定
+    pub fn set_emit_empty(&mut self, enabled: bool) {
+        self.emit_empty = enabled;
+    }
+
+    /// 統計ファイルを暗号化する鍵を設定
+    pub fn set_cipher(&mut self, cipher: Option<RecordCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// メッセージを統計に追加（期間は受信時の壁時計で区切る）
+    pub fn add_message(&mut self, msg: &MqttMessage) {
+        self.add_message_at(msg, Local::now());
+    }
+
+    /// 指定時刻に受信したメッセージとして統計に追加
+    pub fn add_message_at(&mut self, msg: &MqttMessage, now: DateTime<Local>) {
         if !self.stats_enabled {
             return; // 統計が無効な場合は何もしない
         }
+        let topic = msg.topic.as_str();
 
         // トピックの統計が存在しない場合は作成
         if !self.topic_stats.contains_key(topic) {
             let stats_file_path = self.get_stats_file_path(topic);
-            match TopicStats::new(stats_file_path, self.stats_interval_secs) {
-                Ok(stats) => {
+            let drift_path = self.get_topic_file_path(topic, "drift.jsonl");
+            if self.overwrite {
+                for path in [&stats_file_path, &drift_path] {
+                    if !path.exists() {
+                        continue;
+                    }
+                    if let Err(e) = fs::remove_file(path) {
+                        error!("Failed to replace stats file {:?}: {:?}", path, e);
+                        return;
+                    }
+                }
+            }
+            match TopicStats::new(
+                topic,
+                stats_file_path,
+                self.stats_interval_secs,
+                self.cipher.clone(),
+                self.format,
+            ) {
+                Ok(mut stats) => {
+                    // アラートのルールで使用する統計量も計算する
+                    let (mut metrics, top_k) = self.config.metrics_for(topic);
+                    for metric in self.alerts.stat_metrics_for(topic) {
+                        if !metrics.contains(&metric) {
+                            metrics.push(metric);
+                        }
+                    }
+                    stats.set_metrics(metrics, top_k);
+                    stats.set_keys(self.config.keys_for(topic));
+                    stats.set_emit_empty(self.emit_empty);
+                    stats.set_window_start(now);
+                    if self.schema_drift {
+                        let schema_path = self.get_topic_file_path(topic, "schema.jsonl");
+                        if let Err(e) = stats.enable_schema(schema_path, &drift_path) {
+                            error!("Failed to create schema drift log for topic '{}': {}", topic, e);
+                        }
+                    }
                     self.topic_stats.insert(topic.to_string(), stats);
                 }
                 Err(e) => {
-                    error!("Failed to create stats file for topic '{}': {:?}", topic, e);
+                    error!("Failed to create stats file for topic '{}': {}", topic, e);
                     return;
                 }
             }
         }
 
-        // メッセージを統計に追加
-        if let Some(stats) = self.topic_stats.get_mut(topic) {
-            stats.add_message(json_payload);
+        // メッセージを統計に追加（Sparkplug B などはJSONの値に変換してから集計）
+        let bytes = match base64::decode(&msg.msg_b64) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("Failed to decode payload on '{}': {:?}", topic, e);
+                return;
+            }
+        };
+        let decoded = self.decoder.decode(topic, &bytes);
+        let (payload, drift_events) = match self.topic_stats.get_mut(topic) {
+            Some(stats) => (stats.add_decoded(msg, &bytes, decoded.value), stats.take_drift_events()),
+            None => return,
+        };
+        if let Some(alert_topic) = self.schema_alert_topic.clone() {
+            for event in drift_events {
+                self.queue_outbound(&alert_topic, &event);
+            }
+        }
+        if !self.alerts.is_empty() {
+            let events = self.alerts.on_message(topic, payload.as_ref(), epoch_secs(&now));
+            self.handle_alerts(events);
+        }
+    }
+
+    /// 出力した統計期間をブローカーに送信し、アラートを評価
+    fn handle_windows(&mut self, windows: Vec<StatsWindow>) {
+        self.metrics.stats_windows_emitted(windows.len());
+        for window in &windows {
+            if let Some(prefix) = self.publish_prefix.clone() {
+                let topic = format!("{}/{}", prefix, window.topic);
+                self.queue_outbound(&topic, &window.to_json());
+            }
+            if !self.alerts.is_empty() {
+                let events = self.alerts.on_window(window);
+                self.handle_alerts(events);
+            }
         }
     }
 
-    /// 定期的な統計計算チェック
+    /// アラートをログ、アラートログファイル、MQTTに出力
+    fn handle_alerts(&mut self, events: Vec<AlertEvent>) {
+        for event in events {
+            // 暗号化している場合はアラートの内容（統計の値を含む）をログに出さない
+            if self.cipher.is_some() {
+                warn!("Alert {} on '{}'", event.rule, event.topic);
+            } else {
+                warn!("Alert {}", event);
+            }
+            if let Err(e) = self.write_alert_log(&event) {
+                error!("Failed to write alert log: {:?}", e);
+            }
+            if let Some(alert_topic) = self.alert_topic.clone() {
+                self.queue_outbound(&alert_topic, &event);
+            }
+        }
+    }
+
+    /// アラートログ（`mqtt-recorder-alerts.jsonl`）に追記
+    fn write_alert_log(&mut self, event: &AlertEvent) -> Result<(), std::io::Error> {
+        if self.alert_log.is_none() {
+            let _ = fs::create_dir_all(&self.base_dir);
+            let path = self.base_dir.join(ALERT_LOG_FILE_NAME);
+            if self.overwrite && path.exists() {
+                fs::remove_file(&path)?;
+            }
+            self.alert_log = Some(SealedFile::append(&path, self.cipher.clone())?);
+        }
+        if let Some(ref mut file) = self.alert_log {
+            let line = serde_json::to_string(event)?;
+            file.write_line(&line)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// JSONにしてブローカーへの送信キューに追加
+    fn queue_outbound<T: Serialize>(&mut self, topic: &str, value: &T) {
+        match serde_json::to_vec(value) {
+            Ok(payload) => self.outbound.push(OutboundMessage {
+                topic: topic.to_string(),
+                payload,
+            }),
+            Err(e) => error!("Failed to serialize message for '{}': {:?}", topic, e),
+        }
+    }
+
+    /// 定期的な統計計算チェック（タイマーから呼び出し、終了した期間を出力）
     pub fn check_and_calculate_stats(&mut self) {
+        self.check_and_calculate_stats_at(Local::now());
+    }
+
+    /// 指定時刻までに終了した期間を出力
+    pub fn check_and_calculate_stats_at(&mut self, now: DateTime<Local>) {
         if !self.stats_enabled {
             return; // 統計が無効な場合は何もしない
         }
 
-        let topics_to_update: Vec<String> = self.topic_stats
-            .iter()
-            .filter(|(_, stats)| stats.should_calculate_stats())
-            .map(|(topic, _)| topic.clone())
-            .collect();
-
-        for topic in topics_to_update {
-            if let Some(stats) = self.topic_stats.get_mut(&topic) {
-                if let Err(e) = stats.calculate_and_write_stats() {
-                    error!("Failed to calculate stats for topic '{}': {:?}", topic, e);
+        let mut windows = Vec::new();
+        for (topic, stats) in self.topic_stats.iter_mut() {
+            if !stats.should_calculate_stats(now) {
+                continue;
+            }
+            match stats.calculate_and_write_stats(now) {
+                Ok(written) => windows.extend(written),
+                Err(e) => error!("Failed to calculate stats for topic '{}': {}", topic, e),
+            }
+        }
+        self.handle_windows(windows);
+
+        if !self.alerts.is_empty() {
+            let events = self.alerts.on_tick(epoch_secs(&now));
+            self.handle_alerts(events);
+        }
+    }
+
+    /// 記録済みのメッセージを時刻順に統計に追加（期間はメッセージの時刻で区切る）
+    /// 追加したメッセージ数を返す（残りの期間を出力できなかった場合はエラー）
+    pub fn add_recorded_messages<I>(&mut self, messages: I) -> Result<u64, RecorderError>
+    where
+        I: IntoIterator<Item = MqttMessage>,
+    {
+        let mut count = 0;
+        for msg in messages {
+            let now = timestamp_to_local(msg.order_key().0);
+            self.check_and_calculate_stats_at(now);
+            self.add_message_at(&msg, now);
+            count += 1;
+        }
+        self.flush_all()?;
+        Ok(count)
+    }
+
+    /// 全トピックの残りの期間を出力（記録済みファイルの再計算の終了時）
+    /// 出力できないトピックがあっても残りのトピックを出力し、最初のエラーを返す
+    pub fn flush_all(&mut self) -> Result<(), RecorderError> {
+        let mut windows = Vec::new();
+        let mut result = Ok(());
+        for (topic, stats) in self.topic_stats.iter_mut() {
+            match stats.flush() {
+                Ok(written) => windows.extend(written),
+                Err(e) => {
+                    error!("Failed to write stats for topic '{}': {}", topic, e);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
                 }
             }
         }
+        self.handle_windows(windows);
+        result
+    }
+
+    /// 全トピックの統計を強制計算（記録の終了時）
+    pub fn force_calculate_all_stats(&mut self) {
+        let topics: Vec<String> = self.topic_stats.keys().cloned().collect();
+        for topic in topics {
+            self.force_calculate_stats_for_topic(&topic);
+        }
     }
 
     /// 特定のトピックの統計を強制計算（ファイル分割時）
@@ -279,16 +1184,22 @@ impl StatsManager {
         }
 
         if let Some(stats) = self.topic_stats.get_mut(topic) {
-            if let Err(e) = stats.force_calculate_stats() {
-                error!("Failed to force calculate stats for topic '{}': {:?}", topic, e);
+            match stats.force_calculate_stats() {
+                Ok(written) => self.handle_windows(written),
+                Err(e) => error!("Failed to force calculate stats for topic '{}': {}", topic, e),
             }
         }
     }
 
     /// 統計ファイルのパスを生成
     fn get_stats_file_path(&self, topic: &str) -> PathBuf {
+        self.get_topic_file_path(topic, &format!("stats.{}", self.format.extension()))
+    }
+
+    /// トピックのディレクトリ内の統計関連ファイルのパスを生成（`mqtt-recorder-{topic}-{suffix}`）
+    fn get_topic_file_path(&self, topic: &str, suffix: &str) -> PathBuf {
         let topic_filename = topic.replace('/', "-").replace('+', "plus").replace('#', "hash");
-        let stats_filename = format!("mqtt-recorder-{}-stats.txt", topic_filename);
+        let stats_filename = format!("mqtt-recorder-{}-{}", topic_filename, suffix);
         
         // トピックのディレクトリ構造内に統計ファイルを配置
         let topic_dir = self.base_dir.join(topic);
@@ -298,3 +1209,241 @@ impl StatsManager {
         topic_dir.join(stats_filename)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(time: f64, payload: &str) -> MqttMessage {
+        MqttMessage {
+            time,
+            qos: 0,
+            retain: false,
+            topic: "s/t".to_string(),
+            msg_b64: base64::encode(payload),
+            seq: None,
+            mono: None,
+            run: None,
+            chain: None,
+        }
+    }
+
+    fn topic_stats(name: &str, interval_secs: u64, format: StatsFormat) -> (TopicStats, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("mqtt-recorder-stats-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let stats = TopicStats::new("s/t", dir.join("stats"), interval_secs, None, format).unwrap();
+        (stats, dir)
+    }
+
+    fn local(timestamp: i64) -> DateTime<Local> {
+        Local.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    fn value<'a>(window: &'a StatsWindow, key: &str, metric: StatMetric) -> &'a StatValue {
+        window
+            .values
+            .iter()
+            .find(|(k, m, _)| k == key && *m == metric)
+            .map(|(_, _, value)| value)
+            .unwrap_or_else(|| panic!("no {}{{{}}}", key, metric))
+    }
+
+    fn number(window: &StatsWindow, key: &str, metric: StatMetric) -> f64 {
+        match value(window, key, metric) {
+            StatValue::Number(n) => *n,
+            other => panic!("{}{{{}}} is {:?}", key, metric, other),
+        }
+    }
+
+    #[test]
+    fn computes_per_key_statistics() {
+        let (mut stats, dir) = topic_stats("keys", 60, StatsFormat::Text);
+        stats.set_metrics(StatMetric::ALL.to_vec(), 2);
+        let time = stats.window_end().timestamp() as f64 - 30.0;
+        // 1〜100を並べ替えた順序で追加（37は100と互いに素）
+        for i in (0..100).map(|i| i * 37 % 100 + 1) {
+            let s = if i % 4 == 0 { "b" } else { "a" };
+            stats.add_message(&message(time, &format!(r#"{{"v":{},"s":"{}","flag":{}}}"#, i, s, i % 2 == 0)));
+        }
+        for _ in 0..2 {
+            stats.add_message(&message(time, r#"{"v":null,"s":"a"}"#));
+        }
+        for _ in 0..3 {
+            stats.add_message(&message(time, r#"{"s":"c"}"#));
+        }
+        stats.add_message(&message(time, "not json"));
+
+        let end = stats.window_end();
+        let window = stats.take_window(end).unwrap();
+        assert_eq!(window.messages, 105);
+        assert_eq!(window.payload.messages, 106);
+
+        assert_eq!(number(&window, "v", StatMetric::Count), 100.0);
+        assert_eq!(number(&window, "v", StatMetric::Nulls), 2.0);
+        assert_eq!(number(&window, "v", StatMetric::Missing), 3.0);
+        assert_eq!(number(&window, "v", StatMetric::Min), 1.0);
+        assert_eq!(number(&window, "v", StatMetric::Max), 100.0);
+        assert!((number(&window, "v", StatMetric::Mean) - 50.5).abs() < 1e-9);
+        assert!((number(&window, "v", StatMetric::Variance) - 833.25).abs() < 1e-6);
+        assert!((number(&window, "v", StatMetric::Stddev) - 833.25f64.sqrt()).abs() < 1e-9);
+        assert!((number(&window, "v", StatMetric::P50) - 50.5).abs() < 2.0);
+        assert!((number(&window, "v", StatMetric::P95) - 95.0).abs() < 2.0);
+        assert!((number(&window, "v", StatMetric::P99) - 99.0).abs() < 2.0);
+
+        assert_eq!(number(&window, "s", StatMetric::Count), 105.0);
+        assert_eq!(number(&window, "s", StatMetric::Unique), 3.0);
+        assert_eq!(
+            value(&window, "s", StatMetric::Top),
+            &StatValue::Top(vec![("a".to_string(), 77), ("b".to_string(), 25)])
+        );
+        assert_eq!(
+            value(&window, "flag", StatMetric::Top),
+            &StatValue::Top(vec![("true".to_string(), 50), ("false".to_string(), 50)])
+        );
+        assert_eq!(number(&window, "flag", StatMetric::Missing), 5.0);
+
+        // 次の期間は空から始まる
+        assert!(stats.take_window(end + Duration::seconds(60)).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn sample_window() -> StatsWindow {
+        StatsWindow {
+            topic: "plant,1/temp".to_string(),
+            start: local(1_753_405_200),
+            end: local(1_753_405_260),
+            messages: 2,
+            payload: PayloadSummary {
+                messages: 2,
+                rate: 2.0 / 60.0,
+                ..PayloadSummary::default()
+            },
+            values: vec![
+                ("temp".to_string(), StatMetric::Mean, StatValue::Number(21.5)),
+                ("status".to_string(), StatMetric::Top, StatValue::Top(vec![("ok".to_string(), 1), ("a,b".to_string(), 1)])),
+            ],
+        }
+    }
+
+    #[test]
+    fn formats_windows_as_text_json_and_csv() {
+        let window = sample_window();
+
+        let text = window.format_lines(StatsFormat::Text);
+        assert_eq!(text.len(), 1);
+        let time_range = format!("{} - {}", window.start.format("%Y-%m-%d %H:%M:%S"), window.end.format("%Y-%m-%d %H:%M:%S"));
+        assert!(text[0].starts_with(&time_range), "{}", text[0]);
+        assert!(text[0].contains("$payload{messages}:2.000"), "{}", text[0]);
+        assert!(text[0].ends_with("temp{mean}:21.500, status{top}:ok=1|a,b=1"), "{}", text[0]);
+
+        let json: Value = serde_json::from_str(&window.format_lines(StatsFormat::Jsonl)[0]).unwrap();
+        assert_eq!(json["topic"], "plant,1/temp");
+        assert_eq!(json["window_start"], 1_753_405_200.0);
+        assert_eq!(json["window_end"], 1_753_405_260.0);
+        assert_eq!(
+            DateTime::parse_from_rfc3339(json["window_start_iso"].as_str().unwrap()).unwrap().timestamp(),
+            1_753_405_200
+        );
+        assert_eq!(json["messages"], 2);
+        assert_eq!(json["payload"]["messages"], 2);
+        assert_eq!(json["stats"]["temp"]["mean"], 21.5);
+        assert_eq!(json["stats"]["status"]["top"], json!([{"value": "ok", "count": 1}, {"value": "a,b", "count": 1}]));
+
+        let csv = window.format_lines(StatsFormat::Csv);
+        let rows = PayloadSummary::default().rows().len();
+        assert_eq!(csv.len(), rows + 2);
+        let prefix = format!(
+            "1753405200,1753405260,{},{},\"plant,1/temp\",",
+            window.start.to_rfc3339_opts(SecondsFormat::Millis, false),
+            window.end.to_rfc3339_opts(SecondsFormat::Millis, false)
+        );
+        assert_eq!(csv[0], format!("{}$payload,messages,2", prefix));
+        assert_eq!(csv[rows], format!("{}temp,mean,21.5", prefix));
+        assert_eq!(csv[rows + 1], format!("{}status,top,\"ok=1|a,b=1\"", prefix));
+        assert_eq!(STATS_CSV_HEADER.split(',').count(), 8);
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_stats_file_starts_with_header() {
+        let (mut stats, dir) = topic_stats("csv", 60, StatsFormat::Csv);
+        let time = stats.window_end().timestamp() as f64 - 30.0;
+        stats.add_message(&message(time, r#"{"x":1}"#));
+        let now = stats.window_end();
+        assert_eq!(stats.calculate_and_write_stats(now).unwrap().len(), 1);
+
+        let content = fs::read_to_string(dir.join("stats")).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some(STATS_CSV_HEADER));
+        assert!(lines.all(|line| line.split(',').count() >= 8));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn windows_align_to_wall_clock_boundaries() {
+        let time = local(1_753_405_237) + Duration::milliseconds(500);
+        for interval in [1u64, 10, 60, 300, 3600] {
+            let start = aligned_window_start(time, interval);
+            let local_secs = start.timestamp() + start.offset().local_minus_utc() as i64;
+            assert_eq!(local_secs.rem_euclid(interval as i64), 0, "interval {}", interval);
+            assert!(start <= time && time < start + Duration::seconds(interval as i64), "interval {}", interval);
+            assert_eq!(aligned_window_start(start, interval), start);
+        }
+    }
+
+    #[test]
+    fn due_windows_close_at_boundaries() {
+        let (mut stats, dir) = topic_stats("windows", 60, StatsFormat::Text);
+        let start = aligned_window_start(local(1_753_405_237), 60);
+        stats.set_window_start(start + Duration::seconds(17));
+        assert_eq!(stats.window_end(), start + Duration::seconds(60));
+
+        stats.add_message(&message(start.timestamp() as f64 + 20.0, r#"{"x":1}"#));
+        assert!(stats.take_due_windows(start + Duration::seconds(59)).is_empty());
+
+        // 空の期間を出力しない場合、メッセージのない期間は飛ばす
+        let windows = stats.take_due_windows(start + Duration::seconds(185));
+        assert_eq!(windows.len(), 1);
+        assert_eq!((windows[0].start, windows[0].end), (start, start + Duration::seconds(60)));
+        assert_eq!(windows[0].payload.messages, 1);
+        assert_eq!(stats.window_end(), start + Duration::seconds(240));
+
+        // 空の期間も出力する場合は区切りごとに1つずつ
+        stats.set_emit_empty(true);
+        let windows = stats.take_due_windows(start + Duration::seconds(365));
+        let ends: Vec<_> = windows.iter().map(|window| window.end).collect();
+        assert_eq!(ends, vec![start + Duration::seconds(240), start + Duration::seconds(300), start + Duration::seconds(360)]);
+        assert!(windows.iter().all(|window| window.payload.messages == 0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn summarizes_non_json_payloads() {
+        let (mut stats, dir) = topic_stats("binary", 60, StatsFormat::Text);
+        let start = aligned_window_start(local(1_753_405_237), 60);
+        stats.set_window_start(start);
+        let t0 = start.timestamp() as f64;
+        let mut binary = message(t0 + 10.0, "");
+        binary.msg_b64 = base64::encode([0u8, 1, 2]);
+        assert!(stats.add_message(&binary).is_none());
+        binary.time = t0 + 11.0;
+        assert!(stats.add_message(&binary).is_none());
+        assert!(stats.add_message(&message(t0 + 13.0, "hello")).is_none());
+
+        let windows = stats.take_due_windows(start + Duration::seconds(60));
+        assert_eq!(windows.len(), 1);
+        let window = &windows[0];
+        // JSONでないペイロードはキーパスごとの統計を持たない
+        assert!(window.values.is_empty());
+        let payload = &window.payload;
+        assert_eq!(payload.messages, 3);
+        assert!((payload.rate - 0.05).abs() < 1e-9);
+        assert_eq!((payload.size_min, payload.size_max), (3.0, 5.0));
+        assert!((payload.size_mean - 11.0 / 3.0).abs() < 1e-9);
+        assert!((payload.interval_mean - 1.5).abs() < 1e-9);
+        assert!((payload.jitter - 0.5).abs() < 1e-9);
+        assert!((payload.duplicate_rate - 1.0 / 3.0).abs() < 1e-9);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
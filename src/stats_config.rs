@@ -0,0 +1,317 @@
+use std::{convert::TryFrom, fmt, fs, io, path::Path, str::FromStr};
+use serde::{Deserialize, Serialize};
+use crate::alert::AlertRule;
+use crate::message::topic_matches_filter;
+
+/// キーパスごとに計算できる統計量
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatMetric {
+    /// 値の数（nullを除く）
+    Count,
+    /// nullの数
+    Nulls,
+    /// キーが存在しなかったメッセージの数
+    Missing,
+    Min,
+    Max,
+    Mean,
+    Stddev,
+    Variance,
+    P50,
+    P95,
+    P99,
+    /// ユニーク数（文字列・真偽値）
+    Unique,
+    /// 出現回数の多い値（文字列・真偽値）
+    Top,
+}
+
+impl StatMetric {
+    pub const ALL: [StatMetric; 13] = [
+        StatMetric::Count,
+        StatMetric::Nulls,
+        StatMetric::Missing,
+        StatMetric::Min,
+        StatMetric::Max,
+        StatMetric::Mean,
+        StatMetric::Stddev,
+        StatMetric::Variance,
+        StatMetric::P50,
+        StatMetric::P95,
+        StatMetric::P99,
+        StatMetric::Unique,
+        StatMetric::Top,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatMetric::Count => "count",
+            StatMetric::Nulls => "nulls",
+            StatMetric::Missing => "missing",
+            StatMetric::Min => "min",
+            StatMetric::Max => "max",
+            StatMetric::Mean => "mean",
+            StatMetric::Stddev => "stddev",
+            StatMetric::Variance => "variance",
+            StatMetric::P50 => "p50",
+            StatMetric::P95 => "p95",
+            StatMetric::P99 => "p99",
+            StatMetric::Unique => "unique",
+            StatMetric::Top => "top",
+        }
+    }
+}
+
+impl fmt::Display for StatMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for StatMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StatMetric::ALL
+            .iter()
+            .find(|metric| metric.name() == s.trim())
+            .copied()
+            .ok_or_else(|| format!("unknown statistic '{}'", s))
+    }
+}
+
+fn default_metrics() -> Vec<StatMetric> {
+    vec![StatMetric::Variance, StatMetric::Unique]
+}
+
+fn default_top_k() -> usize {
+    5
+}
+
+/// JSONの配列のキーパスの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ArrayMode {
+    /// 要素ごとに別のキーパス（`values[0]`, `values[1]`, ...）
+    #[default]
+    Index,
+    /// 全要素を1つのキーパスに集計（`values[*]`）
+    Aggregate,
+    /// 先頭N個の要素のみ要素ごとに集計
+    First(usize),
+}
+
+impl fmt::Display for ArrayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArrayMode::Index => f.write_str("index"),
+            ArrayMode::Aggregate => f.write_str("aggregate"),
+            ArrayMode::First(n) => write!(f, "first:{}", n),
+        }
+    }
+}
+
+impl FromStr for ArrayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "index" => Ok(ArrayMode::Index),
+            "aggregate" | "*" => Ok(ArrayMode::Aggregate),
+            other => other
+                .strip_prefix("first:")
+                .and_then(|n| n.parse().ok())
+                .map(ArrayMode::First)
+                .ok_or_else(|| format!("unknown array mode '{}', expected index, aggregate or first:<n>", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ArrayMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ArrayMode> for String {
+    fn from(mode: ArrayMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// 集計するキーパスの選択（配列の扱いと include / exclude のグロブ）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeySelection {
+    pub arrays: ArrayMode,
+    /// 空の場合は全てのキーパス
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl KeySelection {
+    /// キーパスが除外されているか（除外されたオブジェクトや配列の子孫も除外）
+    pub fn is_excluded(&self, key_path: &str) -> bool {
+        self.exclude.iter().any(|pattern| key_glob_matches(pattern, key_path))
+    }
+
+    /// キーパスが include に一致するか（一致したオブジェクトや配列の子孫も対象）
+    pub fn is_included(&self, key_path: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|pattern| key_glob_matches(pattern, key_path))
+    }
+}
+
+/// ヘルパー関数：キーパスのグロブ（`*` は `.` 以外の任意の文字列、`**` は任意の文字列、`?` は任意の1文字）
+pub fn key_glob_matches(pattern: &str, key_path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = key_path.chars().collect();
+    glob_matches_at(&pattern, &path)
+}
+
+fn glob_matches_at(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            (0..=path.len()).any(|skip| glob_matches_at(&pattern[2..], &path[skip..]))
+        }
+        Some('*') => {
+            let segment = path.iter().position(|c| *c == '.').unwrap_or(path.len());
+            (0..=segment).any(|skip| glob_matches_at(&pattern[1..], &path[skip..]))
+        }
+        Some('?') => !path.is_empty() && glob_matches_at(&pattern[1..], &path[1..]),
+        Some(c) => path.first() == Some(c) && glob_matches_at(&pattern[1..], &path[1..]),
+    }
+}
+
+/// トピックフィルタごとの統計設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopicStatsConfig {
+    /// MQTTのトピックフィルタ（`+` と `#` を使用可能）
+    pub filter: String,
+    pub metrics: Vec<StatMetric>,
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// 配列の扱い（省略時は全体の設定）
+    #[serde(default)]
+    pub arrays: Option<ArrayMode>,
+    /// 集計するキーパスのグロブ（省略時は全体の設定）
+    #[serde(default)]
+    pub include: Option<Vec<String>>,
+    /// 集計しないキーパスのグロブ（省略時は全体の設定）
+    #[serde(default)]
+    pub exclude: Option<Vec<String>>,
+}
+
+/// 統計分析の設定（`--stats-config` のJSONファイル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    /// トピックごとの設定に一致しない場合の統計量
+    #[serde(default = "default_metrics")]
+    pub metrics: Vec<StatMetric>,
+    /// `top` で出力する値の数
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// 配列の扱い
+    #[serde(default)]
+    pub arrays: ArrayMode,
+    /// 集計するキーパスのグロブ（空の場合は全て）
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// 集計しないキーパスのグロブ
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// トピックごとの設定（最初に一致したものを使用）
+    #[serde(default)]
+    pub topics: Vec<TopicStatsConfig>,
+    /// アラートのルール
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            metrics: default_metrics(),
+            top_k: default_top_k(),
+            arrays: ArrayMode::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            topics: Vec::new(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+impl StatsConfig {
+    /// JSONファイルから読み込み
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config: StatsConfig =
+            serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for rule in &config.alerts {
+            rule.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        Ok(config)
+    }
+
+    /// トピックに適用する統計量と上位件数を取得
+    pub fn metrics_for(&self, topic: &str) -> (Vec<StatMetric>, usize) {
+        match self.topics.iter().find(|t| topic_matches_filter(&t.filter, topic)) {
+            Some(t) => (t.metrics.clone(), t.top_k.unwrap_or(self.top_k)),
+            None => (self.metrics.clone(), self.top_k),
+        }
+    }
+
+    /// トピックに適用するキーパスの選択を取得
+    pub fn keys_for(&self, topic: &str) -> KeySelection {
+        let topic_config = self.topics.iter().find(|t| topic_matches_filter(&t.filter, topic));
+        KeySelection {
+            arrays: topic_config.and_then(|t| t.arrays).unwrap_or(self.arrays),
+            include: topic_config
+                .and_then(|t| t.include.clone())
+                .unwrap_or_else(|| self.include.clone()),
+            exclude: topic_config
+                .and_then(|t| t.exclude.clone())
+                .unwrap_or_else(|| self.exclude.clone()),
+        }
+    }
+}
+
+/// 統計ファイルの出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsFormat {
+    /// 1期間1行のテキスト（`key{metric}:value` をカンマ区切り）
+    #[default]
+    Text,
+    /// 1期間1行のJSON
+    Jsonl,
+    /// 1統計量1行の固定スキーマのCSV
+    Csv,
+}
+
+impl StatsFormat {
+    /// 統計ファイルの拡張子
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StatsFormat::Text => "txt",
+            StatsFormat::Jsonl => "jsonl",
+            StatsFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "txt" => Ok(StatsFormat::Text),
+            "jsonl" | "json" => Ok(StatsFormat::Jsonl),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!("unknown stats format '{}', expected text, jsonl or csv", s)),
+        }
+    }
+}